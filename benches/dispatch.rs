@@ -0,0 +1,44 @@
+//! Decoding of hot stream messages through the untagged enums against `parse`, which routes
+//! them by their discriminator, and of the depth and book ticker streams into borrowed events.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use crypto_websockets::{
+    borrowed::BorrowedEvent, BinanceSpotWebsocketEvent, BinanceUSwapWebsocketEvent, FtxWebsocketEvent,
+    HuobiUSwapWebsocketEvent, OkexWebsocketEvent,
+};
+use serde::de::DeserializeOwned;
+
+const BINANCE_USWAP_DEPTH: &str = r#"{"stream":"btcusdt@depth@100ms","data":{"e":"depthUpdate","E":1672531200123,"T":1672531200120,"s":"BTCUSDT","U":2451832180,"u":2451832210,"pu":2451832179,"b":[["16530.10","3.125"],["16530.00","0.010"],["16529.90","1.502"],["16529.50","0.000"],["16528.70","12.304"]],"a":[["16530.20","0.881"],["16530.30","0.000"],["16530.60","4.200"],["16531.00","9.017"],["16532.40","0.450"]]}}"#;
+const BINANCE_USWAP_BOOK_TICKER: &str = r#"{"stream":"btcusdt@bookTicker","data":{"e":"bookTicker","u":2451832211,"s":"BTCUSDT","b":"16530.10","B":"3.125","a":"16530.20","A":"0.881","T":1672531200120,"E":1672531200123}}"#;
+const BINANCE_USWAP_AGG_TRADE: &str = r#"{"stream":"btcusdt@aggTrade","data":{"e":"aggTrade","E":1672531200123,"a":1429371010,"s":"BTCUSDT","p":"16530.10","q":"0.250","f":3008321801,"l":3008321803,"T":1672531200120,"m":true}}"#;
+const BINANCE_SPOT_DEPTH: &str = r#"{"stream":"btcusdt@depth@100ms","data":{"e":"depthUpdate","E":1672531200123,"s":"BTCUSDT","U":27836110110,"u":27836110140,"b":[["16530.10000000","3.12500000"],["16530.00000000","0.01000000"],["16529.90000000","1.50200000"]],"a":[["16530.20000000","0.88100000"],["16530.30000000","0.00000000"],["16530.60000000","4.20000000"]]}}"#;
+const HUOBI_INCREMENTAL_DEPTH: &str = r#"{"ch":"market.BTC-USDT.depth.size_20.high_freq","tick":{"asks":[[16530.2,881],[16530.3,0],[16530.6,4200]],"bids":[[16530.1,3125],[16530.0,10],[16529.9,1502]],"ch":"market.BTC-USDT.depth.size_20.high_freq","event":"update","id":115338190720,"mrid":115338190720,"ts":1672531200120,"version":1806745017},"ts":1672531200123}"#;
+const OKEX_BOOKS: &str = r#"{"arg":{"channel":"books50-l2-tbt","instId":"BTC-USDT"},"action":"update","data":[{"asks":[["16530.2","0.881","0","3"],["16530.3","0","0","0"]],"bids":[["16530.1","3.125","0","12"],["16530","0.01","0","1"]],"ts":"1672531200120","checksum":-855196043}]}"#;
+const FTX_ORDERBOOK: &str = r#"{"channel":"orderbook","market":"BTC-PERP","type":"update","data":{"time":1672531200.2234566,"checksum":2871451239,"bids":[[16530.0,0.01],[16529.5,0.0]],"asks":[[16530.3,0.0],[16530.6,4.2]],"action":"update"}}"#;
+
+fn compare<T: DeserializeOwned>(c: &mut Criterion, name: &str, raw: &str, parse: fn(&str) -> crypto_websockets::Result<T>) {
+    let mut group = c.benchmark_group(name);
+    group.bench_function("untagged", |b| b.iter(|| serde_json::from_str::<T>(black_box(raw)).unwrap()));
+    group.bench_function("tagged", |b| b.iter(|| parse(black_box(raw)).unwrap()));
+    group.finish();
+}
+
+fn dispatch(c: &mut Criterion) {
+    compare(c, "binance_uswap_depth", BINANCE_USWAP_DEPTH, BinanceUSwapWebsocketEvent::parse);
+    compare(c, "binance_uswap_book_ticker", BINANCE_USWAP_BOOK_TICKER, BinanceUSwapWebsocketEvent::parse);
+    compare(c, "binance_uswap_agg_trade", BINANCE_USWAP_AGG_TRADE, BinanceUSwapWebsocketEvent::parse);
+    compare(c, "binance_spot_depth", BINANCE_SPOT_DEPTH, BinanceSpotWebsocketEvent::parse);
+    compare(c, "huobi_incremental_depth", HUOBI_INCREMENTAL_DEPTH, HuobiUSwapWebsocketEvent::parse);
+    compare(c, "okex_books", OKEX_BOOKS, OkexWebsocketEvent::parse);
+    compare(c, "ftx_orderbook", FTX_ORDERBOOK, FtxWebsocketEvent::parse);
+}
+
+fn borrowed(c: &mut Criterion) {
+    for (name, raw) in [("binance_uswap_depth", BINANCE_USWAP_DEPTH), ("binance_uswap_book_ticker", BINANCE_USWAP_BOOK_TICKER)] {
+        let mut group = c.benchmark_group(name);
+        group.bench_function("borrowed", |b| b.iter(|| BorrowedEvent::parse(black_box(raw)).unwrap()));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, dispatch, borrowed);
+criterion_main!(benches);
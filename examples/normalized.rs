@@ -0,0 +1,24 @@
+use crypto_websockets::{normalized::NormalizedEvent, Subscription, Websocket, Result};
+use std::collections::HashMap;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut ws = Websocket::new_normalized(HashMap::new(), |event: NormalizedEvent| {
+        match event {
+            NormalizedEvent::Trade(trade) => println!("{:?} {} trade {} @ {}", trade.exchange, trade.symbol, trade.quantity, trade.price),
+            NormalizedEvent::BookTicker(ticker) => println!("{:?} {} {} / {}", ticker.exchange, ticker.symbol, ticker.bid_price, ticker.ask_price),
+            event => println!("{:?}", event),
+        }
+        Ok(())
+    });
+
+    let mut subs: HashMap<Subscription, Vec<&str> > = HashMap::new();
+    subs.insert(Subscription::BinanceUSwapMStream, vec!["btcusdt@aggTrade", "btcusdt@bookTicker"]);
+    subs.insert(Subscription::HuobiUSwapMarketStream, vec!["market.BTC-USDT.trade.detail", "market.BTC-USDT.bbo"]);
+
+    if let Err(e) = ws.connect(subs).await {
+        println!("### websocket error: {:?}", e);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use crypto_websockets::{Subscription, WebsocketEvent, Websocket, Result};
+use futures::StreamExt;
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut ws = Websocket::new_streaming(HashMap::new());
+
+    let mut subs: HashMap<Subscription, Vec<&str> > = HashMap::new();
+    subs.insert(Subscription::BinanceUSwapMStream, vec!["btcusdt@bookTicker"]);
+
+    ws.open(subs).await?;
+
+    let mut events = ws.into_stream();
+    let mut report = tokio::time::interval(Duration::from_secs(10));
+    let mut count = 0_u64;
+
+    loop {
+        tokio::select! {
+            Some(event) = events.next() => match event {
+                Ok(WebsocketEvent::BinanceUSwapBookTickerEvent(event)) => {
+                    count += 1;
+                    println!("BookTicker: {:?}", event);
+                }
+                Ok(WebsocketEvent::Connection(event)) => println!("Connection: {:?}", event),
+                Ok(_) => (),
+                Err(e) => println!("### websocket error: {:?}", e),
+            },
+            _ = report.tick() => println!("{} book tickers so far", count),
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    Ok(())
+}
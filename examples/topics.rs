@@ -0,0 +1,41 @@
+use crypto_websockets::{Interval, Subscription, Topic, WebsocketEvent, Websocket, Result};
+use std::{collections::HashMap, time::Duration};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut ws = Websocket::new(HashMap::new(), |event: WebsocketEvent| {
+        println!("{:?}", event);
+        Ok(())
+    });
+
+    let mut subs: HashMap<Subscription, Vec<Topic>> = HashMap::new();
+    subs.insert(Subscription::BinanceUSwapMStream, vec![
+        Topic::Trades("BTCUSDT".to_string()),
+        Topic::Depth { symbol: "BTCUSDT".to_string(), levels: Some(5), speed: Some(Duration::from_millis(100)) },
+    ]);
+    subs.insert(Subscription::HuobiUSwapMarketStream, vec![
+        Topic::BookTicker("BTC-USDT".to_string()),
+        Topic::Kline("BTC-USDT".to_string(), Interval::Minute1),
+    ]);
+    subs.insert(Subscription::OkexMarketStream, vec![
+        Topic::Trades("BTC-USDT".to_string()),
+    ]);
+
+    // add and remove topics from another task once the connections are up.
+    let control = ws.control();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let eth = [Topic::Trades("ETHUSDT".to_string())];
+        control.subscribe(Subscription::BinanceUSwapMStream, &eth)?;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        control.unsubscribe(Subscription::BinanceUSwapMStream, &eth)?;
+        println!("topics: {:?}", control.topics(&Subscription::BinanceUSwapMStream));
+        Result::<()>::Ok(())
+    });
+
+    if let Err(e) = ws.connect_topics(subs).await {
+        println!("### websocket error: {:?}", e);
+    }
+
+    Ok(())
+}
@@ -1,12 +1,11 @@
-use crypto_websockets::{Subscription, WebsocketEvent, Websocket};
-use failure::Fallible;
+use crypto_websockets::{Subscription, WebsocketEvent, Websocket, Result};
 use std::{
     collections::HashMap,
 };
 extern crate simple_logger;
 
 #[tokio::main]
-async fn main() -> Fallible<()> {
+async fn main() -> Result<()> {
     // simple_logger::init().unwrap();
 
     // let access_key = "";
@@ -61,22 +60,36 @@ async fn main() -> Fallible<()> {
 
             WebsocketEvent::FtxRsp(event) => println!{"Ftx Rsp: {:?}", event},
 
+            WebsocketEvent::Connection(event) => println!{"Connection: {:?}", event},
+            WebsocketEvent::Unparsed { subscription, error, .. } => println!{"Unparsed {:?}: {}", subscription, error},
+
+            WebsocketEvent::BinanceLocalOrderBook(book) => println!{"Binance local book {}: {:?} / {:?}", book.symbol, book.best_bid(), book.best_ask()},
+            WebsocketEvent::BinanceOrderBookResync(event) => println!{"Binance book resync: {:?}", event},
+            WebsocketEvent::OkexLocalOrderBook(book) => println!{"Okex local book {}: {:?} / {:?}", book.inst_id, book.best_bid(), book.best_ask()},
+            WebsocketEvent::OkexOrderBookResync(event) => println!{"Okex book resync: {:?}", event},
+            WebsocketEvent::HuobiUSwapLocalOrderBook(book) => println!{"Huobi local book {}: {:?} / {:?}", book.contract_code, book.best_bid(), book.best_ask()},
+            WebsocketEvent::HuobiUSwapOrderBookResync(event) => println!{"Huobi book resync: {:?}", event},
+            WebsocketEvent::FtxLocalOrderBook(book) => println!{"Ftx local book {}: {:?}", book.symbol, book.best_bid_and_ask()},
+            WebsocketEvent::FtxOrderBookResync(event) => println!{"Ftx book resync: {:?}", event},
+
             _ => (),
         };
 
         Ok(())
     });
 
-    let mut subs: HashMap<Subscription, Vec<&str> > = HashMap::new();
+    // connect to a testnet, demo host or local server instead of production
+    // ws.set_endpoint(Subscription::BinanceUSwapMStream, Endpoint::binance_testnet(&Subscription::BinanceUSwapMStream).unwrap());
+    // ws.set_endpoint(Subscription::OkexOrderStream, Endpoint::okex_demo(&Subscription::OkexOrderStream).unwrap());
+    // write every frame sent and received to ./frames/frames-<day>.ndjson.gz
+    // ws.set_recorder(Recorder::start(RecorderConfig::new("frames"))?);
+    // or feed a recording through the handler instead of connecting, ten times faster:
+    // ws.replay(Replay::open("frames/frames-2023-01-01.ndjson.gz")?.with_speed(ReplaySpeed::Accelerated(10.0))).await?;
 
-    let binance_market_topics = vec![
-        "btcusdt@depth5@100ms",
-        "ethusdt@depth5@100ms",
-        // "ethusdt@depth@100ms",
-        // "btcusdt@depth@100ms",
-    ];
+    // exchange specific topic names, see examples/topics.rs for the typed `Topic`s.
+    let mut subs: HashMap<Subscription, Vec<&str> > = HashMap::new();
 
-    // subs.insert(Subscription::BinanceSpotMStream, binance_market_topics);
+    // subs.insert(Subscription::BinanceSpotMStream, vec!["btcusdt@depth5@100ms", "ethusdt@depth5@100ms"]);
 
     /*
     // generate listenkey
@@ -88,6 +101,14 @@ async fn main() -> Fallible<()> {
 
    subs.insert(Subscription::BinanceSpotOrder, binance_account_topics);
    */
+    // or let the client create, keep alive and rotate the listen key:
+    // let rest = BinanceRest::production(Arc::new(HmacSigner::new(&binance_access_key, &binance_secret_key)));
+    // ws.manage_listen_key(Subscription::BinanceSpotOrder, rest)?;
+    // subs.insert(Subscription::BinanceSpotOrder, vec![]);
+    // orders go through the WebSocket API, on a connection of its own:
+    // subs.insert(Subscription::BinanceUSwapApi, vec![]);
+    // let api = ws.binance_api(Subscription::BinanceUSwapApi, Arc::new(HmacSigner::new(&binance_access_key, &binance_secret_key)))?;
+    // let order = api.place_order(&NewOrder::limit("BTCUSDT", OrderSide::Buy, dec!(0.01), dec!(20000), TimeInForce::GTC));
 
     let binance_uswap_market_topics = vec![
         // "dotusdt@depth5@100ms",
@@ -99,39 +120,47 @@ async fn main() -> Fallible<()> {
     ];
 
     subs.insert(Subscription::BinanceUSwapMStream, binance_uswap_market_topics);
-
-    let huobi_uswap_market_topics = vec![
-        "market.BTC-USDT.kline.1min",
-        "market.BTC-USDT.depth.step0",
-        "market.BTC-USDT.depth.size_20.high_freq",
-        "market.BTC-USDT.bbo",
-        "market.BTC-USDT.trade.detail",
-    ];
-    // subs.insert(Subscription::HuobiUSwapMarketStream, huobi_uswap_market_topics);
-
-    let huobi_uswap_order_topics = vec![
-        "orders_cross.btc-usdt",
-        "matchOrders_cross.btc-usdt",
-        "accounts_cross.usdt",
-        "positions_cross.btc-usdt",
-    ];
-    // subs.insert(Subscription::HuobiUSwapOrderStream, huobi_uswap_order_topics);
-
-    let okex_market_topics = vec![
-        "BTC-USDT"
-    ];
-    // subs.insert(Subscription::OkexMarketStream, okex_market_topics);
-
-    let okex_order_topics = vec![
-        "SWAP"
-    ];
-    // subs.insert(Subscription::OkexOrderStream, okex_order_topics);
-
-
-    let ftx_market_topics = vec![
-        "BTC-PERP"
-    ];
-    // subs.insert(Subscription::FtxMarketStream, ftx_market_topics);
+    // keep local books from the "@depth" streams above
+    // ws.enable_local_order_book(Subscription::BinanceUSwapMStream)?;
+    // read the depth and book ticker streams without copying them out of the frames
+    // ws.enable_borrowed(Subscription::BinanceUSwapMStream, |event| {
+    //     if let BorrowedEvent::BookTicker(rx) = event {
+    //         println!("{} {} / {}", rx.data.symbol, rx.data.best_bid, rx.data.best_ask);
+    //     }
+    //     Ok(())
+    // })?;
+
+    // subs.insert(Subscription::HuobiUSwapMarketStream, vec![
+    //     "market.BTC-USDT.kline.1min",
+    //     "market.BTC-USDT.depth.step0",
+    //     "market.BTC-USDT.depth.size_20.high_freq",
+    //     "market.BTC-USDT.bbo",
+    //     "market.BTC-USDT.trade.detail",
+    // ]);
+    // ws.enable_local_order_book(Subscription::HuobiUSwapMarketStream)?;
+
+    // subs.insert(Subscription::HuobiUSwapOrderStream, vec![
+    //     "orders_cross.btc-usdt",
+    //     "matchOrders_cross.btc-usdt",
+    //     "accounts_cross.usdt",
+    //     "positions_cross.btc-usdt",
+    // ]);
+
+    // subs.insert(Subscription::OkexMarketStream, vec!["BTC-USDT"]);
+    // ws.enable_local_order_book(Subscription::OkexMarketStream)?;
+
+    // subs.insert(Subscription::OkexOrderStream, vec!["SWAP"]);
+    // orders are placed on the same logged in connection:
+    // let trading = ws.okex_trading(Subscription::OkexOrderStream)?;
+    // let ack = trading.place_order(&PlaceOrder::limit("BTC-USDT-SWAP", TdMode::Cross, Side::Buy, dec!(1), dec!(20000)));
+    // another account streams the same subscription with its own key, its events carry the
+    // label with `new_enveloped` or `envelopes`:
+    // let sub_account = ws.add_account("sub-1", Subscription::OkexOrderStream, (key, secret, passphrase));
+    // ws.open(HashMap::from([(sub_account, vec!["SWAP"])])).await?;
+
+
+    // subs.insert(Subscription::FtxMarketStream, vec!["BTC-PERP"]);
+    // ws.enable_local_order_book(Subscription::FtxMarketStream)?;
 
     // let ftx_order_topics = vec![
     // ];
@@ -0,0 +1,319 @@
+//! Order entry over the signed Binance WebSocket API, for spot and USDⓈ-M futures.
+//!
+//! Open a `BinanceSpotApi` or `BinanceUSwapApi` connection like any other, without topics,
+//! and get a `BinanceApi` handle for it with `Websocket::binance_api`. Every request is
+//! signed with the `RestSigner` of the handle and returns a future of the typed answer, see
+//! `rpc` for how answers are delivered. Binance refusing a request fails it with
+//! `Error::RequestRejected`, carrying the Binance error code.
+//!
+//! Not every method exists on both markets, Binance refuses the ones it does not know.
+use crate::{
+    binance_model::{OrderSide, OrderStatus, OrderType, TimeInForce},
+    error::{Error, Result},
+    listen_key::RestSigner,
+    models::{ConnectionId, Subscription},
+    rpc::{ResponseFuture, RpcSender},
+    websocket::{decode, Websocket},
+};
+use rust_decimal::Decimal;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::BTreeMap, sync::Arc};
+
+/// An order to place with `BinanceApi::place_order`.
+#[derive(Debug, Clone)]
+pub struct NewOrder {
+    pub symbol: String,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub time_in_force: Option<TimeInForce>,
+    pub quantity: Option<Decimal>,
+    /// Spot market orders only, the amount of quote asset to spend or receive.
+    pub quote_order_qty: Option<Decimal>,
+    pub price: Option<Decimal>,
+    pub stop_price: Option<Decimal>,
+    pub client_order_id: Option<String>,
+    /// USDⓈ-M only.
+    pub reduce_only: Option<bool>,
+    /// USDⓈ-M only, `BOTH`, `LONG` or `SHORT`.
+    pub position_side: Option<String>,
+}
+
+impl NewOrder {
+    #[must_use]
+    pub fn limit(symbol: &str, side: OrderSide, quantity: Decimal, price: Decimal, time_in_force: TimeInForce) -> Self {
+        Self {
+            time_in_force: Some(time_in_force),
+            quantity: Some(quantity),
+            price: Some(price),
+            ..Self::new(symbol, side, OrderType::Limit)
+        }
+    }
+
+    #[must_use]
+    pub fn market(symbol: &str, side: OrderSide, quantity: Decimal) -> Self {
+        Self { quantity: Some(quantity), ..Self::new(symbol, side, OrderType::Market) }
+    }
+
+    /// An order without any quantity or price, set the fields the type needs.
+    #[must_use]
+    pub fn new(symbol: &str, side: OrderSide, order_type: OrderType) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            side,
+            order_type,
+            time_in_force: None,
+            quantity: None,
+            quote_order_qty: None,
+            price: None,
+            stop_price: None,
+            client_order_id: None,
+            reduce_only: None,
+            position_side: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_client_order_id(mut self, client_order_id: &str) -> Self {
+        self.client_order_id = Some(client_order_id.to_string());
+        self
+    }
+
+    fn params(&self) -> Params {
+        let mut params = Params::new();
+        params.insert("symbol", self.symbol.clone());
+        params.insert("side", name(&self.side));
+        params.insert("type", name(&self.order_type));
+        params.insert("newOrderRespType", "RESULT".to_string());
+        let optional = [
+            ("timeInForce", self.time_in_force.as_ref().map(name)),
+            ("quantity", self.quantity.map(|quantity| quantity.to_string())),
+            ("quoteOrderQty", self.quote_order_qty.map(|qty| qty.to_string())),
+            ("price", self.price.map(|price| price.to_string())),
+            ("stopPrice", self.stop_price.map(|price| price.to_string())),
+            ("newClientOrderId", self.client_order_id.clone()),
+            ("reduceOnly", self.reduce_only.map(|reduce_only| reduce_only.to_string())),
+            ("positionSide", self.position_side.clone()),
+        ];
+        params.extend(optional.into_iter().filter_map(|(key, value)| Some((key, value?))));
+        params
+    }
+}
+
+/// Identifies an order to cancel or query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderRef {
+    OrderId(u64),
+    ClientOrderId(String),
+}
+
+impl OrderRef {
+    fn param(&self) -> (&'static str, String) {
+        match self {
+            Self::OrderId(id) => ("orderId", id.to_string()),
+            Self::ClientOrderId(id) => ("origClientOrderId", id.clone()),
+        }
+    }
+}
+
+/// An order as the API reports it, for both markets.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiOrder {
+    pub symbol: String,
+    pub order_id: u64,
+    pub client_order_id: String,
+    /// The client id of the canceled order, in spot cancel answers.
+    pub orig_client_order_id: Option<String>,
+    pub price: Decimal,
+    pub orig_qty: Decimal,
+    pub executed_qty: Decimal,
+    /// `cumQuote` on USDⓈ-M.
+    #[serde(alias = "cumQuote")]
+    pub cummulative_quote_qty: Option<Decimal>,
+    /// USDⓈ-M only.
+    pub avg_price: Option<Decimal>,
+    pub status: OrderStatus,
+    pub time_in_force: Option<TimeInForce>,
+    #[serde(rename = "type")]
+    pub order_type: OrderType,
+    pub side: OrderSide,
+    pub stop_price: Option<Decimal>,
+    /// USDⓈ-M only.
+    pub reduce_only: Option<bool>,
+    /// USDⓈ-M only.
+    pub position_side: Option<String>,
+    /// `transactTime` of spot orders placed or canceled.
+    #[serde(alias = "transactTime")]
+    pub update_time: Option<u64>,
+}
+
+/// A spot balance.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiBalance {
+    pub asset: String,
+    pub free: Decimal,
+    pub locked: Decimal,
+}
+
+/// A USDⓈ-M margin asset.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiAsset {
+    pub asset: String,
+    pub wallet_balance: Decimal,
+    pub unrealized_profit: Decimal,
+    pub available_balance: Decimal,
+}
+
+/// A USDⓈ-M position.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiPosition {
+    pub symbol: String,
+    pub position_side: String,
+    pub position_amt: Decimal,
+    pub entry_price: Option<Decimal>,
+    pub unrealized_profit: Decimal,
+}
+
+/// The answer of `account.status`. Spot fills `balances`, USDⓈ-M `assets` and `positions`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountStatus {
+    /// Spot only.
+    pub can_trade: Option<bool>,
+    #[serde(default)]
+    pub balances: Vec<ApiBalance>,
+    #[serde(default)]
+    pub assets: Vec<ApiAsset>,
+    #[serde(default)]
+    pub positions: Vec<ApiPosition>,
+    pub update_time: Option<u64>,
+}
+
+/// `{"id":"1","status":200,"result":{...}}`, or `{"id":"1","status":400,"error":{"code":-2010,"msg":"..."}}`.
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    status: u16,
+    result: Option<T>,
+    error: Option<ApiError>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    code: i64,
+    msg: String,
+}
+
+type Params = BTreeMap<&'static str, String>;
+
+/// The name serde gives a `binance_model` enum value.
+fn name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value).ok()
+        .and_then(|value| value.as_str().map(ToString::to_string))
+        .unwrap_or_default()
+}
+
+/// Places, cancels and queries orders of one `BinanceSpotApi` or `BinanceUSwapApi`
+/// connection, see `Websocket::binance_api`. It can be cloned and used from other tasks.
+#[derive(Clone)]
+pub struct BinanceApi {
+    rpc: RpcSender,
+    signer: Arc<dyn RestSigner>,
+    recv_window: Option<u64>,
+}
+
+impl BinanceApi {
+    /// Requests older than `recv_window` milliseconds when Binance receives them are
+    /// refused, 5000 by default.
+    #[must_use]
+    pub const fn with_recv_window(mut self, recv_window: u64) -> Self {
+        self.recv_window = Some(recv_window);
+        self
+    }
+
+    /// `order.place`. The answer is the order after matching.
+    #[must_use]
+    pub fn place_order(&self, order: &NewOrder) -> ResponseFuture<ApiOrder> {
+        self.call("order.place", order.params())
+    }
+
+    /// `order.cancel`.
+    #[must_use]
+    pub fn cancel_order(&self, symbol: &str, order: &OrderRef) -> ResponseFuture<ApiOrder> {
+        self.call("order.cancel", Self::order_params(symbol, order))
+    }
+
+    /// `order.status`.
+    #[must_use]
+    pub fn order_status(&self, symbol: &str, order: &OrderRef) -> ResponseFuture<ApiOrder> {
+        self.call("order.status", Self::order_params(symbol, order))
+    }
+
+    /// `openOrders.status`, of every symbol when `symbol` is `None`.
+    #[must_use]
+    pub fn open_orders(&self, symbol: Option<&str>) -> ResponseFuture<Vec<ApiOrder>> {
+        let params = symbol.map(|symbol| ("symbol", symbol.to_string())).into_iter().collect();
+        self.call("openOrders.status", params)
+    }
+
+    /// `account.status`.
+    #[must_use]
+    pub fn account_status(&self) -> ResponseFuture<AccountStatus> {
+        self.call("account.status", Params::new())
+    }
+
+    fn order_params(symbol: &str, order: &OrderRef) -> Params {
+        Params::from([("symbol", symbol.to_string()), order.param()])
+    }
+
+    /// Signs `params` and sends them as a `method` request.
+    fn call<T: DeserializeOwned + Send + 'static>(&self, method: &str, mut params: Params) -> ResponseFuture<T> {
+        params.insert("apiKey", self.signer.api_key().to_string());
+        params.insert("timestamp", chrono::Utc::now().timestamp_millis().to_string());
+        if let Some(recv_window) = self.recv_window {
+            params.insert("recvWindow", recv_window.to_string());
+        }
+        let payload = params.iter().map(|(key, value)| format!("{key}={value}")).collect::<Vec<_>>().join("&");
+        params.insert("signature", self.signer.sign(&payload));
+
+        let id = self.rpc.next_id();
+        let message = json!({ "id": id, "method": method, "params": params }).to_string();
+        let answer = self.rpc.send(id, message);
+        let subscription = self.rpc.connection.to_string();
+
+        Box::pin(async move {
+            let raw = answer.await?;
+            let rsp: ApiResponse<T> = decode(&raw)?;
+            match (rsp.result, rsp.error) {
+                (Some(result), None) => Ok(result),
+                (_, Some(error)) => Err(Error::RequestRejected { subscription, code: error.code.to_string(), msg: error.msg }),
+                (None, None) => Err(Error::RequestRejected { subscription, code: rsp.status.to_string(), msg: raw }),
+            }
+        })
+    }
+}
+
+impl std::fmt::Debug for BinanceApi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BinanceApi")
+            .field("connection", &self.rpc.connection)
+            .field("api_key", &self.signer.api_key())
+            .field("recv_window", &self.recv_window)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Websocket {
+    /// Returns a handle sending signed requests on `connection`, a `BinanceSpotApi` or
+    /// `BinanceUSwapApi` connection. Open the connection before sending.
+    pub fn binance_api(&self, connection: impl Into<ConnectionId>, signer: Arc<dyn RestSigner>) -> Result<BinanceApi> {
+        let connection = connection.into();
+        match connection.subscription {
+            Subscription::BinanceSpotApi | Subscription::BinanceUSwapApi =>
+                Ok(BinanceApi { rpc: self.rpc_sender(connection), signer, recv_window: None }),
+            _ => Err(Error::NoRequests { subscription: connection.to_string() }),
+        }
+    }
+}
@@ -33,6 +33,14 @@ pub enum OrderType {
     TakeProfit,
     TakeProfitLimit,
     LimitMaker,
+    /// USDⓈ-M only.
+    Stop,
+    /// USDⓈ-M only.
+    StopMarket,
+    /// USDⓈ-M only.
+    TakeProfitMarket,
+    /// USDⓈ-M only.
+    TrailingStopMarket,
 }
 
 /// By default, use market orders
@@ -333,6 +341,15 @@ pub struct BookTickerEvent {
     pub best_ask_qty: String,
 }
 
+/// Sent on the user data stream when its listen key expired, the stream ends after it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListenKeyEvent {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+}
+
 
 
 /// Account position update
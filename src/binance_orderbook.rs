@@ -0,0 +1,759 @@
+//! Local order books built from the Binance diff. depth streams (`<symbol>@depth`,
+//! `<symbol>@depth@100ms`, ...) of spot and USDⓈ-M futures.
+//!
+//! The streams only carry changes, so a book starts from a REST snapshot and the updates
+//! buffered while it was fetched are replayed on top of it. Every update has to follow the
+//! previous one, otherwise the book is thrown away and synchronized again from a new snapshot.
+use crate::{
+    binance_model,
+    binance_uswap_model,
+    borrowed::{DepthDiff, Level},
+    error::{self, Result},
+    models::{ConnectionId, Subscription, WebsocketEvent},
+    recorder::{Direction, FrameKind},
+    websocket::Websocket,
+};
+use futures::prelude::*;
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::{
+    collections::{BTreeMap, HashMap},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use tracing::warn;
+
+/// Wait before asking again for a snapshot that could not be fetched.
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A depth snapshot being fetched, with the subscription and symbol it belongs to.
+pub(crate) type PendingSnapshot = Pin<Box<dyn Future<Output = (ConnectionId, String, Result<binance_model::OrderBook>)> + Send>>;
+
+pub type SnapshotFuture = Pin<Box<dyn Future<Output = Result<binance_model::OrderBook>> + Send>>;
+
+/// The payload of a `FrameKind::Snapshot` frame.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RecordedSnapshot {
+    pub symbol: String,
+    pub snapshot: binance_model::OrderBook,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepthMarket {
+    Spot,
+    USwap,
+}
+
+impl DepthMarket {
+    /// Returns the market of a Binance market data subscription.
+    #[must_use]
+    pub const fn of(subscription: &Subscription) -> Option<Self> {
+        match *subscription {
+            Subscription::BinanceSpotMStream => Some(Self::Spot),
+            Subscription::BinanceUSwapMStream => Some(Self::USwap),
+            _ => None,
+        }
+    }
+}
+
+/// Source of the depth snapshots a local book starts from.
+///
+/// The default is `RestSnapshotProvider`, implement this to serve snapshots from elsewhere,
+/// e.g. a local stand-in in tests.
+pub trait SnapshotProvider: Send + Sync {
+    fn snapshot(&self, market: DepthMarket, symbol: &str) -> SnapshotFuture;
+}
+
+/// Fetches snapshots from the public REST depth endpoints.
+#[derive(Debug, Clone)]
+pub struct RestSnapshotProvider {
+    client: reqwest::Client,
+    spot_url: String,
+    uswap_url: String,
+    limit: u32,
+}
+
+impl Default for RestSnapshotProvider {
+    fn default() -> Self {
+        Self::new("https://api.binance.com", "https://fapi.binance.com", 1000)
+    }
+}
+
+impl RestSnapshotProvider {
+    /// The REST hosts of the spot and USDⓈ-M testnets, to go with `Endpoint::binance_testnet`.
+    #[must_use]
+    pub fn binance_testnet() -> Self {
+        Self::new("https://testnet.binance.vision", "https://testnet.binancefuture.com", 1000)
+    }
+
+    /// `limit` is the number of levels per side, see the Binance docs for the accepted values.
+    #[must_use]
+    pub fn new(spot_url: &str, uswap_url: &str, limit: u32) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            spot_url: spot_url.to_string(),
+            uswap_url: uswap_url.to_string(),
+            limit,
+        }
+    }
+}
+
+impl SnapshotProvider for RestSnapshotProvider {
+    fn snapshot(&self, market: DepthMarket, symbol: &str) -> SnapshotFuture {
+        let url = match market {
+            DepthMarket::Spot => format!("{}/api/v3/depth", self.spot_url),
+            DepthMarket::USwap => format!("{}/fapi/v1/depth", self.uswap_url),
+        };
+        let request = self.client
+            .get(url)
+            .query(&[("symbol", symbol.to_uppercase()), ("limit", self.limit.to_string())]);
+
+        Box::pin(async move {
+            let response = request.send().await
+                .and_then(reqwest::Response::error_for_status)
+                .context(error::HttpSnafu)?;
+            let body = response.text().await.context(error::HttpSnafu)?;
+            crate::websocket::decode(&body)
+        })
+    }
+}
+
+/// One diff. depth event, common to spot and USDⓈ-M.
+#[derive(Debug, Clone)]
+pub struct DepthUpdate {
+    pub symbol: String,
+    pub event_time: u64,
+    pub first_update_id: u64,
+    pub final_update_id: u64,
+    /// Only sent on USDⓈ-M.
+    pub previous_final_update_id: Option<u64>,
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+impl From<&binance_model::DepthOrderBookEvent> for DepthUpdate {
+    fn from(event: &binance_model::DepthOrderBookEvent) -> Self {
+        Self {
+            symbol: event.symbol.clone(),
+            event_time: event.event_time,
+            first_update_id: event.first_update_id,
+            final_update_id: event.final_update_id,
+            previous_final_update_id: None,
+            bids: levels(event.bids.iter().map(|bid| (bid.price, bid.qty))),
+            asks: levels(event.asks.iter().map(|ask| (ask.price, ask.qty))),
+        }
+    }
+}
+
+impl From<&binance_uswap_model::DepthOrderBookEvent> for DepthUpdate {
+    fn from(event: &binance_uswap_model::DepthOrderBookEvent) -> Self {
+        Self {
+            symbol: event.symbol.clone(),
+            event_time: event.event_time,
+            first_update_id: event.first_update_id,
+            final_update_id: event.final_update_id,
+            previous_final_update_id: event.previous_final_update_id,
+            bids: levels(event.bids.iter().map(|bid| (bid.price, bid.qty))),
+            asks: levels(event.asks.iter().map(|ask| (ask.price, ask.qty))),
+        }
+    }
+}
+
+impl From<&DepthDiff<'_>> for DepthUpdate {
+    fn from(event: &DepthDiff<'_>) -> Self {
+        let levels = |levels: &[Level<'_>]| {
+            levels
+                .iter()
+                .filter_map(|level| Some((Decimal::from_str(level.price()).ok()?, Decimal::from_str(level.qty()).ok()?)))
+                .collect()
+        };
+        Self {
+            symbol: event.symbol.to_string(),
+            event_time: event.event_time,
+            first_update_id: event.first_update_id,
+            final_update_id: event.final_update_id,
+            previous_final_update_id: event.previous_final_update_id,
+            bids: levels(&event.bids),
+            asks: levels(&event.asks),
+        }
+    }
+}
+
+fn levels(levels: impl Iterator<Item = (f64, f64)>) -> Vec<(Decimal, Decimal)> {
+    levels
+        .filter_map(|(price, qty)| Some((Decimal::from_f64(price)?, Decimal::from_f64(qty)?)))
+        .collect()
+}
+
+/// A book maintained from a snapshot and the diff. depth events that followed it.
+#[derive(Debug, Clone)]
+pub struct LocalOrderBook {
+    pub market: DepthMarket,
+    pub symbol: String,
+    /// `u` of the last applied event, or `lastUpdateId` of the snapshot.
+    pub last_update_id: u64,
+    /// `E` of the last applied event, 0 until one was applied.
+    pub event_time: u64,
+    pub bids: BTreeMap<Decimal, Decimal>,
+    pub asks: BTreeMap<Decimal, Decimal>,
+}
+
+impl LocalOrderBook {
+    #[must_use]
+    pub fn from_snapshot(market: DepthMarket, symbol: &str, snapshot: &binance_model::OrderBook) -> Self {
+        Self {
+            market,
+            symbol: symbol.to_string(),
+            last_update_id: snapshot.last_update_id,
+            event_time: 0,
+            bids: levels(snapshot.bids.iter().map(|bid| (bid.price, bid.qty))).into_iter().collect(),
+            asks: levels(snapshot.asks.iter().map(|ask| (ask.price, ask.qty))).into_iter().collect(),
+        }
+    }
+
+    /// Applies the levels of `update`, a quantity of 0 removes the level.
+    /// Sequencing is checked by `DepthSynchronizer`, not here.
+    pub fn apply(&mut self, update: &DepthUpdate) {
+        for (price, qty) in &update.bids {
+            if *qty == dec!(0) {
+                self.bids.remove(price);
+            } else {
+                self.bids.insert(*price, *qty);
+            }
+        }
+        for (price, qty) in &update.asks {
+            if *qty == dec!(0) {
+                self.asks.remove(price);
+            } else {
+                self.asks.insert(*price, *qty);
+            }
+        }
+        self.last_update_id = update.final_update_id;
+        self.event_time = update.event_time;
+    }
+
+    /// Returns the price of the best bid
+    #[must_use]
+    pub fn bid_price(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().copied()
+    }
+
+    /// Returns the price of the best ask
+    #[must_use]
+    pub fn ask_price(&self) -> Option<Decimal> {
+        self.asks.keys().next().copied()
+    }
+
+    /// Returns the midpoint between the best bid price and best ask price.
+    /// Output is not rounded to the smallest price increment.
+    #[must_use]
+    pub fn mid_price(&self) -> Option<Decimal> {
+        Some((self.bid_price()? + self.ask_price()?) / dec!(2))
+    }
+
+    /// Returns the price and quantity of the best bid
+    /// `(bid_price, bid_quantity)`
+    #[must_use]
+    pub fn best_bid(&self) -> Option<(Decimal, Decimal)> {
+        let (price, quantity) = self.bids.iter().next_back()?;
+
+        Some((*price, *quantity))
+    }
+
+    /// Returns the price and quantity of the best ask
+    /// `(ask_price, ask_quantity)`
+    #[must_use]
+    pub fn best_ask(&self) -> Option<(Decimal, Decimal)> {
+        let (price, quantity) = self.asks.iter().next()?;
+
+        Some((*price, *quantity))
+    }
+}
+
+/// Outcome of feeding an update or a snapshot to a `DepthSynchronizer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthSync {
+    /// The book is waiting for a snapshot, or for the first update following it. A snapshot
+    /// has to be fetched if `request_snapshot` is set.
+    Buffered { request_snapshot: bool },
+    /// The update is older than the book and was dropped.
+    Stale,
+    /// The book changed.
+    Updated,
+    /// The update does not follow the book, which was dropped. A new snapshot has to be
+    /// fetched, the update is kept to be replayed on it.
+    Gap { expected: u64, received: u64 },
+}
+
+/// Why a local book was dropped and is being synchronized again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResyncReason {
+    /// An update did not follow the previous one.
+    Gap { expected: u64, received: u64 },
+    /// The snapshot could not be fetched, it is retried.
+    SnapshotFailed(String),
+    /// The connection of the depth stream was lost.
+    Disconnected,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResyncEvent {
+    pub market: DepthMarket,
+    pub symbol: String,
+    pub reason: ResyncReason,
+}
+
+#[derive(Debug)]
+enum BookState {
+    /// Updates received before the snapshot, in order.
+    Buffering(Vec<DepthUpdate>),
+    /// `synced` is unset until the first update after the snapshot was applied.
+    Live { book: LocalOrderBook, synced: bool },
+}
+
+/// Keeps the local books of one market in sync, following the procedure from the Binance
+/// docs ("How to manage a local order book correctly").
+#[derive(Debug)]
+pub struct DepthSynchronizer {
+    market: DepthMarket,
+    books: HashMap<String, BookState>,
+}
+
+impl DepthSynchronizer {
+    #[must_use]
+    pub fn new(market: DepthMarket) -> Self {
+        Self {
+            market,
+            books: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn market(&self) -> DepthMarket {
+        self.market
+    }
+
+    /// Returns the book of `symbol` once it is in sync.
+    #[must_use]
+    pub fn book(&self, symbol: &str) -> Option<&LocalOrderBook> {
+        match self.books.get(symbol) {
+            Some(BookState::Live { book, synced: true }) => Some(book),
+            _ => None,
+        }
+    }
+
+    /// Drops every book, returning their symbols. Used when the stream was interrupted.
+    pub fn clear(&mut self) -> Vec<String> {
+        self.books.drain().map(|(symbol, _)| symbol).collect()
+    }
+
+    pub fn update(&mut self, update: DepthUpdate) -> DepthSync {
+        let market = self.market;
+        let Some(state) = self.books.get_mut(&update.symbol) else {
+            self.books.insert(update.symbol.clone(), BookState::Buffering(vec![update]));
+            return DepthSync::Buffered { request_snapshot: true };
+        };
+
+        let (book, synced) = match &mut *state {
+            BookState::Buffering(buffer) => {
+                buffer.push(update);
+                return DepthSync::Buffered { request_snapshot: false };
+            }
+            BookState::Live { book, synced } => (book, synced),
+        };
+
+        let last = book.last_update_id;
+        let outdated = match market {
+            DepthMarket::Spot => update.final_update_id <= last,
+            DepthMarket::USwap => update.final_update_id < last,
+        };
+        if outdated {
+            return DepthSync::Stale;
+        }
+
+        // the first update has to straddle the snapshot, later ones have to chain on the previous.
+        let (follows, expected, received) = match (market, *synced) {
+            (DepthMarket::Spot, false) => (
+                update.first_update_id <= last + 1 && update.final_update_id > last,
+                last + 1,
+                update.first_update_id,
+            ),
+            (DepthMarket::Spot, true) => (update.first_update_id == last + 1, last + 1, update.first_update_id),
+            (DepthMarket::USwap, false) => (
+                update.first_update_id <= last && update.final_update_id >= last,
+                last,
+                update.first_update_id,
+            ),
+            (DepthMarket::USwap, true) => {
+                let previous = update.previous_final_update_id.unwrap_or_default();
+                (previous == last, last, previous)
+            }
+        };
+
+        if !follows {
+            *state = BookState::Buffering(vec![update]);
+            return DepthSync::Gap { expected, received };
+        }
+
+        book.apply(&update);
+        *synced = true;
+        DepthSync::Updated
+    }
+
+    /// Starts the book of `symbol` from `snapshot` and replays the buffered updates on it.
+    /// Ignored if the book is not waiting for a snapshot.
+    pub fn snapshot(&mut self, symbol: &str, snapshot: &binance_model::OrderBook) -> DepthSync {
+        let buffer = match self.books.get(symbol) {
+            Some(BookState::Buffering(_)) => match self.books.remove(symbol) {
+                Some(BookState::Buffering(buffer)) => buffer,
+                _ => return DepthSync::Stale,
+            },
+            _ => return DepthSync::Stale,
+        };
+
+        let book = LocalOrderBook::from_snapshot(self.market, symbol, snapshot);
+        self.books.insert(symbol.to_string(), BookState::Live { book, synced: false });
+
+        let mut outcome = DepthSync::Buffered { request_snapshot: false };
+        for update in buffer {
+            match self.update(update) {
+                DepthSync::Stale => (),
+                DepthSync::Gap { expected, received } => {
+                    // the snapshot is too old for the updates, queue the rest for the next one.
+                    outcome = DepthSync::Gap { expected, received };
+                }
+                sync @ (DepthSync::Updated | DepthSync::Buffered { .. }) => {
+                    if !matches!(outcome, DepthSync::Gap { .. }) {
+                        outcome = sync;
+                    }
+                }
+            }
+        }
+        outcome
+    }
+}
+
+impl Websocket {
+    /// Replaces the source of the depth snapshots, `RestSnapshotProvider` by default. Replays
+    /// take theirs from the recording.
+    pub fn set_snapshot_provider(&mut self, provider: Arc<dyn SnapshotProvider>) {
+        self.snapshot_provider = provider;
+    }
+
+    pub(crate) fn on_depth_update(&mut self, connection: &ConnectionId, update: DepthUpdate) {
+        let Some(sync) = self.depth_books.get_mut(connection) else {
+            return;
+        };
+        let market = sync.market();
+        let symbol = update.symbol.clone();
+
+        match sync.update(update) {
+            DepthSync::Buffered { request_snapshot: true } => {
+                self.request_snapshot(connection.clone(), market, symbol, Duration::ZERO);
+            }
+            DepthSync::Updated => self.emit_order_book(connection, &symbol),
+            DepthSync::Gap { expected, received } => {
+                warn!("[Websocket] {:?} depth gap on {}: expected {}, received {}", market, symbol, expected, received);
+                self.emit(connection, WebsocketEvent::BinanceOrderBookResync(ResyncEvent {
+                    market,
+                    symbol: symbol.clone(),
+                    reason: ResyncReason::Gap { expected, received },
+                }));
+                self.request_snapshot(connection.clone(), market, symbol, Duration::ZERO);
+            }
+            DepthSync::Buffered { .. } | DepthSync::Stale => (),
+        }
+    }
+
+    pub(crate) fn on_depth_snapshot(&mut self, connection: ConnectionId, symbol: String, snapshot: Result<binance_model::OrderBook>) {
+        let Some(sync) = self.depth_books.get_mut(&connection) else {
+            return;
+        };
+        let market = sync.market();
+
+        let snapshot = match snapshot {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!("[Websocket] {:?} depth snapshot of {} failed: {}", market, symbol, e);
+                self.emit(&connection, WebsocketEvent::BinanceOrderBookResync(ResyncEvent {
+                    market,
+                    symbol: symbol.clone(),
+                    reason: ResyncReason::SnapshotFailed(e.to_string()),
+                }));
+                self.request_snapshot(connection, market, symbol, SNAPSHOT_RETRY_DELAY);
+                return;
+            }
+        };
+
+        match sync.snapshot(&symbol, &snapshot) {
+            DepthSync::Updated => self.emit_order_book(&connection, &symbol),
+            DepthSync::Gap { expected, received } => {
+                warn!("[Websocket] {:?} depth snapshot of {} is behind the stream: expected {}, received {}", market, symbol, expected, received);
+                self.emit(&connection, WebsocketEvent::BinanceOrderBookResync(ResyncEvent {
+                    market,
+                    symbol: symbol.clone(),
+                    reason: ResyncReason::Gap { expected, received },
+                }));
+                self.request_snapshot(connection, market, symbol, Duration::ZERO);
+            }
+            DepthSync::Buffered { .. } | DepthSync::Stale => (),
+        }
+    }
+
+    /// Drops the books of `connection` after its connection was lost, they are synchronized
+    /// again from the first update of the new connection.
+    pub(crate) fn reset_order_books(&mut self, connection: &ConnectionId) {
+        let Some(sync) = self.depth_books.get_mut(connection) else {
+            return;
+        };
+        let market = sync.market();
+
+        for symbol in sync.clear() {
+            self.emit(connection, WebsocketEvent::BinanceOrderBookResync(ResyncEvent {
+                market,
+                symbol,
+                reason: ResyncReason::Disconnected,
+            }));
+        }
+    }
+
+    fn emit_order_book(&mut self, connection: &ConnectionId, symbol: &str) {
+        let book = self.depth_books.get(connection).and_then(|sync| sync.book(symbol)).cloned();
+        if let Some(book) = book {
+            self.emit(connection, WebsocketEvent::BinanceLocalOrderBook(book));
+        }
+    }
+
+    /// Records a snapshot fetched for `connection` if a recorder is set, see `replay`.
+    pub(crate) fn record_snapshot(&self, connection: &ConnectionId, symbol: &str, snapshot: &Result<binance_model::OrderBook>) {
+        let (Some(_), Ok(snapshot)) = (&self.recorder, snapshot) else {
+            return;
+        };
+        let recorded = RecordedSnapshot { symbol: symbol.to_string(), snapshot: snapshot.clone() };
+        match serde_json::to_string(&recorded) {
+            Ok(payload) => self.record(connection, Direction::Inbound, FrameKind::Snapshot, &payload),
+            Err(e) => warn!("[Websocket] cannot record the depth snapshot of {}: {}", symbol, e),
+        }
+    }
+
+    /// Does nothing while replaying, the snapshots come from the recording.
+    fn request_snapshot(&self, connection: ConnectionId, market: DepthMarket, symbol: String, delay: Duration) {
+        if self.replaying {
+            return;
+        }
+        let snapshot = self.snapshot_provider.snapshot(market, &symbol);
+
+        self.snapshots.push(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            (connection, symbol, snapshot.await)
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::{collections::VecDeque, sync::Mutex};
+
+    /// Serves the snapshots it was given, in order, and counts the requests.
+    struct FakeSnapshots {
+        answers: Mutex<VecDeque<Result<binance_model::OrderBook>>>,
+        requests: Mutex<usize>,
+    }
+
+    impl SnapshotProvider for FakeSnapshots {
+        fn snapshot(&self, _market: DepthMarket, _symbol: &str) -> SnapshotFuture {
+            *self.requests.lock().unwrap() += 1;
+            let answer = self.answers.lock().unwrap().pop_front().unwrap_or(Err(Error::SymbolNotFound));
+            Box::pin(async move { answer })
+        }
+    }
+
+    fn snapshot_at(last_update_id: u64) -> binance_model::OrderBook {
+        binance_model::OrderBook {
+            last_update_id,
+            bids: vec![binance_model::Bids { price: 100.0, qty: 1.0 }],
+            asks: vec![binance_model::Asks { price: 101.0, qty: 1.0 }],
+        }
+    }
+
+    struct Books {
+        ws: Websocket,
+        connection: ConnectionId,
+        provider: Arc<FakeSnapshots>,
+    }
+
+    impl Books {
+        fn new(market: DepthMarket, answers: Vec<Result<binance_model::OrderBook>>) -> Self {
+            let subscription = match market {
+                DepthMarket::Spot => Subscription::BinanceSpotMStream,
+                DepthMarket::USwap => Subscription::BinanceUSwapMStream,
+            };
+            let provider = Arc::new(FakeSnapshots { answers: Mutex::new(answers.into()), requests: Mutex::new(0) });
+            let mut ws = Websocket::new_streaming(HashMap::new());
+            ws.set_snapshot_provider(provider.clone());
+            ws.enable_local_order_book(subscription.clone()).unwrap();
+            Self { ws, connection: subscription.into(), provider }
+        }
+
+        /// Feeds the update `U..=u`, with `pu` on USDⓈ-M, setting the bid at 100 to `u`.
+        fn update(&mut self, first: u64, last: u64, previous: Option<u64>) {
+            let update = DepthUpdate {
+                symbol: "BTCUSDT".to_string(),
+                event_time: last,
+                first_update_id: first,
+                final_update_id: last,
+                previous_final_update_id: previous,
+                bids: vec![(dec!(100), Decimal::from(last))],
+                asks: Vec::new(),
+            };
+            self.ws.on_depth_update(&self.connection.clone(), update);
+        }
+
+        /// Waits for the next snapshot request and hands its answer to the book.
+        async fn answer_snapshot(&mut self) {
+            let (connection, symbol, snapshot) = self.ws.snapshots.next().await.unwrap();
+            self.ws.on_depth_snapshot(connection, symbol, snapshot);
+        }
+
+        fn requests(&self) -> usize {
+            *self.provider.requests.lock().unwrap()
+        }
+
+        fn events(&mut self) -> Vec<WebsocketEvent> {
+            self.ws.pending.drain(..).map(|envelope| envelope.event).collect()
+        }
+
+        fn book(&self) -> Option<&LocalOrderBook> {
+            self.ws.depth_books[&self.connection].book("BTCUSDT")
+        }
+    }
+
+    fn resyncs(events: &[WebsocketEvent]) -> Vec<ResyncReason> {
+        events.iter().filter_map(|event| match event {
+            WebsocketEvent::BinanceOrderBookResync(resync) => Some(resync.reason.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn spot_first_update_straddles_the_snapshot() {
+        let mut books = Books::new(DepthMarket::Spot, vec![Ok(snapshot_at(100))]);
+        books.update(95, 98, None);
+        books.update(99, 103, None);
+        books.update(104, 106, None);
+        assert!(books.book().is_none());
+        assert_eq!(books.requests(), 1);
+
+        books.answer_snapshot().await;
+        let book = books.book().unwrap();
+        assert_eq!(book.last_update_id, 106);
+        assert_eq!(book.best_bid(), Some((dec!(100), dec!(106))));
+        assert!(resyncs(&books.events()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn spot_first_update_after_the_snapshot_is_a_gap() {
+        let mut books = Books::new(DepthMarket::Spot, vec![Ok(snapshot_at(100)), Ok(snapshot_at(104))]);
+        books.update(102, 104, None);
+        books.answer_snapshot().await;
+        assert!(books.book().is_none());
+        assert_eq!(resyncs(&books.events()), vec![ResyncReason::Gap { expected: 101, received: 102 }]);
+
+        // the update is kept for the next snapshot, which it is stale for.
+        books.update(105, 107, None);
+        books.answer_snapshot().await;
+        assert_eq!(books.book().unwrap().last_update_id, 107);
+        assert_eq!(books.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn uswap_updates_chain_on_the_previous_final_id() {
+        let mut books = Books::new(DepthMarket::USwap, vec![Ok(snapshot_at(100))]);
+        books.update(95, 101, Some(94));
+        books.answer_snapshot().await;
+        assert_eq!(books.book().unwrap().last_update_id, 101);
+
+        // `U` does not have to follow on USDⓈ-M, `pu` does.
+        books.update(110, 115, Some(101));
+        assert_eq!(books.book().unwrap().last_update_id, 115);
+
+        books.update(120, 125, Some(118));
+        assert!(books.book().is_none());
+        assert_eq!(resyncs(&books.events()), vec![ResyncReason::Gap { expected: 115, received: 118 }]);
+        assert_eq!(books.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn stale_updates_are_dropped() {
+        let mut books = Books::new(DepthMarket::Spot, vec![Ok(snapshot_at(100))]);
+        books.update(99, 103, None);
+        books.answer_snapshot().await;
+        books.events();
+
+        books.update(90, 103, None);
+        let book = books.book().unwrap();
+        assert_eq!(book.last_update_id, 103);
+        assert_eq!(book.best_bid(), Some((dec!(100), dec!(103))));
+        assert!(books.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn gap_resyncs_from_a_new_snapshot() {
+        let mut books = Books::new(DepthMarket::Spot, vec![Ok(snapshot_at(100)), Ok(snapshot_at(110))]);
+        books.update(99, 103, None);
+        books.answer_snapshot().await;
+        books.events();
+
+        books.update(105, 108, None);
+        assert!(books.book().is_none());
+        assert_eq!(resyncs(&books.events()), vec![ResyncReason::Gap { expected: 104, received: 105 }]);
+
+        books.update(109, 112, None);
+        books.answer_snapshot().await;
+        assert_eq!(books.book().unwrap().last_update_id, 112);
+        assert!(books.events().iter().any(|event| matches!(event, WebsocketEvent::BinanceLocalOrderBook(_))));
+        assert_eq!(books.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_snapshot_is_retried() {
+        let mut books = Books::new(DepthMarket::Spot, vec![Err(Error::SymbolNotFound), Ok(snapshot_at(100))]);
+        books.update(99, 103, None);
+        books.answer_snapshot().await;
+        assert!(books.book().is_none());
+        assert!(matches!(resyncs(&books.events())[..], [ResyncReason::SnapshotFailed(_)]));
+
+        books.answer_snapshot().await;
+        assert_eq!(books.book().unwrap().last_update_id, 103);
+        assert_eq!(books.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn replay_takes_the_recorded_snapshot() {
+        use crate::{recorder::RecordedFrame, replay::Replay};
+
+        let mut books = Books::new(DepthMarket::Spot, Vec::new());
+        let frame = |kind, payload: String| RecordedFrame {
+            ts: chrono::Utc::now(),
+            subscription: Subscription::BinanceSpotMStream,
+            account: None,
+            direction: Direction::Inbound,
+            kind,
+            payload,
+        };
+        let update = |first, last| frame(FrameKind::Text, format!(
+            r#"{{"stream":"btcusdt@depth@100ms","data":{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{first},"u":{last},"b":[["100","{last}"]],"a":[]}}}}"#,
+        ));
+        let snapshot = serde_json::to_string(&RecordedSnapshot { symbol: "BTCUSDT".to_string(), snapshot: snapshot_at(100) }).unwrap();
+        let replay = Replay::from_frames([update(99, 103), frame(FrameKind::Snapshot, snapshot), update(104, 106)]);
+
+        books.ws.replay_envelopes(replay).for_each(|_| future::ready(())).await;
+        assert_eq!(books.book().unwrap().last_update_id, 106);
+        assert_eq!(books.requests(), 0);
+    }
+}
@@ -707,6 +707,7 @@ pub struct BookTickerEvent {
     pub best_ask_qty: String,
 }
 
+/// `prev_close` and the best bid and ask are not sent on the futures streams, they are empty.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct DayTickerEvent {
@@ -728,7 +729,7 @@ pub struct DayTickerEvent {
     #[serde(rename = "w")]
     pub average_price: String,
 
-    #[serde(rename = "x")]
+    #[serde(rename = "x", default)]
     pub prev_close: String,
 
     #[serde(rename = "c")]
@@ -737,16 +738,16 @@ pub struct DayTickerEvent {
     #[serde(rename = "Q")]
     pub current_close_qty: String,
 
-    #[serde(rename = "b")]
+    #[serde(rename = "b", default)]
     pub best_bid: String,
 
-    #[serde(rename = "B")]
+    #[serde(rename = "B", default)]
     pub best_bid_qty: String,
 
-    #[serde(rename = "a")]
+    #[serde(rename = "a", default)]
     pub best_ask: String,
 
-    #[serde(rename = "A")]
+    #[serde(rename = "A", default)]
     pub best_ask_qty: String,
 
     #[serde(rename = "o")]
@@ -909,10 +910,10 @@ pub struct Kline {
     pub interval: String,
 
     #[serde(rename = "f")]
-    pub first_trade_id: i32,
+    pub first_trade_id: i64,
 
     #[serde(rename = "L")]
-    pub last_trade_id: i32,
+    pub last_trade_id: i64,
 
     #[serde(rename = "o")]
     pub open: String,
@@ -930,7 +931,7 @@ pub struct Kline {
     pub volume: String,
 
     #[serde(rename = "n")]
-    pub number_of_trades: i32,
+    pub number_of_trades: i64,
 
     #[serde(rename = "x")]
     pub is_final_bar: bool,
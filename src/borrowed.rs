@@ -0,0 +1,177 @@
+//! Zero-copy decoding of the high-frequency Binance market streams.
+//!
+//! Diff. depth, partial depth and book ticker messages arrive many times a second per symbol.
+//! With `Websocket::enable_borrowed` they skip the owned `WebsocketEvent`s: they are decoded
+//! into `BorrowedEvent`s whose strings point into the frame buffer, handed to a dedicated
+//! handler, and dropped with the frame. Prices and quantities stay the strings Binance sent,
+//! parse the ones you need. Local order books keep working on a borrowed subscription.
+use crate::{
+    dispatch::{binance_channel, is_partial_depth},
+    error::{self, Error, Result},
+    models::{ConnectionId, Subscription},
+    websocket::Websocket,
+};
+use serde::Deserialize;
+use snafu::ResultExt;
+
+/// Receives the borrowed events of one subscription, see `Websocket::enable_borrowed`.
+pub type BorrowedHandler = Box<dyn FnMut(BorrowedEvent<'_>) -> Result<()> + Send>;
+
+/// A combined stream message, borrowed from the frame.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BorrowedRx<'a, T> {
+    pub stream: &'a str,
+    pub data: T,
+}
+
+#[derive(Debug, Clone)]
+pub enum BorrowedEvent<'a> {
+    /// `<symbol>@depth` and `<symbol>@depth@100ms`.
+    Depth(BorrowedRx<'a, DepthDiff<'a>>),
+    /// `<symbol>@depth<levels>`.
+    PartialDepth(BorrowedRx<'a, PartialDepth<'a>>),
+    /// `<symbol>@bookTicker` and `!bookTicker`.
+    BookTicker(BorrowedRx<'a, BookTicker<'a>>),
+}
+
+/// A price level, `[price, qty]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Level<'a>(pub &'a str, pub &'a str);
+
+impl<'a> Level<'a> {
+    #[must_use]
+    pub const fn price(&self) -> &'a str {
+        self.0
+    }
+
+    #[must_use]
+    pub const fn qty(&self) -> &'a str {
+        self.1
+    }
+}
+
+/// One diff. depth event, of spot or USDⓈ-M.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DepthDiff<'a> {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    /// Only sent on USDⓈ-M.
+    #[serde(rename = "T", default)]
+    pub transaction_time: Option<u64>,
+    #[serde(rename = "s")]
+    pub symbol: &'a str,
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    #[serde(rename = "u")]
+    pub final_update_id: u64,
+    /// Only sent on USDⓈ-M.
+    #[serde(rename = "pu", default)]
+    pub previous_final_update_id: Option<u64>,
+    #[serde(rename = "b", borrow)]
+    pub bids: Vec<Level<'a>>,
+    #[serde(rename = "a", borrow)]
+    pub asks: Vec<Level<'a>>,
+}
+
+/// The top levels of a book. Spot sends them as `lastUpdateId`, `bids` and `asks`, USDⓈ-M in
+/// the format of the diff. stream, both are read here. The symbol is only in the stream name.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PartialDepth<'a> {
+    #[serde(rename = "lastUpdateId", alias = "u")]
+    pub last_update_id: u64,
+    #[serde(rename = "bids", alias = "b", borrow)]
+    pub bids: Vec<Level<'a>>,
+    #[serde(rename = "asks", alias = "a", borrow)]
+    pub asks: Vec<Level<'a>>,
+}
+
+/// Best bid and ask of a symbol.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BookTicker<'a> {
+    #[serde(rename = "u")]
+    pub update_id: u64,
+    #[serde(rename = "s")]
+    pub symbol: &'a str,
+    #[serde(rename = "b")]
+    pub best_bid: &'a str,
+    #[serde(rename = "B")]
+    pub best_bid_qty: &'a str,
+    #[serde(rename = "a")]
+    pub best_ask: &'a str,
+    #[serde(rename = "A")]
+    pub best_ask_qty: &'a str,
+    /// Only sent on USDⓈ-M.
+    #[serde(rename = "E", default)]
+    pub event_time: Option<u64>,
+    /// Only sent on USDⓈ-M.
+    #[serde(rename = "T", default)]
+    pub transaction_time: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct StreamProbe<'a> {
+    #[serde(borrow)]
+    stream: Option<&'a str>,
+}
+
+impl<'a> BorrowedEvent<'a> {
+    /// Decodes a combined stream message if it belongs to a borrowed channel, `None` for the
+    /// other channels.
+    pub fn parse(raw: &'a str) -> Result<Option<Self>> {
+        let probe: StreamProbe = serde_json::from_str(raw).context(error::DecodeSnafu { raw })?;
+        let Some(channel) = probe.stream.and_then(binance_channel) else {
+            return Ok(None);
+        };
+
+        Ok(Some(match channel {
+            "depth" => Self::Depth(decode(raw)?),
+            "bookTicker" | "!bookTicker" => Self::BookTicker(decode(raw)?),
+            channel if is_partial_depth(channel) => Self::PartialDepth(decode(raw)?),
+            _ => return Ok(None),
+        }))
+    }
+}
+
+fn decode<'a, T: Deserialize<'a>>(raw: &'a str) -> Result<T> {
+    serde_json::from_str(raw).context(error::DecodeSnafu { raw })
+}
+
+impl Websocket {
+    /// Delivers the depth, partial depth and book ticker messages of `connection` to
+    /// `handler` as `BorrowedEvent`s, instead of the event handler or stream. Only the Binance
+    /// market streams are supported.
+    pub fn enable_borrowed<Callback>(&mut self, connection: impl Into<ConnectionId>, handler: Callback) -> Result<()>
+    where
+        Callback: FnMut(BorrowedEvent<'_>) -> Result<()> + Send + 'static,
+    {
+        let connection = connection.into();
+        match connection.subscription {
+            Subscription::BinanceSpotMStream | Subscription::BinanceUSwapMStream => {
+                self.borrowed.insert(connection, Box::new(handler));
+                Ok(())
+            }
+            _ => Err(Error::NoBorrowedChannels { subscription: connection.to_string() }),
+        }
+    }
+
+    /// Hands `raw` to the borrowed handler of `connection`. Returns `false` if there is none
+    /// or the message is not on a borrowed channel.
+    pub(crate) fn on_borrowed(&mut self, connection: &ConnectionId, raw: &str) -> Result<bool> {
+        let Some(handler) = self.borrowed.get_mut(connection) else {
+            return Ok(false);
+        };
+        let Some(event) = BorrowedEvent::parse(raw)? else {
+            return Ok(false);
+        };
+
+        let update = match &event {
+            BorrowedEvent::Depth(rx) if self.depth_books.contains_key(connection) => Some((&rx.data).into()),
+            _ => None,
+        };
+        handler(event)?;
+        if let Some(update) = update {
+            self.on_depth_update(connection, update);
+        }
+        Ok(true)
+    }
+}
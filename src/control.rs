@@ -0,0 +1,416 @@
+//! Adding and removing topics of open connections.
+//!
+//! `Websocket::control` returns a `ControlHandle`, it can be cloned and used from other tasks
+//! while the receive loop runs. Every subscribe and unsubscribe request is tracked until the
+//! exchange answers it: `ControlHandle::topics` tells the status of each topic and every
+//! answer is delivered as `WebsocketEvent::Topic`.
+//!
+//! Answers that do not name their topic (OKX and FTX errors, Binance answers) settle the
+//! oldest unanswered request of the connection. A refused request, including the ones sent
+//! when connecting, is a `TopicAck::Rejected` and leaves the connection up.
+use crate::{
+    error::Error,
+    ftx_model,
+    huobi_uswap_model::{OpStatus, SubStatus},
+    models::{ConnectionId, WebsocketEvent},
+    topic::{self, NativeTopic, Op, OkexArg, Topic},
+    websocket::Websocket,
+};
+use crate::error::Result;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, PoisonError},
+};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicStatus {
+    /// Requested, not acknowledged yet.
+    Subscribing,
+    Subscribed,
+    /// Unsubscribe requested, not acknowledged yet.
+    Unsubscribing,
+}
+
+/// Answer of the exchange to a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopicAck {
+    Subscribed,
+    Unsubscribed,
+    /// The request failed with the given message. A rejected subscription is dropped, a
+    /// rejected unsubscription stays subscribed.
+    Rejected(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TopicEvent {
+    /// The connection, and so the account, the topic is on.
+    pub connection: ConnectionId,
+    pub topic: NativeTopic,
+    pub ack: TopicAck,
+}
+
+#[derive(Debug)]
+pub(crate) struct Command {
+    op: Op,
+    connection: ConnectionId,
+    topics: Vec<NativeTopic>,
+}
+
+/// Status of the topics of every connection, shared with the handles.
+pub(crate) type TopicTable = Arc<Mutex<HashMap<ConnectionId, HashMap<NativeTopic, TopicStatus>>>>;
+
+/// A request sent and not answered yet.
+#[derive(Debug)]
+pub(crate) struct TopicRequest {
+    id: u64,
+    op: Op,
+    topics: Vec<NativeTopic>,
+}
+
+/// Unanswered requests, by connection.
+pub(crate) type TopicRequests = HashMap<ConnectionId, VecDeque<TopicRequest>>;
+
+/// Adds and removes topics of the connections of a `Websocket`, see `Websocket::control`.
+#[derive(Debug, Clone)]
+pub struct ControlHandle {
+    commands: UnboundedSender<Command>,
+    table: TopicTable,
+}
+
+impl ControlHandle {
+    /// Subscribes `topics` on `connection`, a `Subscription` for the default account.
+    /// Returns once the request is queued, the answer arrives as `WebsocketEvent::Topic`.
+    pub fn subscribe(&self, connection: impl Into<ConnectionId>, topics: &[Topic]) -> Result<()> {
+        self.command(Op::Subscribe, connection.into(), topics)
+    }
+
+    /// Unsubscribes `topics` on `connection`.
+    pub fn unsubscribe(&self, connection: impl Into<ConnectionId>, topics: &[Topic]) -> Result<()> {
+        self.command(Op::Unsubscribe, connection.into(), topics)
+    }
+
+    /// The topics of `connection` and their status.
+    #[must_use]
+    pub fn topics(&self, connection: impl Into<ConnectionId>) -> Vec<(NativeTopic, TopicStatus)> {
+        let table = self.table.lock().unwrap_or_else(PoisonError::into_inner);
+        table.get(&connection.into())
+            .map(|topics| topics.iter().map(|(topic, status)| (topic.clone(), *status)).collect())
+            .unwrap_or_default()
+    }
+
+    fn command(&self, op: Op, connection: ConnectionId, topics: &[Topic]) -> Result<()> {
+        let mut native = Vec::new();
+        for topic in topics {
+            native.extend(topic.native(&connection.subscription)?);
+        }
+
+        self.commands
+            .send(Command { op, connection, topics: native })
+            .map_err(|_| Error::WebsocketDropped)
+    }
+}
+
+/// `{"result":null,"id":1}`, or `{"code":2,"msg":"Invalid request","id":1}` on error.
+#[derive(Debug, Deserialize)]
+pub(crate) struct BinanceResponse {
+    id: u64,
+    code: Option<i64>,
+    msg: Option<String>,
+}
+
+/// `subscribe`, `unsubscribe` and `error` events of OKX.
+#[derive(Debug, Deserialize)]
+struct OkexEvent {
+    event: String,
+    arg: Option<OkexArg>,
+    code: Option<String>,
+    msg: Option<String>,
+}
+
+impl Websocket {
+    /// Returns a handle adding and removing topics while the connections are open.
+    #[must_use]
+    pub fn control(&self) -> ControlHandle {
+        ControlHandle {
+            commands: self.commands_tx.clone(),
+            table: self.topic_table.clone(),
+        }
+    }
+
+    pub(crate) async fn on_command(&mut self, command: Command) {
+        let Command { op, connection, topics } = command;
+
+        let Some(current) = self.topics.get_mut(&connection) else {
+            for topic in topics {
+                self.emit_topic(&connection, topic, TopicAck::Rejected("not connected".to_string()));
+            }
+            return;
+        };
+
+        // only what changes the set of the connection is sent.
+        let topics: Vec<NativeTopic> = match op {
+            Op::Subscribe => topics.into_iter()
+                .filter(|topic| !current.contains(topic))
+                .collect(),
+            Op::Unsubscribe => topics.into_iter()
+                .filter(|topic| current.contains(topic))
+                .collect(),
+        };
+        match op {
+            Op::Subscribe => current.extend(topics.iter().cloned()),
+            Op::Unsubscribe => current.retain(|topic| !topics.contains(topic)),
+        }
+        if topics.is_empty() {
+            return;
+        }
+
+        if !self.sinks.contains_key(&connection) {
+            // reconnecting, the handshake of the new connection subscribes the current set.
+            let ack = match op {
+                Op::Subscribe => {
+                    self.set_status(&connection, &topics, Some(TopicStatus::Subscribing));
+                    return;
+                }
+                Op::Unsubscribe => TopicAck::Unsubscribed,
+            };
+            self.set_status(&connection, &topics, None);
+            for topic in topics {
+                self.emit_topic(&connection, topic, ack.clone());
+            }
+            return;
+        }
+
+        if let Err(e) = self.send_requests(&connection, op, &topics).await {
+            self.connection_lost(&connection, e.to_string());
+        }
+    }
+
+    /// Sends the requests for `topics` and tracks them until they are answered.
+    pub(crate) async fn send_requests(&mut self, connection: &ConnectionId, op: Op, topics: &[NativeTopic]) -> Result<()> {
+        if topics.is_empty() {
+            return Ok(());
+        }
+        let status = match op {
+            Op::Subscribe => TopicStatus::Subscribing,
+            Op::Unsubscribe => TopicStatus::Unsubscribing,
+        };
+        self.set_status(connection, topics, Some(status));
+
+        self.next_request_id += 1;
+        let id = self.next_request_id;
+        for (message, topics) in topic::requests(op, &connection.subscription, topics, id) {
+            self.topic_requests.entry(connection.clone()).or_default()
+                .push_back(TopicRequest { id, op, topics });
+            self.send(connection, message).await?;
+        }
+        Ok(())
+    }
+
+    /// Subscribes the topics of a freshly opened connection, after the login on private
+    /// streams. Binance topics are part of the url and subscribed already.
+    pub(crate) async fn sub_topics(&mut self, connection: &ConnectionId) -> Result<()> {
+        let topics = self.topics.get(connection).cloned().unwrap_or_default();
+        let (binance, others): (Vec<NativeTopic>, Vec<NativeTopic>) = topics.into_iter()
+            .partition(|topic| matches!(topic, NativeTopic::Binance(_)));
+
+        self.set_status(connection, &binance, Some(TopicStatus::Subscribed));
+        self.send_requests(connection, Op::Subscribe, &others).await
+    }
+
+    /// Forgets the requests of a lost connection. The topics that are still wanted are
+    /// subscribed again after reconnecting, the ones being unsubscribed are gone with it.
+    pub(crate) fn reset_topics(&mut self, connection: &ConnectionId) {
+        self.topic_requests.remove(connection);
+
+        let current = self.topics.get(connection).cloned().unwrap_or_default();
+        let dropped: Vec<NativeTopic> = {
+            let mut table = self.topic_table.lock().unwrap_or_else(PoisonError::into_inner);
+            let Some(statuses) = table.get_mut(connection) else {
+                return;
+            };
+            let dropped = statuses.keys().filter(|topic| !current.contains(topic)).cloned().collect();
+            statuses.retain(|topic, _| current.contains(topic));
+            for status in statuses.values_mut() {
+                *status = TopicStatus::Subscribing;
+            }
+            drop(table);
+            dropped
+        };
+
+        for topic in dropped {
+            self.emit_topic(connection, topic, TopicAck::Unsubscribed);
+        }
+    }
+
+    /// The exchange acknowledged `op` for `topic`.
+    pub(crate) fn on_topic_ack(&mut self, connection: &ConnectionId, op: Op, topic: &NativeTopic) {
+        if let Some(requests) = self.topic_requests.get_mut(connection) {
+            if let Some(index) = requests.iter().position(|request| request.op == op && request.topics.contains(topic)) {
+                requests[index].topics.retain(|pending| pending != topic);
+                if requests[index].topics.is_empty() {
+                    requests.remove(index);
+                }
+            }
+        }
+
+        // the books resubscribe their channels on their own, such answers change nothing.
+        let wanted = self.topics.get(connection).is_some_and(|topics| topics.contains(topic));
+        let status = self.status(connection, topic);
+        let ack = match op {
+            Op::Subscribe if wanted && status != Some(TopicStatus::Subscribed) => {
+                self.set_status(connection, std::slice::from_ref(topic), Some(TopicStatus::Subscribed));
+                TopicAck::Subscribed
+            }
+            Op::Unsubscribe if !wanted && status.is_some() => {
+                self.set_status(connection, std::slice::from_ref(topic), None);
+                TopicAck::Unsubscribed
+            }
+            _ => return,
+        };
+        self.emit_topic(connection, topic.clone(), ack);
+    }
+
+    /// The exchange answered the request `id`, or the oldest request when `None`. A refused
+    /// request is reported as `TopicAck::Rejected` for each of its topics, the connection
+    /// stays up.
+    pub(crate) fn on_request_answer(&mut self, connection: &ConnectionId, id: Option<u64>, error: Option<String>) {
+        let Some(requests) = self.topic_requests.get_mut(connection) else {
+            return;
+        };
+        let index = match id {
+            Some(id) => requests.iter().position(|request| request.id == id),
+            None if requests.is_empty() => None,
+            None => Some(0),
+        };
+        let Some(request) = index.and_then(|index| requests.remove(index)) else {
+            return;
+        };
+
+        let Some(reason) = error else {
+            for topic in &request.topics {
+                self.on_topic_ack(connection, request.op, topic);
+            }
+            return;
+        };
+
+        warn!("[Websocket] {:?} request on {:?} rejected: {}", request.op, connection, reason);
+        match request.op {
+            Op::Subscribe => {
+                if let Some(topics) = self.topics.get_mut(connection) {
+                    topics.retain(|topic| !request.topics.contains(topic));
+                }
+                self.set_status(connection, &request.topics, None);
+            }
+            Op::Unsubscribe => {
+                if let Some(topics) = self.topics.get_mut(connection) {
+                    topics.extend(request.topics.iter().cloned());
+                }
+                self.set_status(connection, &request.topics, Some(TopicStatus::Subscribed));
+            }
+        }
+        for topic in request.topics {
+            self.emit_topic(connection, topic, TopicAck::Rejected(reason.clone()));
+        }
+    }
+
+    pub(crate) fn has_topic_requests(&self, connection: &ConnectionId) -> bool {
+        self.topic_requests.get(connection).is_some_and(|requests| !requests.is_empty())
+    }
+
+    pub(crate) fn on_binance_response(&mut self, connection: &ConnectionId, rsp: BinanceResponse) {
+        let error = rsp.code.map(|code| format!("{}: {}", code, rsp.msg.unwrap_or_default()));
+        self.on_request_answer(connection, Some(rsp.id), error);
+    }
+
+    /// Handles the `subscribe`, `unsubscribe` and `error` events of OKX, others are ignored.
+    pub(crate) fn on_okex_event(&mut self, connection: &ConnectionId, raw: &str) {
+        let Ok(event) = serde_json::from_str::<OkexEvent>(raw) else {
+            return;
+        };
+        match (event.event.as_str(), event.arg) {
+            ("subscribe", Some(arg)) => self.on_topic_ack(connection, Op::Subscribe, &NativeTopic::Okex(arg)),
+            ("unsubscribe", Some(arg)) => self.on_topic_ack(connection, Op::Unsubscribe, &NativeTopic::Okex(arg)),
+            ("error", _) => {
+                let reason = format!("{}: {}", event.code.unwrap_or_default(), event.msg.unwrap_or_default());
+                self.on_request_answer(connection, None, Some(reason));
+            }
+            _ => (),
+        }
+    }
+
+    /// Handles the `subbed`, `unsubbed` and error answers of the Huobi market stream.
+    pub(crate) fn on_huobi_sub_status(&mut self, connection: &ConnectionId, msg: &SubStatus) {
+        if let Some(ch) = &msg.subbed {
+            self.on_topic_ack(connection, Op::Subscribe, &NativeTopic::Huobi(ch.clone()));
+        } else if let Some(ch) = &msg.unsubbed {
+            self.on_topic_ack(connection, Op::Unsubscribe, &NativeTopic::Huobi(ch.clone()));
+        } else if msg.status == "error" {
+            let reason = format!("{}: {}", msg.err_code.clone().unwrap_or_default(), msg.err_msg.clone().unwrap_or_default());
+            self.on_request_answer(connection, None, Some(reason));
+        }
+    }
+
+    /// Handles the `sub` and `unsub` answers of the Huobi order stream.
+    pub(crate) fn on_huobi_op_status(&mut self, connection: &ConnectionId, msg: &OpStatus) {
+        let op = match msg.op.as_str() {
+            "sub" => Op::Subscribe,
+            "unsub" => Op::Unsubscribe,
+            _ => return,
+        };
+        match (msg.err_code, &msg.topic) {
+            (Some(0), Some(topic)) => self.on_topic_ack(connection, op, &NativeTopic::Huobi(topic.clone())),
+            (Some(code), _) => {
+                let reason = format!("{}: {}", code, msg.err_msg.clone().unwrap_or_default());
+                self.on_request_answer(connection, None, Some(reason));
+            }
+            _ => (),
+        }
+    }
+
+    /// Handles the `subscribed`, `unsubscribed` and `error` responses of FTX.
+    pub(crate) fn on_ftx_response(&mut self, connection: &ConnectionId, msg: &ftx_model::Response) {
+        let topic = || msg.channel.as_deref().map(|channel| NativeTopic::ftx(channel, msg.market.as_deref()));
+        match msg.r#type {
+            ftx_model::Type::Subscribed => {
+                if let Some(topic) = topic() {
+                    self.on_topic_ack(connection, Op::Subscribe, &topic);
+                }
+            }
+            ftx_model::Type::Unsubscribed => {
+                if let Some(topic) = topic() {
+                    self.on_topic_ack(connection, Op::Unsubscribe, &topic);
+                }
+            }
+            ftx_model::Type::Error => {
+                let reason = format!("{}: {}", msg.code.unwrap_or_default(), msg.msg.clone().unwrap_or_default());
+                self.on_request_answer(connection, None, Some(reason));
+            }
+            _ => (),
+        }
+    }
+
+    fn status(&self, connection: &ConnectionId, topic: &NativeTopic) -> Option<TopicStatus> {
+        let table = self.topic_table.lock().unwrap_or_else(PoisonError::into_inner);
+        table.get(connection).and_then(|statuses| statuses.get(topic)).copied()
+    }
+
+    fn set_status(&self, connection: &ConnectionId, topics: &[NativeTopic], status: Option<TopicStatus>) {
+        let mut table = self.topic_table.lock().unwrap_or_else(PoisonError::into_inner);
+        let statuses = table.entry(connection.clone()).or_default();
+        for topic in topics {
+            match status {
+                Some(status) => statuses.insert(topic.clone(), status),
+                None => statuses.remove(topic),
+            };
+        }
+        drop(table);
+    }
+
+    fn emit_topic(&mut self, connection: &ConnectionId, topic: NativeTopic, ack: TopicAck) {
+        let event = TopicEvent { connection: connection.clone(), topic, ack };
+        self.emit(connection, WebsocketEvent::Topic(event));
+    }
+}
@@ -0,0 +1,316 @@
+//! Routing of raw messages to their struct by the discriminator each exchange sends.
+//!
+//! The stream enums are `#[serde(untagged)]`, decoding them tries every variant in turn. The
+//! `parse` functions here read the discriminator first (the Binance stream name or event
+//! type, the Huobi `ch` or `topic`, the OKX `arg.channel`, the FTX `channel` and `type`) and decode the message straight
+//! into the matching variant. Messages they do not recognize still go through the untagged
+//! enum.
+use crate::{
+    binance_uswap_model::{DepthOrderBookEvent, OrderBook},
+    error::{self, Result},
+    ftx_model::{Response, ResponseData, Symbol, Type},
+    models::{
+        BnWsRx, BinanceSpotWebsocketEvent, BinanceUSwapWebsocketEvent, FtxWebsocketEvent,
+        HuobiUSwapWebsocketEvent, OkexWebsocketEvent,
+    },
+    websocket::decode,
+};
+use serde::{de::{DeserializeOwned, IgnoredAny}, Deserialize};
+use snafu::ResultExt;
+use std::borrow::Cow;
+
+/// The discriminators of a Binance message: `stream` of combined streams, `e` of raw ones.
+#[derive(Deserialize)]
+struct BinanceProbe<'a> {
+    #[serde(borrow)]
+    stream: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    e: Option<Cow<'a, str>>,
+}
+
+/// The event type of a combined user data stream, whose stream name is the listen key.
+#[derive(Deserialize)]
+struct BinanceDataProbe<'a> {
+    #[serde(borrow)]
+    data: BinanceProbe<'a>,
+}
+
+#[derive(Deserialize)]
+struct HuobiProbe<'a> {
+    ping: Option<IgnoredAny>,
+    rep: Option<IgnoredAny>,
+    #[serde(borrow)]
+    ch: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    op: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    topic: Option<Cow<'a, str>>,
+}
+
+#[derive(Deserialize)]
+struct OkexProbe<'a> {
+    event: Option<IgnoredAny>,
+    #[serde(borrow)]
+    arg: Option<OkexArgProbe<'a>>,
+}
+
+#[derive(Deserialize)]
+struct OkexArgProbe<'a> {
+    #[serde(borrow)]
+    channel: Cow<'a, str>,
+}
+
+#[derive(Deserialize)]
+struct FtxProbe<'a> {
+    #[serde(borrow)]
+    channel: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    r#type: Cow<'a, str>,
+}
+
+/// An FTX `Response` whose `data` is decoded as `T`.
+#[derive(Deserialize)]
+struct FtxFrame<T> {
+    channel: Option<String>,
+    market: Option<Symbol>,
+    r#type: Type,
+    data: Option<T>,
+    code: Option<i32>,
+    msg: Option<String>,
+}
+
+/// The OKX channels whose messages `OkexWebsocketEvent::parse` decodes as `OkexOrderBook`:
+/// the incremental books with a checksum. `books5` and `bbo-tbt` send whole snapshots without
+/// one and are not decoded.
+const OKEX_BOOK_CHANNELS: &[&str] = &["books", "books50-l2-tbt", "books-l2-tbt"];
+
+fn probe<'a, T: Deserialize<'a>>(raw: &'a str) -> Result<T> {
+    serde_json::from_str(raw).context(error::DecodeSnafu { raw })
+}
+
+/// The channel of a Binance stream name: `depth5` of `btcusdt@depth5@100ms`, `!ticker` of
+/// `!ticker@arr`. `None` for a listen key.
+pub fn binance_channel(stream: &str) -> Option<&str> {
+    if stream.starts_with('!') {
+        return stream.split('@').next();
+    }
+    stream.split('@').nth(1)
+}
+
+/// `depth5`, `depth10` and `depth20` are partial books, `depth` is the diff. stream.
+pub fn is_partial_depth(channel: &str) -> bool {
+    channel.strip_prefix("depth").is_some_and(|levels| !levels.is_empty())
+}
+
+impl BinanceSpotWebsocketEvent {
+    /// Decodes a message of a spot stream by its stream name, or by its event type on user
+    /// data streams.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let probe: BinanceProbe = probe(raw)?;
+        let Some(stream) = probe.stream else {
+            return decode(raw);
+        };
+
+        Ok(match binance_channel(&stream) {
+            Some("aggTrade") => Self::BinanceSpotAggrTrades(decode(raw)?),
+            Some("trade") => Self::BinanceSpotTrade(decode(raw)?),
+            Some("depth") => Self::BinanceSpotDepthOrderBook(decode(raw)?),
+            Some(channel) if is_partial_depth(channel) => Self::BinanceSpotOrderBook(decode(raw)?),
+            Some("ticker") => Self::BinanceSpotDayTicker(decode(raw)?),
+            Some("!ticker") => Self::BinanceSpotDayTickerAll(decode(raw)?),
+            Some(channel) if channel.starts_with("kline_") => Self::BinanceSpotKline(decode(raw)?),
+            Some("bookTicker") => Self::BinanceSpotBookTicker(decode(raw)?),
+            Some(_) => decode(raw)?,
+            None => {
+                let probe: BinanceDataProbe = self::probe(raw)?;
+                match probe.data.e.as_deref() {
+                    Some("outboundAccountPosition") => Self::BinanceSpotAccountUpdate(decode(raw)?),
+                    Some("balanceUpdate") => Self::BinanceSpotBalanceUpdate(decode(raw)?),
+                    Some("executionReport") => Self::BinanceSpotOrderTrade(decode(raw)?),
+                    Some("listenKeyExpired") => Self::BinanceSpotListenKeyEvent(decode(raw)?),
+                    _ => decode(raw)?,
+                }
+            }
+        })
+    }
+}
+
+impl BinanceUSwapWebsocketEvent {
+    /// Decodes a message of a USDⓈ-M or BLVT stream by its stream name, or by its event type
+    /// on the user data stream.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let probe: BinanceProbe = probe(raw)?;
+        let Some(stream) = probe.stream else {
+            return Ok(match probe.e.as_deref() {
+                Some("ACCOUNT_UPDATE") => Self::BinanceUSwapAccountUpdateEvent(decode(raw)?),
+                Some("ORDER_TRADE_UPDATE") => Self::BinanceUSwapOrderTradeEvent(decode(raw)?),
+                Some("listenKeyExpired") => Self::BinanceUSwapListenKeyEvent(decode(raw)?),
+                Some("MARGIN_CALL") => Self::BinanceUSwapMarginCallEvent(decode(raw)?),
+                Some("ACCOUNT_CONFIG_UPDATE") => Self::BinanceUSwapAccountConfigEvent(decode(raw)?),
+                _ => decode(raw)?,
+            });
+        };
+
+        Ok(match binance_channel(&stream) {
+            Some("bookTicker" | "!bookTicker") => Self::BinanceUSwapBookTickerEvent(decode(raw)?),
+            Some("aggTrade") => Self::BinanceUSwapAggrTradesEvent(decode(raw)?),
+            Some("ticker") => Self::BinanceUSwapDayTickerEvent(decode(raw)?),
+            Some("!ticker") => Self::BinanceUSwapVec(decode(raw)?),
+            Some("miniTicker") => Self::BinanceUSwapMiniTickerEvent(decode(raw)?),
+            Some("!miniTicker") => Self::BinanceUSwapVecMiniTickerEvent(decode(raw)?),
+            Some("markPrice") => Self::BinanceUSwapMarkPriceEvent(decode(raw)?),
+            Some("!markPrice") => Self::BinanceUSwapVecMarkPriceEvent(decode(raw)?),
+            Some("indexPrice") => Self::BinanceUSwapIndexPriceEvent(decode(raw)?),
+            Some("trade") => Self::BinanceUSwapTradeEvent(decode(raw)?),
+            Some("forceOrder" | "!forceOrder") => Self::BinanceUSwapLiquidationEvent(decode(raw)?),
+            Some("depth") => Self::BinanceUSwapDepthOrderBookEvent(decode(raw)?),
+            Some(channel) if is_partial_depth(channel) => {
+                // partial books come in the format of the diff. stream.
+                let rx: BnWsRx<DepthOrderBookEvent> = decode(raw)?;
+                Self::BinanceUSwapOrderBook(BnWsRx {
+                    stream: rx.stream,
+                    data: OrderBook { last_update_id: rx.data.final_update_id, bids: rx.data.bids, asks: rx.data.asks },
+                })
+            }
+            Some(channel) if channel.starts_with("kline_") => Self::BinanceUSwapKlineEvent(decode(raw)?),
+            Some(channel) if channel.starts_with("continuousKline_") => Self::BinanceUSwapContinuousKlineEvent(decode(raw)?),
+            Some(channel) if channel.starts_with("indexPriceKline_") => Self::BinanceUSwapIndexKlineEvent(decode(raw)?),
+            Some("tokenNav") => Self::BinanceUSwapEtpNavEvent(decode(raw)?),
+            // `nav_Kline_<interval>` as documented, the example subscribes to `nav_kline_`.
+            Some(channel) if channel.starts_with("nav_Kline_") || channel.starts_with("nav_kline_") => Self::BinanceUSwapEtpKlineEvent(decode(raw)?),
+            _ => decode(raw)?,
+        })
+    }
+}
+
+impl HuobiUSwapWebsocketEvent {
+    /// Decodes a decompressed Huobi message by its `ch` on the market stream, or its `op` and
+    /// `topic` on the order stream.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let probe: HuobiProbe = probe(raw)?;
+
+        if probe.ping.is_some() {
+            return Ok(Self::HuobiUSwapMarketPing(decode(raw)?));
+        }
+        if probe.rep.is_some() {
+            return Ok(Self::HuobiUSwapDepthSnapshot(decode(raw)?));
+        }
+        if let Some(ch) = probe.ch {
+            return Ok(if ch.contains(".depth.size_") {
+                Self::HuobiUSwapIncrementalOrderBook(decode(raw)?)
+            } else if ch.contains(".depth.step") {
+                Self::HuobiUSwapOrderBook(decode(raw)?)
+            } else if ch.ends_with(".bbo") {
+                Self::HuobiUSwapBBO(decode(raw)?)
+            } else if ch.contains(".kline.") {
+                Self::HuobiUSwapKline(decode(raw)?)
+            } else if ch.ends_with(".trade.detail") {
+                Self::HuobiUSwapTradeDetail(decode(raw)?)
+            } else {
+                decode(raw)?
+            });
+        }
+
+        match (probe.op.as_deref(), probe.topic.as_deref()) {
+            (Some("notify"), Some(topic)) => {
+                // `orders_cross.btc-usdt`, `accounts.btc-usdt`, ...
+                let channel = topic.split('.').next().unwrap_or_default();
+                Ok(match channel.trim_end_matches("_cross") {
+                    "orders" => Self::HuobiUSwapOrder(decode(raw)?),
+                    "matchOrders" => Self::HuobiUSwapMatchOrder(decode(raw)?),
+                    "accounts" => Self::HuobiUSwapAccount(decode(raw)?),
+                    "positions" => Self::HuobiUSwapPosition(decode(raw)?),
+                    _ => decode(raw)?,
+                })
+            }
+            (Some(_), _) => Ok(Self::HuobiUSwapOpStatus(decode(raw)?)),
+            // answers to `sub` and `unsub` on the market stream.
+            (None, _) => Ok(Self::HuobiUSwapSubStatus(decode(raw)?)),
+        }
+    }
+}
+
+/// Returns `true` if `raw` is a message of an OKX book channel.
+pub fn is_okex_book(raw: &str) -> bool {
+    serde_json::from_str::<OkexProbe>(raw)
+        .is_ok_and(|probe| probe.event.is_none() && probe.arg.is_some_and(|arg| OKEX_BOOK_CHANNELS.contains(&arg.channel.as_ref())))
+}
+
+impl OkexWebsocketEvent {
+    /// Decodes an OKX message by its `arg.channel`, events by whether they name a channel.
+    pub fn parse(raw: &str) -> Result<Self> {
+        if raw == "pong" {
+            return Ok(Self::Pong);
+        }
+        let probe: OkexProbe = probe(raw)?;
+
+        if probe.event.is_some() {
+            return Ok(match probe.arg {
+                Some(_) => Self::OkexSubRsp(decode(raw)?),
+                None => Self::OkexSubEvent(decode(raw)?),
+            });
+        }
+
+        let Some(arg) = probe.arg else {
+            return decode(raw);
+        };
+        Ok(match arg.channel.as_ref() {
+            channel if OKEX_BOOK_CHANNELS.contains(&channel) => Self::OkexOrderBook(decode(raw)?),
+            "trades" => Self::OkexTrade(decode(raw)?),
+            "orders" => Self::OkexOrder(decode(raw)?),
+            "balance_and_position" => Self::OkexAccountPosition(decode(raw)?),
+            "account" => Self::OkexAccount(decode(raw)?),
+            "positions" => Self::OkexPosition(decode(raw)?),
+            _ => decode(raw)?,
+        })
+    }
+}
+
+impl FtxWebsocketEvent {
+    /// Decodes an FTX message, the `data` of updates and partials by their `channel`.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let probe: FtxProbe = probe(raw)?;
+        if !matches!(probe.r#type.as_ref(), "update" | "partial") {
+            return Ok(Self::FtxRsp(decode(raw)?));
+        }
+
+        Ok(Self::FtxRsp(match probe.channel.as_deref() {
+            Some("ticker") => ftx_response(raw, ResponseData::Ticker)?,
+            Some("trades") => ftx_response(raw, ResponseData::Trades)?,
+            Some("orderbook") => ftx_response(raw, ResponseData::OrderbookData)?,
+            Some("fills") => ftx_response(raw, ResponseData::Fill)?,
+            Some("orders") => ftx_response(raw, ResponseData::Order)?,
+            _ => decode(raw)?,
+        }))
+    }
+}
+
+/// Decodes an FTX message with its `data` as `T`, wrapped by `data`.
+fn ftx_response<T: DeserializeOwned>(raw: &str, data: fn(T) -> ResponseData) -> Result<Response> {
+    let frame: FtxFrame<T> = decode(raw)?;
+    Ok(Response {
+        channel: frame.channel,
+        market: frame.market,
+        r#type: frame.r#type,
+        data: frame.data.map(data),
+        code: frame.code,
+        msg: frame.msg,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn okex_books_are_the_incremental_channels() {
+        let message = |channel: &str| format!(r#"{{"arg":{{"channel":"{channel}","instId":"BTC-USDT"}},"data":[]}}"#);
+        for channel in ["books", "books50-l2-tbt", "books-l2-tbt"] {
+            assert!(is_okex_book(&message(channel)), "{channel}");
+        }
+        for channel in ["books5", "bbo-tbt", "trades"] {
+            assert!(!is_okex_book(&message(channel)), "{channel}");
+        }
+        assert!(!is_okex_book(r#"{"event":"subscribe","arg":{"channel":"books","instId":"BTC-USDT"}}"#));
+    }
+}
@@ -0,0 +1,115 @@
+//! The hosts the subscriptions connect to.
+//!
+//! Every subscription connects to the production host of its exchange unless another
+//! `Endpoint` is set with `Websocket::set_endpoint`: a testnet, a regional host or a local
+//! stand-in server. The path of each stream (`/stream?streams=...`, `/ws/v5/public`, ...) is
+//! appended to the configured base, and signatures that include the host, like the Huobi
+//! auth request, are computed with the host of the base.
+use crate::{
+    error::{self, Error, Result},
+    models::Subscription,
+};
+use snafu::ResultExt;
+use tungstenite::{
+    client::IntoClientRequest,
+    handshake::client::Request,
+    http::{header::HeaderName, HeaderValue},
+};
+use url::Url;
+
+/// Base URL and handshake headers of a subscription.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// Scheme, host, port and path prefix, e.g. `wss://fstream.binance.com`.
+    pub base: String,
+    /// Sent with the websocket handshake.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Endpoint {
+    #[must_use]
+    pub fn new(base: &str) -> Self {
+        Self { base: base.trim_end_matches('/').to_string(), headers: Vec::new() }
+    }
+
+    /// Adds a header to the websocket handshake.
+    #[must_use]
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The production host of `subscription`.
+    #[must_use]
+    pub fn production(subscription: &Subscription) -> Self {
+        Self::new(match *subscription {
+            Subscription::BinanceSpotMStream | Subscription::BinanceSpotOrder => "wss://stream.binance.com:9443",
+            Subscription::BinanceUSwapMStream | Subscription::BinanceUSwapOrder => "wss://fstream.binance.com",
+            Subscription::BinanceBLVTStream => "wss://nbstream.binance.com/lvt-p",
+            Subscription::BinanceSpotApi => "wss://ws-api.binance.com:443",
+            Subscription::BinanceUSwapApi => "wss://ws-fapi.binance.com",
+            Subscription::HuobiUSwapMarketStream | Subscription::HuobiUSwapOrderStream => "wss://api.hbdm.vn",
+            Subscription::OkexMarketStream | Subscription::OkexOrderStream => "wss://wsaws.okex.com:8443",
+            Subscription::FtxMarketStream | Subscription::FtxOrderStream => "wss://ftx.com",
+        })
+    }
+
+    /// The Binance spot or USDⓈ-M futures testnet. `None` for the BLVT stream and the other
+    /// exchanges.
+    #[must_use]
+    pub fn binance_testnet(subscription: &Subscription) -> Option<Self> {
+        match *subscription {
+            Subscription::BinanceSpotMStream | Subscription::BinanceSpotOrder | Subscription::BinanceSpotApi =>
+                Some(Self::new("wss://testnet.binance.vision")),
+            Subscription::BinanceUSwapMStream | Subscription::BinanceUSwapOrder => Some(Self::new("wss://stream.binancefuture.com")),
+            Subscription::BinanceUSwapApi => Some(Self::new("wss://testnet.binancefuture.com")),
+            _ => None,
+        }
+    }
+
+    /// OKX demo trading, which needs the `x-simulated-trading: 1` header. Demo API keys only
+    /// log in there.
+    #[must_use]
+    pub fn okex_demo(subscription: &Subscription) -> Option<Self> {
+        match *subscription {
+            Subscription::OkexMarketStream | Subscription::OkexOrderStream => {
+                Some(Self::new("wss://wspap.okx.com:8443").with_header("x-simulated-trading", "1"))
+            }
+            _ => None,
+        }
+    }
+
+    /// The `api.hbdm.com` host of Huobi, for clients outside of AWS Japan.
+    #[must_use]
+    pub fn huobi_com(subscription: &Subscription) -> Option<Self> {
+        match *subscription {
+            Subscription::HuobiUSwapMarketStream | Subscription::HuobiUSwapOrderStream => Some(Self::new("wss://api.hbdm.com")),
+            _ => None,
+        }
+    }
+
+    /// The URL of the stream at `path` on this endpoint.
+    pub(crate) fn url(&self, path: &str) -> Result<Url> {
+        let url = format!("{}{path}", self.base);
+        Url::parse(&url).context(error::InvalidUrlSnafu { url })
+    }
+
+    /// The host, with the port if the base has one, as it goes into signatures.
+    pub(crate) fn host(&self) -> Result<String> {
+        let url = self.url("")?;
+        let host = url.host_str().unwrap_or_default();
+        Ok(url.port().map_or_else(|| host.to_string(), |port| format!("{host}:{port}")))
+    }
+
+    /// The handshake request of `url`, with the headers of this endpoint.
+    pub(crate) fn request(&self, url: &Url) -> Result<Request> {
+        let mut request = url.as_str().into_client_request().context(error::ConnectSnafu { url: url.as_str() })?;
+        for (name, value) in &self.headers {
+            let invalid = || Error::InvalidHeader { name: name.clone() };
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+            let value = HeaderValue::from_str(value).map_err(|_| invalid())?;
+            request.headers_mut().insert(name, value);
+        }
+        Ok(request)
+    }
+}
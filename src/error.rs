@@ -1,8 +1,9 @@
-use serde::{Deserialize, Serialize};
-use snafu::*;
+use snafu::Snafu;
 
-#[allow(clippy::pub_enum_variant_names)]
-#[derive(Deserialize, Serialize, Debug, Clone, Snafu)]
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
 pub enum Error {
     #[snafu(display("Huobi Future error: {}: {}", code, msg))]
     HuobiError { code: i64, msg: String },
@@ -10,8 +11,79 @@ pub enum Error {
     AssetsNotFound,
     #[snafu(display("Symbol not found"))]
     SymbolNotFound,
-    #[snafu(display("No Api key set for private api"))]
-    NoApiKeySet,
+    #[snafu(display("No Api key set for {}", subscription))]
+    NoApiKeySet { subscription: String },
     #[snafu(display("No stream is subscribed"))]
     NoStreamSubscribed,
+    /// A price, quantity or time of a message that is not a valid value, see `normalized`.
+    #[snafu(display("Invalid number {}", value))]
+    InvalidNumber { value: String },
+    #[snafu(display("Invalid time {}", value))]
+    InvalidTime { value: String },
+    #[snafu(display("Invalid side {}", value))]
+    InvalidSide { value: String },
+    #[snafu(display("{} is not supported on {}: {}", topic, subscription, reason))]
+    UnsupportedTopic { topic: String, subscription: String, reason: String },
+    #[snafu(display("The websocket was dropped"))]
+    WebsocketDropped,
+    #[snafu(display("No local order book for {}", subscription))]
+    NoLocalOrderBook { subscription: String },
+    #[snafu(display("{} has no listen key", subscription))]
+    NoListenKey { subscription: String },
+    #[snafu(display("{} has no channels that can be borrowed", subscription))]
+    NoBorrowedChannels { subscription: String },
+    #[snafu(display("Unknown subscription {}", name))]
+    UnknownSubscription { name: String },
+    #[snafu(display("Cannot record to {}: {}", path, source))]
+    Record { path: String, source: std::io::Error },
+    #[snafu(display("Cannot replay {}: {}", path, source))]
+    Replay { path: String, source: std::io::Error },
+    #[snafu(display("Mock exchange failed: {}", source))]
+    MockServer { source: std::io::Error },
+    #[snafu(display("Invalid url {}: {}", url, source))]
+    InvalidUrl { url: String, source: url::ParseError },
+    #[snafu(display("Invalid handshake header {}", name))]
+    InvalidHeader { name: String },
+    #[snafu(display("Cannot connect to {}: {}", url, source))]
+    Connect {
+        url: String,
+        #[snafu(source(from(tungstenite::Error, Box::new)))]
+        source: Box<tungstenite::Error>,
+    },
+    #[snafu(display("No connection for {}", subscription))]
+    NotConnected { subscription: String },
+    #[snafu(display("Websocket error on {}: {}", subscription, source))]
+    Socket {
+        subscription: String,
+        #[snafu(source(from(tungstenite::Error, Box::new)))]
+        source: Box<tungstenite::Error>,
+    },
+    /// The login or auth request was refused, with the code and message of the exchange. The
+    /// receive loop delivers it as `ConnectionState::LoginRejected`.
+    #[snafu(display("{} rejected the login: {}: {}", subscription, code, msg))]
+    AuthRejected { subscription: String, code: String, msg: String },
+    /// A trading request was refused, with the code and message of the exchange.
+    #[snafu(display("{} rejected the request: {}: {}", subscription, code, msg))]
+    RequestRejected { subscription: String, code: String, msg: String },
+    #[snafu(display("{} does not take requests", subscription))]
+    NoRequests { subscription: String },
+    #[snafu(display("Cannot decode {}: {}", raw, source))]
+    Decode { raw: String, source: serde_json::Error },
+    #[snafu(display("Cannot decompress a message: {}", source))]
+    Gzip { source: std::io::Error },
+    #[snafu(display("Request failed: {}", source))]
+    Http { source: reqwest::Error },
+    /// Returned by the event handler, ends `Websocket::connect`.
+    #[snafu(display("Handler failed: {}", source))]
+    Handler { source: Box<dyn std::error::Error + Send + Sync> },
+}
+
+impl Error {
+    /// Wraps an error of the event handler.
+    pub fn handler<E>(error: E) -> Self
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        Self::Handler { source: error.into() }
+    }
 }
@@ -195,7 +195,7 @@ pub enum OrderbookAction {
 /// Represents the current state of the orderbook, guaranteed to be accurate
 /// up to the best 100 bids and best 100 asks since the latest update.
 /// Supports efficient insertions, updates, and deletions via a BTreeMap.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Orderbook {
     pub symbol: Symbol,
     pub bids: BTreeMap<Decimal, Decimal>,
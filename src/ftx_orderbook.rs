@@ -0,0 +1,173 @@
+//! Keeps a `ftx_model::Orderbook` per market of the FTX `orderbook` channel.
+//!
+//! Every `OrderbookData` carries a checksum of the top 100 levels. The book is verified after
+//! each one, on a mismatch it is dropped and the channel of that market is subscribed again,
+//! which starts over with a partial.
+use crate::{
+    ftx_model::{Orderbook, OrderbookAction, OrderbookData, Symbol},
+    models::{ConnectionId, WebsocketEvent},
+    websocket::Websocket,
+};
+use crate::error::Result;
+use serde_json::json;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Outcome of applying an `OrderbookData` to `OrderBooks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookSync {
+    /// The book changed and matches the checksum.
+    Updated,
+    /// An update for a market without partial, dropped.
+    Ignored,
+    /// The book does not match `checksum` and was dropped.
+    ChecksumMismatch { checksum: u32 },
+}
+
+/// Why a book was invalidated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResyncReason {
+    /// The book did not match the checksum of an update, the market is subscribed again.
+    ChecksumMismatch { checksum: u32 },
+    /// The connection of the market stream was lost.
+    Disconnected,
+}
+
+/// The book of `market` was invalidated, the next `FtxLocalOrderBook` of that market starts
+/// from a new partial.
+#[derive(Debug, Clone)]
+pub struct ResyncEvent {
+    pub market: Symbol,
+    pub reason: ResyncReason,
+}
+
+/// The books of one connection, by market.
+#[derive(Debug, Default)]
+pub struct OrderBooks {
+    books: HashMap<Symbol, Orderbook>,
+}
+
+impl OrderBooks {
+    #[must_use]
+    pub fn book(&self, market: &str) -> Option<&Orderbook> {
+        self.books.get(market)
+    }
+
+    /// Drops every book, returning their markets.
+    pub fn clear(&mut self) -> Vec<Symbol> {
+        self.books.drain().map(|(market, _)| market).collect()
+    }
+
+    pub fn apply(&mut self, market: &str, data: &OrderbookData) -> BookSync {
+        let book = match data.action {
+            OrderbookAction::Partial => {
+                // a partial replaces the book, `Orderbook::update` only adds its levels.
+                let book = self.books.entry(market.to_string())
+                    .or_insert_with(|| Orderbook::new(market.to_string()));
+                book.bids.clear();
+                book.asks.clear();
+                book
+            }
+            OrderbookAction::Update => match self.books.get_mut(market) {
+                Some(book) => book,
+                None => return BookSync::Ignored,
+            },
+        };
+
+        book.update(data);
+
+        if !book.verify_checksum(data.checksum) {
+            self.books.remove(market);
+            return BookSync::ChecksumMismatch { checksum: data.checksum };
+        }
+        BookSync::Updated
+    }
+}
+
+impl Websocket {
+    pub(crate) async fn on_ftx_book(&mut self, connection: &ConnectionId, market: &str, data: &OrderbookData) -> Result<()> {
+        let Some(books) = self.ftx_books.get_mut(connection) else {
+            return Ok(());
+        };
+
+        match books.apply(market, data) {
+            BookSync::Updated => {
+                if let Some(book) = books.book(market).cloned() {
+                    self.emit(connection, WebsocketEvent::FtxLocalOrderBook(book));
+                }
+            }
+            BookSync::ChecksumMismatch { checksum } => {
+                warn!("[Websocket] ftx book {} does not match checksum {}", market, checksum);
+                self.emit(connection, WebsocketEvent::FtxOrderBookResync(ResyncEvent {
+                    market: market.to_string(),
+                    reason: ResyncReason::ChecksumMismatch { checksum },
+                }));
+                self.ftx_resubscribe(connection, market).await?;
+            }
+            BookSync::Ignored => (),
+        }
+
+        Ok(())
+    }
+
+    /// Drops the books of `connection` after its connection was lost, the new connection
+    /// starts them over from a partial.
+    pub(crate) fn reset_ftx_books(&mut self, connection: &ConnectionId) {
+        let Some(books) = self.ftx_books.get_mut(connection) else {
+            return;
+        };
+
+        for market in books.clear() {
+            self.emit(connection, WebsocketEvent::FtxOrderBookResync(ResyncEvent {
+                market,
+                reason: ResyncReason::Disconnected,
+            }));
+        }
+    }
+
+    /// Unsubscribes and subscribes the orderbook channel of `market`, FTX answers with a
+    /// new partial.
+    async fn ftx_resubscribe(&mut self, connection: &ConnectionId, market: &str) -> Result<()> {
+        for op in ["unsubscribe", "subscribe"] {
+            let message = json!({
+                "op": op,
+                "channel": "orderbook",
+                "market": market,
+            });
+            self.send(connection, message.to_string()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // checksums of `5000.5:1.0:5001.0:0.1:5000.0:2.5:5002.5:3.0` and, without the bid at
+    // 5000, `5000.5:1.0:5001.0:0.1:5002.5:3.0`.
+    const PARTIAL: &str = r#"{"time":1672531200.12,"checksum":1391516373,"bids":[[5000.5,1.0],[5000.0,2.5]],"asks":[[5001.0,0.1],[5002.5,3.0]],"action":"partial"}"#;
+    const UPDATE: &str = r#"{"time":1672531200.22,"checksum":240322048,"bids":[[5000.0,0.0]],"asks":[],"action":"update"}"#;
+    const CORRUPTED_UPDATE: &str = r#"{"time":1672531200.22,"checksum":240322049,"bids":[[5000.0,0.0]],"asks":[],"action":"update"}"#;
+
+    fn data(raw: &str) -> OrderbookData {
+        serde_json::from_str(raw).unwrap()
+    }
+
+    #[test]
+    fn known_good_frames_verify() {
+        let mut books = OrderBooks::default();
+        assert_eq!(books.apply("BTC-PERP", &data(PARTIAL)), BookSync::Updated);
+        assert_eq!(books.apply("BTC-PERP", &data(UPDATE)), BookSync::Updated);
+        assert_eq!(books.book("BTC-PERP").unwrap().bids.len(), 1);
+    }
+
+    #[test]
+    fn corrupted_frame_drops_the_book() {
+        let mut books = OrderBooks::default();
+        books.apply("BTC-PERP", &data(PARTIAL));
+        assert_eq!(books.apply("BTC-PERP", &data(CORRUPTED_UPDATE)), BookSync::ChecksumMismatch { checksum: 240_322_049 });
+        assert!(books.book("BTC-PERP").is_none());
+        assert_eq!(books.apply("BTC-PERP", &data(UPDATE)), BookSync::Ignored);
+    }
+}
@@ -0,0 +1,196 @@
+//! Keeping connections alive and noticing when they die.
+//!
+//! Every subscription has a `HeartbeatPolicy`: what the client sends to keep the connection
+//! open and how often, and how long it may stay silent. Any frame received counts, data or
+//! the answer to a ping. A connection silent for longer than `idle_timeout` is dropped and
+//! reconnected like one the exchange closed, with `ConnectionState::Disconnected`.
+//!
+//! The defaults follow the exchanges:
+//!
+//! * Binance: a websocket ping frame every 30 seconds. Binance pings too, its ping frames
+//!   are answered with pong frames.
+//! * Huobi: nothing, Huobi pings every 5 seconds and the pings are answered with `pong`.
+//! * OKX: the text `ping` every 20 seconds, answered with `pong`. OKX drops connections
+//!   silent for 30 seconds.
+//! * FTX: `{"op":"ping"}` every 15 seconds, answered with `{"type":"pong"}`.
+//!
+//! Change them with `Websocket::set_heartbeat_policy`.
+use crate::{
+    models::{ConnectionId, Subscription},
+    recorder::{Direction, FrameKind},
+    websocket::Websocket,
+};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use tungstenite::Message;
+
+/// How often the connections are checked.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the client sends to keep a connection alive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ping {
+    /// A websocket ping frame, answered with a pong frame.
+    Frame,
+    /// A text message, and the text of its answer.
+    Text { ping: String, pong: String },
+}
+
+/// The heartbeat of one subscription, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeartbeatPolicy {
+    /// `None` when the exchange pings the client instead.
+    pub ping: Option<Ping>,
+    /// How often `ping` is sent.
+    pub interval: Duration,
+    /// The connection is dead once nothing was received for this long.
+    pub idle_timeout: Duration,
+}
+
+impl HeartbeatPolicy {
+    /// The heartbeat the exchange of `subscription` expects.
+    #[must_use]
+    pub fn of(subscription: &Subscription) -> Self {
+        let text = |ping: &str, pong: &str| Some(Ping::Text { ping: ping.to_string(), pong: pong.to_string() });
+        match subscription {
+            Subscription::BinanceSpotMStream
+            | Subscription::BinanceSpotOrder
+            | Subscription::BinanceSpotApi
+            | Subscription::BinanceUSwapMStream
+            | Subscription::BinanceUSwapOrder
+            | Subscription::BinanceUSwapApi
+            | Subscription::BinanceBLVTStream => Self {
+                ping: Some(Ping::Frame),
+                interval: Duration::from_secs(30),
+                idle_timeout: Duration::from_secs(90),
+            },
+            Subscription::HuobiUSwapMarketStream | Subscription::HuobiUSwapOrderStream => Self {
+                ping: None,
+                interval: Duration::from_secs(5),
+                idle_timeout: Duration::from_secs(30),
+            },
+            Subscription::OkexMarketStream | Subscription::OkexOrderStream => Self {
+                ping: text("ping", "pong"),
+                interval: Duration::from_secs(20),
+                idle_timeout: Duration::from_secs(40),
+            },
+            Subscription::FtxMarketStream | Subscription::FtxOrderStream => Self {
+                ping: text(r#"{"op":"ping"}"#, r#"{"type":"pong"}"#),
+                interval: Duration::from_secs(15),
+                idle_timeout: Duration::from_secs(45),
+            },
+        }
+    }
+
+    /// Returns `true` if `message` is the answer to `ping`: the same text, or the same JSON
+    /// spaced differently, like the `{"type": "pong"}` of FTX.
+    #[must_use]
+    pub fn is_pong(&self, message: &str) -> bool {
+        let Some(Ping::Text { pong, .. }) = &self.ping else {
+            return false;
+        };
+        // only messages about as short as the pong are parsed, not the data.
+        pong == message || (message.len() <= 2 * pong.len() && same_json(pong, message))
+    }
+}
+
+fn same_json(a: &str, b: &str) -> bool {
+    match (serde_json::from_str::<serde_json::Value>(a), serde_json::from_str::<serde_json::Value>(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// When a connection last received a frame and last sent a ping.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Heartbeat {
+    pub(crate) received: Instant,
+    pub(crate) pinged: Instant,
+}
+
+impl Heartbeat {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        Self { received: now, pinged: now }
+    }
+}
+
+impl Websocket {
+    /// Replaces the heartbeat of `subscription`, from the next check on.
+    pub fn set_heartbeat_policy(&mut self, subscription: Subscription, policy: HeartbeatPolicy) {
+        self.heartbeat_policies.insert(subscription, policy);
+    }
+
+    /// The heartbeat of `subscription`, the exchange's unless it was replaced.
+    #[must_use]
+    pub fn heartbeat_policy(&self, subscription: &Subscription) -> HeartbeatPolicy {
+        self.heartbeat_policies.get(subscription).cloned().unwrap_or_else(|| HeartbeatPolicy::of(subscription))
+    }
+
+    /// Notes that `connection` received a frame.
+    pub(crate) fn on_frame_received(&mut self, connection: &ConnectionId) {
+        if let Some(heartbeat) = self.heartbeats.get_mut(connection) {
+            heartbeat.received = Instant::now();
+        }
+    }
+
+    /// Drops the connections silent for longer than their idle timeout and pings the others
+    /// when their interval has passed.
+    pub(crate) async fn check_heartbeats(&mut self) {
+        let now = Instant::now();
+        let connections: Vec<(ConnectionId, Heartbeat)> = self.heartbeats.iter()
+            .map(|(connection, heartbeat)| (connection.clone(), *heartbeat))
+            .collect();
+
+        for (connection, heartbeat) in connections {
+            let policy = self.heartbeat_policy(&connection.subscription);
+            let silent = now.saturating_duration_since(heartbeat.received);
+            if silent > policy.idle_timeout {
+                warn!("[Websocket] {:?} silent for {:?}", connection, silent);
+                self.connection_lost(&connection, format!("nothing received for {silent:?}"));
+                continue;
+            }
+
+            let Some(ping) = policy.ping else {
+                continue;
+            };
+            if now.saturating_duration_since(heartbeat.pinged) < policy.interval {
+                continue;
+            }
+            if let Some(heartbeat) = self.heartbeats.get_mut(&connection) {
+                heartbeat.pinged = now;
+            }
+            let sent = match ping {
+                Ping::Frame => self.send_frame(&connection, FrameKind::Ping, Message::Ping(Vec::new())).await,
+                Ping::Text { ping, .. } => self.send(&connection, ping).await,
+            };
+            if let Err(e) = sent {
+                self.connection_lost(&connection, e.to_string());
+            }
+        }
+    }
+
+    /// Records a ping frame of the exchange. tungstenite answers it with a pong frame
+    /// carrying the same payload.
+    pub(crate) fn on_ping(&self, connection: &ConnectionId, payload: &[u8]) {
+        self.record(connection, Direction::Inbound, FrameKind::Ping, &String::from_utf8_lossy(payload));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pongs_are_matched_as_json() {
+        let ftx = HeartbeatPolicy::of(&Subscription::FtxMarketStream);
+        assert!(ftx.is_pong(r#"{"type":"pong"}"#));
+        assert!(ftx.is_pong(r#"{"type": "pong"}"#));
+        assert!(!ftx.is_pong(r#"{"type": "subscribed"}"#));
+
+        let okex = HeartbeatPolicy::of(&Subscription::OkexMarketStream);
+        assert!(okex.is_pong("pong"));
+        assert!(!okex.is_pong(r#""pong""#));
+        assert!(!HeartbeatPolicy::of(&Subscription::HuobiUSwapMarketStream).is_pong("pong"));
+    }
+}
@@ -0,0 +1,446 @@
+//! Local order books built from the Huobi USDT swap incremental depth topics
+//! (`market.$contract_code.depth.size_${size}.high_freq`).
+//!
+//! The first push of a topic is a snapshot, every following one an update whose `version` is
+//! one more than the previous. When a version is skipped the updates are buffered and the
+//! snapshot is requested again with a `req` on the same connection, the buffered updates are
+//! then replayed on top of it. A refused `req` is sent again after `SNAPSHOT_RETRY_DELAY`.
+use crate::{
+    huobi_uswap_model::{DepthSnapshot, IncrementalOrderBook, WSMarketResponse, WSReqResponse},
+    models::{ConnectionId, WebsocketEvent},
+    websocket::Websocket,
+};
+use crate::error::Result;
+use futures::prelude::*;
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    pin::Pin,
+    time::Duration,
+};
+use tracing::warn;
+
+/// Wait before asking again for a snapshot that was refused.
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A snapshot `req` waiting to be sent again, with the connection and topic it is for.
+pub(crate) type PendingSnapshotRetry = Pin<Box<dyn Future<Output = (ConnectionId, String)> + Send>>;
+
+/// `(price, size)` levels, best first.
+pub type Levels = Vec<(Decimal, Decimal)>;
+
+#[derive(Debug, Clone)]
+pub struct LocalOrderBook {
+    /// Topic of the book, e.g. `market.BTC-USDT.depth.size_20.high_freq`.
+    pub ch: String,
+    pub contract_code: String,
+    pub version: u64,
+    pub ts: u64,
+    pub bids: BTreeMap<Decimal, Decimal>,
+    pub asks: BTreeMap<Decimal, Decimal>,
+}
+
+impl LocalOrderBook {
+    #[must_use]
+    pub fn new(ch: &str) -> Self {
+        Self {
+            ch: ch.to_string(),
+            contract_code: ch.split('.').nth(1).unwrap_or_default().to_string(),
+            version: 0,
+            ts: 0,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        }
+    }
+
+    /// Replaces the content of the book with a snapshot.
+    pub fn reset(&mut self, bids: &[(f64, f64)], asks: &[(f64, f64)], version: u64, ts: u64) {
+        self.bids.clear();
+        self.asks.clear();
+        self.apply(bids, asks, version, ts);
+    }
+
+    /// Applies the levels of an update, a size of 0 removes the level.
+    pub fn apply(&mut self, bids: &[(f64, f64)], asks: &[(f64, f64)], version: u64, ts: u64) {
+        for (price, size) in levels(bids) {
+            if size == dec!(0) {
+                self.bids.remove(&price);
+            } else {
+                self.bids.insert(price, size);
+            }
+        }
+        for (price, size) in levels(asks) {
+            if size == dec!(0) {
+                self.asks.remove(&price);
+            } else {
+                self.asks.insert(price, size);
+            }
+        }
+        self.version = version;
+        self.ts = ts;
+    }
+
+    /// Returns the best `levels` bids and asks, best first.
+    #[must_use]
+    pub fn depth(&self, levels: usize) -> (Levels, Levels) {
+        let bids = self.bids.iter().rev().take(levels).map(|(price, size)| (*price, *size)).collect();
+        let asks = self.asks.iter().take(levels).map(|(price, size)| (*price, *size)).collect();
+        (bids, asks)
+    }
+
+    /// Returns the price of the best bid
+    #[must_use]
+    pub fn bid_price(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().copied()
+    }
+
+    /// Returns the price of the best ask
+    #[must_use]
+    pub fn ask_price(&self) -> Option<Decimal> {
+        self.asks.keys().next().copied()
+    }
+
+    /// Returns the midpoint between the best bid price and best ask price.
+    /// Output is not rounded to the smallest price increment.
+    #[must_use]
+    pub fn mid_price(&self) -> Option<Decimal> {
+        Some((self.bid_price()? + self.ask_price()?) / dec!(2))
+    }
+
+    /// Returns the price and quantity of the best bid
+    /// `(bid_price, bid_quantity)`
+    #[must_use]
+    pub fn best_bid(&self) -> Option<(Decimal, Decimal)> {
+        let (price, quantity) = self.bids.iter().next_back()?;
+
+        Some((*price, *quantity))
+    }
+
+    /// Returns the price and quantity of the best ask
+    /// `(ask_price, ask_quantity)`
+    #[must_use]
+    pub fn best_ask(&self) -> Option<(Decimal, Decimal)> {
+        let (price, quantity) = self.asks.iter().next()?;
+
+        Some((*price, *quantity))
+    }
+}
+
+fn levels(levels: &[(f64, f64)]) -> impl Iterator<Item = (Decimal, Decimal)> + '_ {
+    levels
+        .iter()
+        .filter_map(|(price, size)| Some((Decimal::from_f64(*price)?, Decimal::from_f64(*size)?)))
+}
+
+/// Outcome of feeding a push or a snapshot to `OrderBooks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookSync {
+    /// The book changed.
+    Updated,
+    /// The update was buffered until the requested snapshot arrives.
+    Buffered,
+    /// The update is not newer than the book, or the snapshot was not requested. Dropped.
+    Stale,
+    /// A version was skipped. The book was dropped and its snapshot has to be requested,
+    /// the update is kept to be replayed on it.
+    Gap { expected: u64, received: u64 },
+}
+
+/// Why a local book was dropped and is being synchronized again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResyncReason {
+    Gap { expected: u64, received: u64 },
+    /// The snapshot `req` was answered with an error, it is requested again.
+    SnapshotFailed(String),
+    /// The connection of the market stream was lost.
+    Disconnected,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResyncEvent {
+    pub ch: String,
+    pub reason: ResyncReason,
+}
+
+#[derive(Debug)]
+enum BookState {
+    Live(LocalOrderBook),
+    /// Updates received while the snapshot is requested, in order.
+    Buffering(Vec<IncrementalOrderBook>),
+}
+
+/// The local books of one connection, by topic.
+#[derive(Debug, Default)]
+pub struct OrderBooks {
+    books: HashMap<String, BookState>,
+}
+
+impl OrderBooks {
+    #[must_use]
+    pub fn book(&self, ch: &str) -> Option<&LocalOrderBook> {
+        match self.books.get(ch) {
+            Some(BookState::Live(book)) => Some(book),
+            _ => None,
+        }
+    }
+
+    /// Drops every book, returning their topics.
+    pub fn clear(&mut self) -> Vec<String> {
+        self.books.drain().map(|(ch, _)| ch).collect()
+    }
+
+    /// Returns `true` if the book of `ch` waits for a requested snapshot.
+    #[must_use]
+    pub fn is_buffering(&self, ch: &str) -> bool {
+        matches!(self.books.get(ch), Some(BookState::Buffering(_)))
+    }
+
+    /// Applies a pushed snapshot or update to the book of `ch`. Only buffered updates are
+    /// copied.
+    pub fn update(&mut self, ch: &str, tick: &IncrementalOrderBook) -> BookSync {
+        if tick.event == "snapshot" {
+            let mut book = LocalOrderBook::new(ch);
+            book.reset(&tick.bids, &tick.asks, tick.version, tick.ts);
+            self.books.insert(ch.to_string(), BookState::Live(book));
+            return BookSync::Updated;
+        }
+
+        let book = match self.books.get_mut(ch) {
+            Some(BookState::Live(book)) => book,
+            Some(BookState::Buffering(buffer)) => {
+                buffer.push(tick.clone());
+                return BookSync::Buffered;
+            }
+            None => {
+                // an update without snapshot, e.g. the topic was subscribed before the book
+                // was enabled.
+                let received = tick.version;
+                self.books.insert(ch.to_string(), BookState::Buffering(vec![tick.clone()]));
+                return BookSync::Gap { expected: 0, received };
+            }
+        };
+
+        if tick.version <= book.version {
+            return BookSync::Stale;
+        }
+        if tick.version != book.version + 1 {
+            let expected = book.version + 1;
+            let received = tick.version;
+            self.books.insert(ch.to_string(), BookState::Buffering(vec![tick.clone()]));
+            return BookSync::Gap { expected, received };
+        }
+
+        book.apply(&tick.bids, &tick.asks, tick.version, tick.ts);
+        BookSync::Updated
+    }
+
+    /// Starts the book of `ch` over from a requested snapshot and replays the buffered
+    /// updates on it.
+    pub fn snapshot(&mut self, ch: &str, snapshot: &DepthSnapshot) -> BookSync {
+        let buffer = match self.books.remove(ch) {
+            Some(BookState::Buffering(buffer)) => buffer,
+            Some(live) => {
+                self.books.insert(ch.to_string(), live);
+                return BookSync::Stale;
+            }
+            None => return BookSync::Stale,
+        };
+
+        let mut book = LocalOrderBook::new(ch);
+        book.reset(&snapshot.bids, &snapshot.asks, snapshot.version, snapshot.ts);
+        self.books.insert(ch.to_string(), BookState::Live(book));
+
+        let mut outcome = BookSync::Updated;
+        for tick in &buffer {
+            match self.update(ch, tick) {
+                BookSync::Gap { expected, received } => outcome = BookSync::Gap { expected, received },
+                BookSync::Updated | BookSync::Buffered | BookSync::Stale => (),
+            }
+        }
+        outcome
+    }
+}
+
+impl Websocket {
+    /// Emits `msg`, then applies it to the local book of its topic if there is one.
+    pub(crate) async fn on_huobi_book(&mut self, connection: &ConnectionId, msg: WSMarketResponse<IncrementalOrderBook>) -> Result<()> {
+        let Some(books) = self.huobi_books.get_mut(connection) else {
+            self.emit(connection, WebsocketEvent::HuobiUSwapIncrementalOrderBook(msg));
+            return Ok(());
+        };
+
+        let outcome = books.update(&msg.ch, &msg.tick);
+        let ch = msg.ch.clone();
+        self.emit(connection, WebsocketEvent::HuobiUSwapIncrementalOrderBook(msg));
+        self.on_huobi_book_sync(connection, &ch, outcome).await
+    }
+
+    pub(crate) async fn on_huobi_depth_snapshot(&mut self, connection: &ConnectionId, msg: &WSReqResponse<DepthSnapshot>) -> Result<()> {
+        let Some(books) = self.huobi_books.get_mut(connection) else {
+            return Ok(());
+        };
+
+        let outcome = match msg.data {
+            Some(ref snapshot) if msg.status == "ok" => books.snapshot(&msg.rep, snapshot),
+            _ => {
+                let reason = msg.err_msg.clone().unwrap_or_else(|| msg.status.clone());
+                warn!("[Websocket] huobi depth snapshot of {} failed: {}", msg.rep, reason);
+                self.emit(connection, WebsocketEvent::HuobiUSwapOrderBookResync(ResyncEvent {
+                    ch: msg.rep.clone(),
+                    reason: ResyncReason::SnapshotFailed(reason),
+                }));
+                let (connection, ch) = (connection.clone(), msg.rep.clone());
+                self.huobi_snapshot_retries.push(Box::pin(async move {
+                    tokio::time::sleep(SNAPSHOT_RETRY_DELAY).await;
+                    (connection, ch)
+                }));
+                return Ok(());
+            }
+        };
+
+        self.on_huobi_book_sync(connection, &msg.rep, outcome).await
+    }
+
+    /// Drops the books of `connection` after its connection was lost, the new connection
+    /// starts them over from the snapshot pushed after subscribing.
+    pub(crate) fn reset_huobi_books(&mut self, connection: &ConnectionId) {
+        let Some(books) = self.huobi_books.get_mut(connection) else {
+            return;
+        };
+
+        for ch in books.clear() {
+            self.emit(connection, WebsocketEvent::HuobiUSwapOrderBookResync(ResyncEvent {
+                ch,
+                reason: ResyncReason::Disconnected,
+            }));
+        }
+    }
+
+    async fn on_huobi_book_sync(&mut self, connection: &ConnectionId, ch: &str, outcome: BookSync) -> Result<()> {
+        match outcome {
+            BookSync::Updated => {
+                let book = self.huobi_books.get(connection).and_then(|books| books.book(ch)).cloned();
+                if let Some(book) = book {
+                    self.emit(connection, WebsocketEvent::HuobiUSwapLocalOrderBook(book));
+                }
+            }
+            BookSync::Gap { expected, received } => {
+                warn!("[Websocket] huobi book {} skipped a version: expected {}, received {}", ch, expected, received);
+                self.emit(connection, WebsocketEvent::HuobiUSwapOrderBookResync(ResyncEvent {
+                    ch: ch.to_string(),
+                    reason: ResyncReason::Gap { expected, received },
+                }));
+                self.huobi_request_snapshot(connection, ch).await?;
+            }
+            BookSync::Buffered | BookSync::Stale => (),
+        }
+        Ok(())
+    }
+
+    /// Sends a snapshot `req` again once its delay passed, unless the book was dropped since.
+    pub(crate) async fn on_huobi_snapshot_retry(&mut self, connection: &ConnectionId, ch: &str) {
+        if !self.huobi_books.get(connection).is_some_and(|books| books.is_buffering(ch)) {
+            return;
+        }
+        if let Err(e) = self.huobi_request_snapshot(connection, ch).await {
+            self.connection_lost(connection, e.to_string());
+        }
+    }
+
+    async fn huobi_request_snapshot(&mut self, connection: &ConnectionId, ch: &str) -> Result<()> {
+        let message = json!({
+            "req": ch,
+            "data_type": "snapshot",
+            "id": "huobiusdtswap_rust",
+        });
+        self.send(connection, message.to_string()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CH: &str = "market.BTC-USDT.depth.size_20.high_freq";
+
+    /// A push of `version` setting the bid at 100 to `version`.
+    fn tick(event: &str, version: u64) -> IncrementalOrderBook {
+        #[allow(clippy::cast_precision_loss)]
+        let size = version as f64;
+        IncrementalOrderBook {
+            bids: vec![(100.0, size)],
+            asks: Vec::new(),
+            mrid: version,
+            id: version,
+            ts: version,
+            version,
+            ch: CH.to_string(),
+            event: event.to_string(),
+        }
+    }
+
+    fn snapshot(version: u64) -> DepthSnapshot {
+        DepthSnapshot { bids: vec![(100.0, 0.5), (99.0, 1.0)], asks: vec![(101.0, 1.0)], version, ts: version }
+    }
+
+    fn bid_at_100(books: &OrderBooks) -> Option<Decimal> {
+        books.book(CH)?.bids.get(&dec!(100)).copied()
+    }
+
+    #[test]
+    fn versions_in_order_are_applied() {
+        let mut books = OrderBooks::default();
+        assert_eq!(books.update(CH, &tick("snapshot", 10)), BookSync::Updated);
+        assert_eq!(books.update(CH, &tick("update", 11)), BookSync::Updated);
+        assert_eq!(books.update(CH, &tick("update", 12)), BookSync::Updated);
+        assert_eq!(books.book(CH).unwrap().version, 12);
+        assert_eq!(bid_at_100(&books), Some(dec!(12)));
+
+        assert_eq!(books.update(CH, &tick("update", 12)), BookSync::Stale);
+    }
+
+    #[test]
+    fn skipped_version_buffers_until_the_snapshot() {
+        let mut books = OrderBooks::default();
+        books.update(CH, &tick("snapshot", 10));
+        assert_eq!(books.update(CH, &tick("update", 12)), BookSync::Gap { expected: 11, received: 12 });
+        assert!(books.book(CH).is_none());
+        assert!(books.is_buffering(CH));
+        assert_eq!(books.update(CH, &tick("update", 13)), BookSync::Buffered);
+    }
+
+    #[test]
+    fn snapshot_replays_the_buffer_skipping_stale_updates() {
+        let mut books = OrderBooks::default();
+        books.update(CH, &tick("snapshot", 10));
+        books.update(CH, &tick("update", 12));
+        books.update(CH, &tick("update", 13));
+        books.update(CH, &tick("update", 14));
+
+        // 12 and 13 are older than the snapshot, 14 follows it.
+        assert_eq!(books.snapshot(CH, &snapshot(13)), BookSync::Updated);
+        let book = books.book(CH).unwrap();
+        assert_eq!(book.version, 14);
+        assert_eq!(bid_at_100(&books), Some(dec!(14)));
+        assert_eq!(book.bids.get(&dec!(99)), Some(&dec!(1)));
+    }
+
+    #[test]
+    fn snapshot_behind_the_buffer_is_a_gap() {
+        let mut books = OrderBooks::default();
+        books.update(CH, &tick("snapshot", 10));
+        books.update(CH, &tick("update", 15));
+        assert_eq!(books.snapshot(CH, &snapshot(12)), BookSync::Gap { expected: 13, received: 15 });
+        assert!(books.is_buffering(CH));
+    }
+
+    #[test]
+    fn unrequested_snapshot_is_stale() {
+        let mut books = OrderBooks::default();
+        books.update(CH, &tick("snapshot", 10));
+        assert_eq!(books.snapshot(CH, &snapshot(20)), BookSync::Stale);
+        assert_eq!(books.book(CH).unwrap().version, 10);
+    }
+}
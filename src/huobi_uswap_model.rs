@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 pub struct SubStatus {
     pub id: String,
     pub subbed: Option<String>,
+    pub unsubbed: Option<String>,
     pub ts: u64,
     pub status: String,
     #[serde(rename = "err-code")]
@@ -57,6 +58,30 @@ pub struct WSAccountResponse<T> {
     pub data: T,
 }
 
+/// Answer to a `req` message, `data` is missing when `status` is not "ok".
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WSReqResponse<T> {
+    pub rep: String,
+    pub status: String,
+    pub id: Option<String>,
+    #[serde(alias = "tick")]
+    pub data: Option<T>,
+    #[serde(rename = "err-code")]
+    pub err_code: Option<String>,
+    #[serde(rename = "err-msg")]
+    pub err_msg: Option<String>,
+}
+
+/// Full depth answered to a `req` on a `depth.size_${size}.high_freq` topic.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DepthSnapshot {
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+    pub version: u64,
+    #[serde(default)]
+    pub ts: u64,
+}
+
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
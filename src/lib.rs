@@ -10,7 +10,32 @@ pub mod models;
 pub mod websocket;
 pub mod subscription;
 pub mod parser;
+pub mod reconnect;
+pub mod heartbeat;
+pub mod binance_orderbook;
+pub mod okex_orderbook;
+pub mod huobi_orderbook;
+pub mod ftx_orderbook;
+pub mod normalized;
+pub mod topic;
+pub mod control;
+pub mod endpoint;
+mod dispatch;
+pub mod borrowed;
+pub mod listen_key;
+pub mod rpc;
+pub mod binance_api;
+pub mod okex_trading;
+pub mod recorder;
+pub mod replay;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 
 pub use crate::models::*;
 pub use crate::error::*;
 pub use crate::{websocket::Websocket};
+pub use crate::reconnect::ReconnectPolicy;
+pub use crate::heartbeat::HeartbeatPolicy;
+pub use crate::topic::{Interval, Topic};
+pub use crate::control::ControlHandle;
+pub use crate::endpoint::Endpoint;
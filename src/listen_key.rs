@@ -0,0 +1,322 @@
+//! Listen keys of the Binance user data streams.
+//!
+//! `BinanceSpotOrder` and `BinanceUSwapOrder` stream the events of the account a listen key
+//! was created for. With `Websocket::manage_listen_key` the client creates the key when the
+//! connection first opens and keeps it alive every 30 minutes, retrying failed keepalives
+//! with the backoff of the `ReconnectPolicy`. On `listenKeyExpired`, or when Binance answers
+//! a keepalive that the key does not exist, it creates a new one and reconnects the stream
+//! with it.
+//!
+//! The REST requests go through a `BinanceRest` client, which takes its hosts and a
+//! `RestSigner` so that it can be pointed at a testnet or a local stand-in.
+use crate::{
+    error::{self, Error, Result},
+    models::{ConnectionId, ConnectionState, Subscription},
+    subscription::sign_hmac_sha256_hex,
+    topic::NativeTopic,
+    websocket::Websocket,
+};
+use futures::prelude::*;
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::{pin::Pin, sync::Arc, time::Duration};
+use tracing::{error, info, trace, warn};
+
+/// How often Binance wants a listen key to be kept alive, it expires after 60 minutes.
+// `Duration::from_mins` is newer than the toolchains this crate supports.
+#[allow(clippy::duration_suboptimal_units)]
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// The code Binance answers a keepalive of a key that does not exist with.
+const UNKNOWN_LISTEN_KEY: &str = "-1125";
+
+pub type ListenKeyFuture = Pin<Box<dyn Future<Output = Result<String>> + Send>>;
+
+/// A listen key request in flight, with the connection it is for.
+pub(crate) type PendingListenKey = Pin<Box<dyn Future<Output = (ConnectionId, ListenKeyRequest, Result<String>)> + Send>>;
+
+/// Signs the REST requests of an account.
+///
+/// `HmacSigner` signs with the API secret, implement this to keep the secret elsewhere.
+pub trait RestSigner: Send + Sync {
+    /// Sent as the `X-MBX-APIKEY` header.
+    fn api_key(&self) -> &str;
+
+    /// The `signature` parameter of `payload`, the query string or body being sent.
+    fn sign(&self, payload: &str) -> String;
+}
+
+/// Signs with HMAC-SHA256 of the API secret, hex encoded.
+#[derive(Clone)]
+pub struct HmacSigner {
+    api_key: String,
+    secret: String,
+}
+
+impl HmacSigner {
+    #[must_use]
+    pub fn new(api_key: &str, secret: &str) -> Self {
+        Self { api_key: api_key.to_string(), secret: secret.to_string() }
+    }
+}
+
+impl std::fmt::Debug for HmacSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HmacSigner").field("api_key", &self.api_key).finish_non_exhaustive()
+    }
+}
+
+impl RestSigner for HmacSigner {
+    fn api_key(&self) -> &str {
+        &self.api_key
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        sign_hmac_sha256_hex(&self.secret, payload)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListenKeyResponse {
+    listen_key: String,
+}
+
+/// `{"code":-1125,"msg":"This listenKey does not exist."}`.
+#[derive(Deserialize)]
+struct RestError {
+    code: i64,
+    msg: String,
+}
+
+/// Creates, keeps alive and closes listen keys over the REST API.
+#[derive(Clone)]
+pub struct BinanceRest {
+    client: reqwest::Client,
+    spot_url: String,
+    uswap_url: String,
+    signer: Arc<dyn RestSigner>,
+}
+
+impl BinanceRest {
+    #[must_use]
+    pub fn new(spot_url: &str, uswap_url: &str, signer: Arc<dyn RestSigner>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            spot_url: spot_url.trim_end_matches('/').to_string(),
+            uswap_url: uswap_url.trim_end_matches('/').to_string(),
+            signer,
+        }
+    }
+
+    #[must_use]
+    pub fn production(signer: Arc<dyn RestSigner>) -> Self {
+        Self::new("https://api.binance.com", "https://fapi.binance.com", signer)
+    }
+
+    /// The REST hosts of the spot and USDⓈ-M testnets, to go with `Endpoint::binance_testnet`.
+    #[must_use]
+    pub fn binance_testnet(signer: Arc<dyn RestSigner>) -> Self {
+        Self::new("https://testnet.binance.vision", "https://testnet.binancefuture.com", signer)
+    }
+
+    /// Creates a listen key for the user data stream of `subscription`. USDⓈ-M returns the
+    /// key that is already active, if any.
+    #[must_use]
+    pub fn create_listen_key(&self, subscription: &Subscription) -> ListenKeyFuture {
+        self.request(reqwest::Method::POST, subscription, None)
+    }
+
+    /// Extends the validity of `listen_key` by 60 minutes, resolves to the key. Binance
+    /// refusing it fails with `Error::RequestRejected`.
+    #[must_use]
+    pub fn keepalive_listen_key(&self, subscription: &Subscription, listen_key: &str) -> ListenKeyFuture {
+        self.request(reqwest::Method::PUT, subscription, Some(listen_key))
+    }
+
+    /// Closes `listen_key`, which ends its stream. Resolves to the key.
+    #[must_use]
+    pub fn close_listen_key(&self, subscription: &Subscription, listen_key: &str) -> ListenKeyFuture {
+        self.request(reqwest::Method::DELETE, subscription, Some(listen_key))
+    }
+
+    fn request(&self, method: reqwest::Method, subscription: &Subscription, listen_key: Option<&str>) -> ListenKeyFuture {
+        let url = match *subscription {
+            Subscription::BinanceSpotOrder => format!("{}/api/v3/userDataStream", self.spot_url),
+            Subscription::BinanceUSwapOrder => format!("{}/fapi/v1/listenKey", self.uswap_url),
+            _ => {
+                let subscription = format!("{subscription:?}");
+                return Box::pin(future::ready(Err(Error::NoListenKey { subscription })));
+            }
+        };
+        let mut request = self.client
+            .request(method, url)
+            .header("X-MBX-APIKEY", self.signer.api_key());
+        if let Some(listen_key) = listen_key {
+            request = request.query(&[("listenKey", listen_key)]);
+        }
+        let listen_key = listen_key.map(ToString::to_string);
+        let subscription = format!("{subscription:?}");
+
+        Box::pin(async move {
+            let response = request.send().await.context(error::HttpSnafu)?;
+            let status = response.status();
+            let body = response.text().await.context(error::HttpSnafu)?;
+            if !status.is_success() {
+                return Err(match serde_json::from_str::<RestError>(&body) {
+                    Ok(rejected) => Error::RequestRejected { subscription, code: rejected.code.to_string(), msg: rejected.msg },
+                    Err(_) => Error::RequestRejected { subscription, code: status.as_u16().to_string(), msg: body },
+                });
+            }
+            // keepalive and close answer `{}`.
+            listen_key.map_or_else(|| crate::websocket::decode::<ListenKeyResponse>(&body).map(|rsp| rsp.listen_key), Ok)
+        })
+    }
+}
+
+impl std::fmt::Debug for BinanceRest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BinanceRest")
+            .field("spot_url", &self.spot_url)
+            .field("uswap_url", &self.uswap_url)
+            .field("api_key", &self.signer.api_key())
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ListenKeyRequest {
+    /// `attempt` counts from 1 like reconnects.
+    Keepalive { attempt: u32 },
+    /// Creation of a new key, `attempt` counts from 1 like reconnects.
+    Rotate { attempt: u32 },
+}
+
+/// The listen key of a managed connection.
+#[derive(Debug, Clone)]
+pub(crate) struct ManagedListenKey {
+    rest: BinanceRest,
+    key: Option<String>,
+}
+
+impl Websocket {
+    /// Lets the client manage the listen key of `connection`, a `BinanceSpotOrder` or
+    /// `BinanceUSwapOrder` stream: a key is created with `rest` when the connection first
+    /// opens, replacing the topics it is opened with, kept alive every 30 minutes, and
+    /// replaced when Binance reports it expired or unknown.
+    pub fn manage_listen_key(&mut self, connection: impl Into<ConnectionId>, rest: BinanceRest) -> Result<()> {
+        let connection = connection.into();
+        match connection.subscription {
+            Subscription::BinanceSpotOrder | Subscription::BinanceUSwapOrder => {
+                self.listen_keys.insert(connection, ManagedListenKey { rest, key: None });
+                Ok(())
+            }
+            _ => Err(Error::NoListenKey { subscription: connection.to_string() }),
+        }
+    }
+
+    /// Replaces the keepalive interval of the listen keys, `KEEPALIVE_INTERVAL` by default.
+    pub fn set_listen_key_keepalive(&mut self, period: Duration) {
+        self.listen_key_timer = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    }
+
+    /// The current listen key of `connection`, if it is managed and was created.
+    #[must_use]
+    pub fn listen_key(&self, connection: &ConnectionId) -> Option<&str> {
+        self.listen_keys.get(connection)?.key.as_deref()
+    }
+
+    /// The topics to open `connection` with: its listen key if it is managed, created if it
+    /// has none yet, `topics` otherwise.
+    pub(crate) async fn listen_key_topics(&mut self, connection: &ConnectionId, topics: Vec<NativeTopic>) -> Result<Vec<NativeTopic>> {
+        let Some(managed) = self.listen_keys.get_mut(connection) else {
+            return Ok(topics);
+        };
+        if let Some(key) = &managed.key {
+            return Ok(vec![NativeTopic::Binance(key.clone())]);
+        }
+        let key = managed.rest.create_listen_key(&connection.subscription).await?;
+        managed.key = Some(key.clone());
+        Ok(vec![NativeTopic::Binance(key)])
+    }
+
+    /// Sends a keepalive for every managed listen key.
+    pub(crate) fn keep_listen_keys_alive(&self) {
+        for connection in self.listen_keys.keys() {
+            self.keepalive_listen_key(connection, 1);
+        }
+    }
+
+    /// Keeps the listen key of `connection` alive after the backoff delay of `attempt`. Does
+    /// nothing if the connection is not managed or has no key yet.
+    fn keepalive_listen_key(&self, connection: &ConnectionId, attempt: u32) {
+        let Some(managed) = self.listen_keys.get(connection) else {
+            return;
+        };
+        let Some(key) = &managed.key else {
+            return;
+        };
+        let delay = if attempt > 1 { self.reconnect_policy.delay(attempt - 1) } else { Duration::ZERO };
+        let request = managed.rest.keepalive_listen_key(&connection.subscription, key);
+        let connection = connection.clone();
+        self.listen_key_requests.push(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            (connection, ListenKeyRequest::Keepalive { attempt }, request.await)
+        }));
+    }
+
+    /// Creates a new listen key for `connection` after the backoff delay of `attempt`. Does
+    /// nothing if the connection is not managed.
+    pub(crate) fn rotate_listen_key(&self, connection: &ConnectionId, attempt: u32) {
+        let Some(managed) = self.listen_keys.get(connection) else {
+            return;
+        };
+        let delay = if attempt > 1 { self.reconnect_policy.delay(attempt - 1) } else { Duration::ZERO };
+        let request = managed.rest.create_listen_key(&connection.subscription);
+        let connection = connection.clone();
+        self.listen_key_requests.push(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            (connection, ListenKeyRequest::Rotate { attempt }, request.await)
+        }));
+    }
+
+    pub(crate) fn on_listen_key(&mut self, connection: &ConnectionId, request: ListenKeyRequest, result: Result<String>) {
+        match (request, result) {
+            (ListenKeyRequest::Keepalive { .. }, Ok(_)) => trace!("[Websocket] kept the listen key of {:?} alive", connection),
+            (ListenKeyRequest::Keepalive { .. }, Err(Error::RequestRejected { code, msg, .. })) if code == UNKNOWN_LISTEN_KEY => {
+                warn!("[Websocket] the listen key of {:?} is gone: {}", connection, msg);
+                self.rotate_listen_key(connection, 1);
+            }
+            (ListenKeyRequest::Keepalive { attempt }, Err(e)) => {
+                warn!("[Websocket] keepalive of the listen key of {:?} failed: {}", connection, e);
+                if self.reconnect_policy.exhausted(attempt + 1) {
+                    // the key outlives two keepalive periods, the next one tries again.
+                    error!("[Websocket] giving up on keeping the listen key of {:?} alive after {} attempts", connection, attempt);
+                } else {
+                    self.keepalive_listen_key(connection, attempt + 1);
+                }
+            }
+            (ListenKeyRequest::Rotate { .. }, Ok(key)) => {
+                let Some(managed) = self.listen_keys.get_mut(connection) else {
+                    return;
+                };
+                info!("[Websocket] new listen key for {:?}", connection);
+                managed.key = Some(key.clone());
+                self.topics.insert(connection.clone(), vec![NativeTopic::Binance(key)]);
+                // a reconnect already in flight picks the new key up when it opens its socket,
+                // or opens another one if it did so with the old key.
+                self.connection_lost(connection, "listen key replaced".to_string());
+            }
+            (ListenKeyRequest::Rotate { attempt }, Err(e)) => {
+                warn!("[Websocket] creating a listen key for {:?} failed: {}", connection, e);
+                if self.reconnect_policy.exhausted(attempt + 1) {
+                    error!("[Websocket] giving up on the listen key of {:?} after {} attempts", connection, attempt);
+                    self.drop_connection(connection);
+                    self.emit_connection_state(connection, ConnectionState::GaveUp { attempts: attempt });
+                } else {
+                    self.rotate_listen_key(connection, attempt + 1);
+                }
+            }
+        }
+    }
+}
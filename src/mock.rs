@@ -0,0 +1,464 @@
+//! A local stand-in for the exchanges, for integration tests.
+//!
+//! Built with the `mock` feature, which the tests of this crate enable.
+//!
+//! `MockExchange::start` listens on a free local port and speaks the protocol of one
+//! exchange, point a subscription at it with `Websocket::set_endpoint` and `endpoint`. It
+//! answers what the client sends the way the exchange does:
+//!
+//! * Binance: `SUBSCRIBE` and `UNSUBSCRIBE` with `{"result":null,"id":..}`, the streams of
+//!   the url are in `paths`.
+//! * Huobi: every frame it sends is gzipped. `sub`/`unsub` get `subbed`/`unsubbed`, `auth`
+//!   and the `op` requests of the order stream get `err-code` 0.
+//! * OKX: `login` gets a `login` event, `subscribe`/`unsubscribe` an event per argument and
+//!   `ping` a `pong`.
+//! * FTX: `subscribe`/`unsubscribe` get `subscribed`/`unsubscribed`, `ping` a `pong`. The
+//!   login is not answered, like on FTX.
+//!
+//! `respond` and `reply_to` script other answers, `push` sends frames of the exchange's
+//! own, `ping` and `disconnect` exercise heartbeats and reconnects. What the client sent is
+//! in `received`, `wait_for` waits for a frame.
+//!
+//! `MockRest` stands in for the listen key endpoints of the Binance REST API, point a
+//! `BinanceRest` at its `url`.
+use crate::{
+    endpoint::Endpoint,
+    error::{self, Result},
+    normalized::Exchange,
+};
+use flate2::{write::GzEncoder, Compression};
+use futures::prelude::*;
+use serde_json::{json, Value};
+use snafu::ResultExt;
+use std::{
+    collections::VecDeque,
+    io::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Notify},
+    task::JoinHandle,
+};
+use tungstenite::{
+    handshake::server::{Request, Response},
+    Message,
+};
+
+/// How long `wait_for` waits.
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Responder = Box<dyn Fn(&str) -> Option<Vec<String>> + Send + Sync>;
+
+#[derive(Default)]
+struct Shared {
+    received: Vec<String>,
+    paths: Vec<String>,
+    /// The frames to send on each connection, closed once it ends.
+    clients: Vec<(String, mpsc::UnboundedSender<Message>)>,
+    responders: Vec<Responder>,
+}
+
+/// A local server speaking the protocol of one exchange, see the module documentation.
+/// It stops when dropped.
+pub struct MockExchange {
+    exchange: Exchange,
+    addr: SocketAddr,
+    shared: Arc<Mutex<Shared>>,
+    /// Notified whenever a frame is received or a client connects.
+    changed: Arc<Notify>,
+    accept: JoinHandle<()>,
+}
+
+impl MockExchange {
+    /// Listens on a free port of `127.0.0.1`.
+    pub async fn start(exchange: Exchange) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.context(error::MockServerSnafu)?;
+        let addr = listener.local_addr().context(error::MockServerSnafu)?;
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let changed = Arc::new(Notify::new());
+
+        let accept = tokio::spawn({
+            let shared = shared.clone();
+            let changed = changed.clone();
+            async move {
+                while let Ok((tcp, _)) = listener.accept().await {
+                    tokio::spawn(serve(exchange, tcp, shared.clone(), changed.clone()));
+                }
+            }
+        });
+
+        Ok(Self { exchange, addr, shared, changed, accept })
+    }
+
+    /// The endpoint to give `Websocket::set_endpoint`.
+    #[must_use]
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint::new(&format!("ws://{}", self.addr))
+    }
+
+    /// Answers the frames for which `respond` returns `Some` with the frames it returns,
+    /// instead of the default answer. The responders are asked in the order they were added.
+    pub fn respond(&self, respond: impl Fn(&str) -> Option<Vec<String>> + Send + Sync + 'static) {
+        self.lock().responders.push(Box::new(respond));
+    }
+
+    /// Answers the frames containing `pattern` with `replies`, see `respond`.
+    pub fn reply_to(&self, pattern: &str, replies: &[&str]) {
+        let pattern = pattern.to_string();
+        let replies: Vec<String> = replies.iter().map(ToString::to_string).collect();
+        self.respond(move |frame| frame.contains(&pattern).then(|| replies.clone()));
+    }
+
+    /// Sends `frame` on every open connection, gzipped for Huobi.
+    pub fn push(&self, frame: &str) {
+        self.broadcast(|_| encode(self.exchange, frame.to_string()));
+    }
+
+    /// Pings every open connection the way the exchange does: a JSON `ping` on Huobi, a
+    /// websocket ping frame elsewhere.
+    pub fn ping(&self) {
+        let ts = chrono::Utc::now().timestamp_millis();
+        self.broadcast(|path| match self.exchange {
+            Exchange::HuobiUSwap if path.contains("notification") =>
+                encode(self.exchange, json!({ "op": "ping", "ts": ts.to_string() }).to_string()),
+            Exchange::HuobiUSwap => encode(self.exchange, json!({ "ping": ts }).to_string()),
+            _ => Message::Ping(ts.to_string().into_bytes()),
+        });
+    }
+
+    /// Closes every open connection.
+    pub fn disconnect(&self) {
+        self.broadcast(|_| Message::Close(None));
+    }
+
+    /// The text frames received from every connection, in order.
+    #[must_use]
+    pub fn received(&self) -> Vec<String> {
+        self.lock().received.clone()
+    }
+
+    /// The path and query of every connection accepted, in order.
+    #[must_use]
+    pub fn paths(&self) -> Vec<String> {
+        self.lock().paths.clone()
+    }
+
+    /// The number of open connections.
+    #[must_use]
+    pub fn connections(&self) -> usize {
+        let mut shared = self.lock();
+        shared.clients.retain(|(_, client)| !client.is_closed());
+        shared.clients.len()
+    }
+
+    /// Waits for a received frame matching `matches`, among those already received too, and
+    /// returns the first one.
+    ///
+    /// # Panics
+    ///
+    /// If none arrives within `WAIT_TIMEOUT`.
+    pub async fn wait_for(&self, matches: impl Fn(&str) -> bool + Send + Sync) -> String {
+        let found = tokio::time::timeout(WAIT_TIMEOUT, async {
+            loop {
+                let changed = self.changed.notified();
+                if let Some(frame) = self.lock().received.iter().find(|frame| matches(frame)) {
+                    return frame.clone();
+                }
+                changed.await;
+            }
+        }).await;
+        found.unwrap_or_else(|_| panic!("no matching frame, received {:?}", self.received()))
+    }
+
+    /// Waits until `count` connections were accepted in total.
+    ///
+    /// # Panics
+    ///
+    /// If they are not within `WAIT_TIMEOUT`.
+    pub async fn wait_for_connections(&self, count: usize) {
+        let accepted = tokio::time::timeout(WAIT_TIMEOUT, async {
+            loop {
+                let changed = self.changed.notified();
+                if self.lock().paths.len() >= count {
+                    return;
+                }
+                changed.await;
+            }
+        }).await;
+        accepted.unwrap_or_else(|_| panic!("{} connections accepted, expected {}", self.paths().len(), count));
+    }
+
+    fn broadcast(&self, frame: impl Fn(&str) -> Message) {
+        for (path, client) in &self.lock().clients {
+            let _ = client.send(frame(path));
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for MockExchange {
+    fn drop(&mut self) {
+        self.accept.abort();
+        self.disconnect();
+    }
+}
+
+impl std::fmt::Debug for MockExchange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockExchange")
+            .field("exchange", &self.exchange)
+            .field("addr", &self.addr)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs one connection until either side closes it.
+// the handshake callback has the signature tungstenite wants.
+#[allow(clippy::result_large_err)]
+async fn serve(exchange: Exchange, tcp: TcpStream, shared: Arc<Mutex<Shared>>, changed: Arc<Notify>) {
+    let mut path = String::new();
+    let accepted = tokio_tungstenite::accept_hdr_async(tcp, |request: &Request, response: Response| {
+        path = request.uri().to_string();
+        Ok(response)
+    }).await;
+    let Ok(ws) = accepted else {
+        return;
+    };
+    let (mut sink, mut stream) = ws.split();
+    let (tx, mut outgoing) = mpsc::unbounded_channel();
+    {
+        let mut shared = shared.lock().unwrap_or_else(PoisonError::into_inner);
+        shared.paths.push(path.clone());
+        shared.clients.push((path.clone(), tx));
+    }
+    changed.notify_waiters();
+
+    loop {
+        tokio::select! {
+            Some(frame) = outgoing.recv() => {
+                let close = matches!(frame, Message::Close(_));
+                if sink.send(frame).await.is_err() || close {
+                    return;
+                }
+            }
+            frame = stream.next() => {
+                let text = match frame {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                    Some(Ok(_)) => continue,
+                };
+                let replies = {
+                    let mut shared = shared.lock().unwrap_or_else(PoisonError::into_inner);
+                    shared.received.push(text.clone());
+                    shared.responders.iter().find_map(|respond| respond(&text))
+                };
+                changed.notify_waiters();
+                for reply in replies.unwrap_or_else(|| answer(exchange, &text)) {
+                    if sink.send(encode(exchange, reply)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The frame the exchange sends `payload` in.
+fn encode(exchange: Exchange, payload: String) -> Message {
+    if exchange != Exchange::HuobiUSwap {
+        return Message::Text(payload);
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    // writing to a `Vec` does not fail.
+    let _ = encoder.write_all(payload.as_bytes());
+    Message::Binary(encoder.finish().unwrap_or_default())
+}
+
+/// What the exchange answers to `frame` by default.
+fn answer(exchange: Exchange, frame: &str) -> Vec<String> {
+    if exchange == Exchange::Okex && frame == "ping" {
+        return vec!["pong".to_string()];
+    }
+    let Ok(request) = serde_json::from_str::<Value>(frame) else {
+        return Vec::new();
+    };
+    let ts = chrono::Utc::now().timestamp_millis();
+    let op = request["op"].as_str().unwrap_or_default();
+
+    let answers = match exchange {
+        Exchange::BinanceSpot | Exchange::BinanceUSwap => match request["method"].as_str() {
+            Some("SUBSCRIBE" | "UNSUBSCRIBE") => vec![json!({ "result": null, "id": request["id"] })],
+            _ => Vec::new(),
+        },
+        Exchange::HuobiUSwap => match (request.get("sub"), request.get("unsub"), op) {
+            (Some(ch), _, _) => vec![json!({ "id": request["id"], "status": "ok", "subbed": ch, "ts": ts })],
+            (_, Some(ch), _) => vec![json!({ "id": request["id"], "status": "ok", "unsubbed": ch, "ts": ts })],
+            (_, _, "auth") => vec![json!({ "op": "auth", "type": "api", "err-code": 0, "ts": ts, "data": { "user-id": "1" } })],
+            (_, _, "sub" | "unsub") => vec![json!({ "op": op, "cid": request["cid"], "topic": request["topic"], "err-code": 0, "ts": ts })],
+            _ => Vec::new(),
+        },
+        Exchange::Okex => match op {
+            "login" => vec![json!({ "event": "login", "code": "0", "msg": "" })],
+            "subscribe" | "unsubscribe" => request["args"].as_array().into_iter().flatten()
+                .map(|arg| json!({ "event": op, "arg": arg }))
+                .collect(),
+            _ => Vec::new(),
+        },
+        Exchange::Ftx => match op {
+            "subscribe" | "unsubscribe" => {
+                let mut answer = json!({ "type": format!("{op}d"), "channel": request["channel"] });
+                if let Some(market) = request.get("market") {
+                    answer["market"] = market.clone();
+                }
+                vec![answer]
+            }
+            "ping" => vec![json!({ "type": "pong" })],
+            _ => Vec::new(),
+        },
+    };
+    answers.into_iter().map(|answer| answer.to_string()).collect()
+}
+
+#[derive(Default)]
+struct RestShared {
+    requests: Vec<String>,
+    created: usize,
+    /// The status and body of the next keepalives, `200 {}` once empty.
+    keepalives: VecDeque<(u16, String)>,
+}
+
+/// A local server answering the listen key requests of `BinanceRest`: `POST` creates the
+/// keys `listen-key-1`, `listen-key-2`, .., `PUT` and `DELETE` answer `{}`. It stops when
+/// dropped.
+pub struct MockRest {
+    addr: SocketAddr,
+    shared: Arc<Mutex<RestShared>>,
+    /// Notified whenever a request is received.
+    changed: Arc<Notify>,
+    accept: JoinHandle<()>,
+}
+
+impl MockRest {
+    /// Listens on a free port of `127.0.0.1`.
+    pub async fn start() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.context(error::MockServerSnafu)?;
+        let addr = listener.local_addr().context(error::MockServerSnafu)?;
+        let shared = Arc::new(Mutex::new(RestShared::default()));
+        let changed = Arc::new(Notify::new());
+
+        let accept = tokio::spawn({
+            let shared = shared.clone();
+            let changed = changed.clone();
+            async move {
+                while let Ok((tcp, _)) = listener.accept().await {
+                    tokio::spawn(serve_rest(tcp, shared.clone(), changed.clone()));
+                }
+            }
+        });
+
+        Ok(Self { addr, shared, changed, accept })
+    }
+
+    /// The url to give `BinanceRest::new`, for spot and USDⓈ-M alike.
+    #[must_use]
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Answers the next keepalives with `answers`, a status and a body each.
+    pub fn answer_keepalives(&self, answers: &[(u16, &str)]) {
+        self.lock().keepalives.extend(answers.iter().map(|(status, body)| (*status, body.to_string())));
+    }
+
+    /// The method and target of every request received, in order, such as
+    /// `PUT /fapi/v1/listenKey?listenKey=listen-key-1`.
+    #[must_use]
+    pub fn requests(&self) -> Vec<String> {
+        self.lock().requests.clone()
+    }
+
+    /// Waits until `count` requests starting with `prefix` were received in total.
+    ///
+    /// # Panics
+    ///
+    /// If they are not within `WAIT_TIMEOUT`.
+    pub async fn wait_for_requests(&self, prefix: &str, count: usize) {
+        let received = tokio::time::timeout(WAIT_TIMEOUT, async {
+            loop {
+                let changed = self.changed.notified();
+                if self.lock().requests.iter().filter(|request| request.starts_with(prefix)).count() >= count {
+                    return;
+                }
+                changed.await;
+            }
+        }).await;
+        received.unwrap_or_else(|_| panic!("expected {} requests starting with {:?}, received {:?}", count, prefix, self.requests()));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RestShared> {
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for MockRest {
+    fn drop(&mut self) {
+        self.accept.abort();
+    }
+}
+
+impl std::fmt::Debug for MockRest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockRest")
+            .field("addr", &self.addr)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Answers one request and closes the connection. The listen key requests have no body.
+async fn serve_rest(tcp: TcpStream, shared: Arc<Mutex<RestShared>>, changed: Arc<Notify>) {
+    let mut tcp = BufReader::new(tcp);
+    let mut request_line = String::new();
+    if tcp.read_line(&mut request_line).await.is_err() {
+        return;
+    }
+    // skips the headers.
+    let mut header = String::new();
+    loop {
+        header.clear();
+        match tcp.read_line(&mut header).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) if header.trim().is_empty() => break,
+            Ok(_) => (),
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let request = format!("{} {}", parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+    let (status, body) = {
+        let mut shared = shared.lock().unwrap_or_else(PoisonError::into_inner);
+        shared.requests.push(request.clone());
+        if request.starts_with("POST") {
+            shared.created += 1;
+            (200, json!({ "listenKey": format!("listen-key-{}", shared.created) }).to_string())
+        } else if request.starts_with("PUT") {
+            shared.keepalives.pop_front().unwrap_or_else(|| (200, "{}".to_string()))
+        } else {
+            (200, "{}".to_string())
+        }
+    };
+    changed.notify_waiters();
+
+    let response = format!(
+        "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    );
+    let _ = tcp.get_mut().write_all(response.as_bytes()).await;
+    let _ = tcp.get_mut().shutdown().await;
+}
@@ -1,10 +1,16 @@
 use serde::{Deserialize,  Serialize};
-use std::fmt::{self, Display};
+use chrono::{DateTime, TimeZone, Utc};
+use std::time::{Duration, Instant};
 use crate::binance_model;
+use crate::binance_orderbook;
 use crate::binance_uswap_model;
 use crate::huobi_uswap_model;
+use crate::huobi_orderbook;
 use crate::okex_model;
+use crate::okex_orderbook;
 use crate::ftx_model;
+use crate::ftx_orderbook;
+use crate::control;
 
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
@@ -16,6 +22,10 @@ pub enum Subscription {
     BinanceUSwapMStream, //blvt use uswap.
     BinanceBLVTStream,
     BinanceUSwapOrder,
+    /// The WebSocket API of spot, for `binance_api::BinanceApi`.
+    BinanceSpotApi,
+    /// The WebSocket API of USDⓈ-M futures, for `binance_api::BinanceApi`.
+    BinanceUSwapApi,
 
     HuobiUSwapMarketStream,
     HuobiUSwapOrderStream,
@@ -27,6 +37,217 @@ pub enum Subscription {
     FtxOrderStream,
 }
 
+/// Parses the variant name, as `Debug` prints it.
+impl std::str::FromStr for Subscription {
+    type Err = crate::error::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name {
+            "BinanceSpotMStream" => Self::BinanceSpotMStream,
+            "BinanceSpotOrder" => Self::BinanceSpotOrder,
+            "BinanceUSwapMStream" => Self::BinanceUSwapMStream,
+            "BinanceBLVTStream" => Self::BinanceBLVTStream,
+            "BinanceUSwapOrder" => Self::BinanceUSwapOrder,
+            "BinanceSpotApi" => Self::BinanceSpotApi,
+            "BinanceUSwapApi" => Self::BinanceUSwapApi,
+            "HuobiUSwapMarketStream" => Self::HuobiUSwapMarketStream,
+            "HuobiUSwapOrderStream" => Self::HuobiUSwapOrderStream,
+            "OkexMarketStream" => Self::OkexMarketStream,
+            "OkexOrderStream" => Self::OkexOrderStream,
+            "FtxMarketStream" => Self::FtxMarketStream,
+            "FtxOrderStream" => Self::FtxOrderStream,
+            _ => return Err(crate::error::Error::UnknownSubscription { name: name.to_string() }),
+        })
+    }
+}
+
+/// One connection: the stream it reads and the account it logs in with.
+///
+/// Connections opened with a bare `Subscription` belong to the default account,
+/// `account: None`, so several accounts can stream the same `Subscription` side by side.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub struct ConnectionId {
+    pub subscription: Subscription,
+    /// The label given to `Websocket::add_account`.
+    pub account: Option<String>,
+}
+
+impl ConnectionId {
+    #[must_use]
+    pub fn new(account: &str, subscription: Subscription) -> Self {
+        Self { subscription, account: Some(account.to_string()) }
+    }
+}
+
+/// `OkexOrderStream`, or `OkexOrderStream@<account>`.
+impl std::fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.account {
+            Some(account) => write!(f, "{:?}@{account}", self.subscription),
+            None => write!(f, "{:?}", self.subscription),
+        }
+    }
+}
+
+impl From<Subscription> for ConnectionId {
+    fn from(subscription: Subscription) -> Self {
+        Self { subscription, account: None }
+    }
+}
+
+impl From<&Subscription> for ConnectionId {
+    fn from(subscription: &Subscription) -> Self {
+        subscription.clone().into()
+    }
+}
+
+impl From<&Self> for ConnectionId {
+    fn from(connection: &Self) -> Self {
+        connection.clone()
+    }
+}
+
+/// Lifecycle of a single connection as seen by the reconnect supervisor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The connection was opened and the login/subscribe handshake was sent.
+    Connected,
+    /// The socket closed or failed to read, with the reason.
+    Disconnected(String),
+    /// Attempt `attempt` to reconnect will be made after `delay`.
+    Reconnecting { attempt: u32, delay: Duration },
+    /// The connection is back and the handshake was replayed.
+    Reconnected { attempts: u32 },
+    /// `ReconnectPolicy::max_attempts` was reached, the connection stays down.
+    GaveUp { attempts: u32 },
+    /// The login or auth request was refused with the code and message of the exchange,
+    /// the connection stays down.
+    LoginRejected { code: String, msg: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionEvent {
+    /// The connection, and so the account, whose state changed.
+    pub connection: ConnectionId,
+    pub state: ConnectionState,
+}
+
+/// What the receive loop does with a frame it cannot decode, see
+/// `Websocket::set_decode_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodePolicy {
+    /// Deliver it as `WebsocketEvent::Unparsed` and keep going.
+    #[default]
+    Emit,
+    /// Log it and keep going, it only shows in `Websocket::unparsed_count`.
+    Log,
+    /// Fail with the decode error, which ends `Websocket::connect`.
+    Fatal,
+}
+
+/// An event with the connection, and so the account, it was received on, and when.
+///
+/// Events derived from a frame, like local order books, carry the receive times of that
+/// frame. Events of the client itself, like `Connection`, carry the time they were raised.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub connection: ConnectionId,
+    pub event: WebsocketEvent,
+    /// When the frame was read from the socket, for measuring latencies.
+    pub received: Instant,
+    /// `received` on the wall clock, for comparing with `exchange_time`.
+    pub received_at: DateTime<Utc>,
+    /// See `WebsocketEvent::exchange_time`.
+    pub exchange_time: Option<DateTime<Utc>>,
+    /// Counts the events delivered on `connection` from 0, in delivery order. It keeps
+    /// counting across reconnects.
+    pub sequence: u64,
+}
+
+impl Envelope {
+    /// The account label of the connection, `None` for the default account.
+    #[must_use]
+    pub fn account(&self) -> Option<&str> {
+        self.connection.account.as_deref()
+    }
+}
+
+impl WebsocketEvent {
+    /// When the exchange generated the event: the event time of Binance, the `ts` of Huobi,
+    /// the time of the first entry of OKX and FTX. `None` for messages without a time, like
+    /// the spot book ticker, and for the events of this crate.
+    #[must_use]
+    pub fn exchange_time(&self) -> Option<DateTime<Utc>> {
+        let ms = match self {
+            Self::BinanceSpotAccountUpdate(msg) => msg.data.event_time,
+            Self::BinanceSpotOrderTrade(msg) => msg.data.event_time,
+            Self::BinanceSpotBalanceUpdate(msg) => msg.data.event_time,
+            Self::BinanceSpotAggrTrades(msg) => msg.data.event_time,
+            Self::BinanceSpotTrade(msg) => msg.data.event_time,
+            Self::BinanceSpotDayTicker(msg) => msg.data.event_time,
+            Self::BinanceSpotDayTickerAll(msg) => msg.data.first()?.event_time,
+            Self::BinanceSpotKline(msg) => msg.data.event_time,
+            Self::BinanceSpotDepthOrderBook(msg) => msg.data.event_time,
+            Self::BinanceSpotListenKeyEvent(msg) => msg.data.event_time,
+
+            Self::BinanceUSwapVec(msg) => msg.data.first()?.event_time,
+            Self::BinanceUSwapDayTickerEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapBookTickerEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapMiniTickerEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapVecMiniTickerEvent(msg) => msg.data.first()?.event_time,
+            Self::BinanceUSwapAccountUpdateEvent(event) => event.event_time,
+            Self::BinanceUSwapOrderTradeEvent(event) => event.event_time,
+            Self::BinanceUSwapListenKeyEvent(event) => event.event_time,
+            Self::BinanceUSwapMarginCallEvent(event) => event.event_time,
+            Self::BinanceUSwapAccountConfigEvent(event) => event.event_time,
+            Self::BinanceUSwapAggrTradesEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapIndexPriceEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapMarkPriceEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapVecMarkPriceEvent(msg) => msg.data.first()?.event_time,
+            Self::BinanceUSwapTradeEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapKlineEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapContinuousKlineEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapIndexKlineEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapLiquidationEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapDepthOrderBookEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapEtpNavEvent(msg) => msg.data.event_time,
+            Self::BinanceUSwapEtpKlineEvent(msg) => msg.data.event_time,
+
+            Self::HuobiUSwapIncrementalOrderBook(msg) => msg.ts,
+            Self::HuobiUSwapOrderBook(msg) => msg.ts,
+            Self::HuobiUSwapBBO(msg) => msg.ts,
+            Self::HuobiUSwapKline(msg) => msg.ts,
+            Self::HuobiUSwapTradeDetail(msg) => msg.ts,
+            Self::HuobiUSwapAccount(msg) => msg.ts,
+            Self::HuobiUSwapOrder(msg) => msg.ts,
+            Self::HuobiUSwapMatchOrder(msg) => msg.ts,
+            Self::HuobiUSwapPosition(msg) => msg.ts,
+
+            Self::OkexOrderBook(msg) => return msg.data.first().map(|book| book.ts),
+            Self::OkexTrade(msg) => return msg.data.first().map(|trade| trade.ts),
+            Self::OkexOrder(msg) => return msg.data.first().map(|order| order.u_time),
+            Self::OkexAccount(msg) => return msg.data.first().map(|data| data.u_time),
+            Self::OkexAccountPosition(msg) => msg.data.first()?.p_time.parse().ok()?,
+            Self::OkexPosition(msg) => msg.data.first()?.u_time.parse().ok()?,
+
+            Self::FtxRsp(msg) => return match msg.data {
+                Some(ftx_model::ResponseData::Ticker(ref ticker)) => Some(ticker.time),
+                Some(ftx_model::ResponseData::Trades(ref trades)) => trades.first().map(|trade| trade.time),
+                Some(ftx_model::ResponseData::OrderbookData(ref data)) => Some(data.time),
+                Some(ftx_model::ResponseData::Fill(ref fill)) => Some(fill.time),
+                _ => None,
+            },
+
+            Self::BinanceLocalOrderBook(book) => book.event_time,
+            Self::OkexLocalOrderBook(book) => return Some(book.ts),
+            Self::HuobiUSwapLocalOrderBook(book) => book.ts,
+
+            _ => return None,
+        };
+        Utc.timestamp_millis_opt(i64::try_from(ms).ok()?).single()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BnWsRx<T> {
     pub stream: String,
@@ -49,6 +270,7 @@ pub enum WebsocketEvent {
     BinanceSpotKline(BnWsRx<binance_model::KlineEvent>),
     BinanceSpotDepthOrderBook(BnWsRx<binance_model::DepthOrderBookEvent>),
     BinanceSpotBookTicker(BnWsRx<binance_model::BookTickerEvent>),
+    BinanceSpotListenKeyEvent(BnWsRx<binance_model::ListenKeyEvent>),
 
     //Binance USDT Swap
     BinanceUSwapVec(BnWsRx<Vec<binance_uswap_model::DayTickerEvent>>),
@@ -104,6 +326,34 @@ pub enum WebsocketEvent {
 
     Text(String),
 
+    #[serde(skip)]
+    Connection(ConnectionEvent),
+
+    /// A frame that could not be decoded, see `DecodePolicy`. `raw` is empty when the frame
+    /// could not be decompressed.
+    #[serde(skip)]
+    Unparsed { subscription: Subscription, raw: String, error: String },
+
+    //Local order books, see `Websocket::enable_local_order_book`
+    #[serde(skip)]
+    BinanceLocalOrderBook(binance_orderbook::LocalOrderBook),
+    #[serde(skip)]
+    BinanceOrderBookResync(binance_orderbook::ResyncEvent),
+    #[serde(skip)]
+    OkexLocalOrderBook(okex_orderbook::LocalOrderBook),
+    #[serde(skip)]
+    OkexOrderBookResync(okex_orderbook::ResyncEvent),
+    #[serde(skip)]
+    HuobiUSwapLocalOrderBook(huobi_orderbook::LocalOrderBook),
+    #[serde(skip)]
+    HuobiUSwapOrderBookResync(huobi_orderbook::ResyncEvent),
+    #[serde(skip)]
+    FtxLocalOrderBook(ftx_model::Orderbook),
+    #[serde(skip)]
+    FtxOrderBookResync(ftx_orderbook::ResyncEvent),
+    /// Answer to a subscribe or unsubscribe request, see `Websocket::control`.
+    #[serde(skip)]
+    Topic(control::TopicEvent),
 }
 
 
@@ -124,6 +374,7 @@ pub enum BinanceSpotWebsocketEvent {
     BinanceSpotKline(BnWsRx<binance_model::KlineEvent>),
     BinanceSpotDepthOrderBook(BnWsRx<binance_model::DepthOrderBookEvent>),
     BinanceSpotBookTicker(BnWsRx<binance_model::BookTickerEvent>),
+    BinanceSpotListenKeyEvent(BnWsRx<binance_model::ListenKeyEvent>),
 
     Text(String),
 
@@ -141,12 +392,14 @@ pub enum BinanceUSwapWebsocketEvent {
     BinanceUSwapVecMiniTickerEvent(BnWsRx<Vec<binance_uswap_model::MiniTickerEvent>>),
     BinanceUSwapAccountUpdateEvent(binance_uswap_model::AccountUpdateEvent),
     BinanceUSwapOrderTradeEvent(binance_uswap_model::OrderTradeEvent),
-    BinanceUSwapListenKeyEvent(binance_uswap_model::ListenKeyEvent),
     BinanceUSwapMarginCallEvent(binance_uswap_model::MarginCallEvent),
     BinanceUSwapAccountConfigEvent(binance_uswap_model::AccountConfigEvent),
+    // after the other user data events, they all have its fields.
+    BinanceUSwapListenKeyEvent(binance_uswap_model::ListenKeyEvent),
     BinanceUSwapAggrTradesEvent(BnWsRx<binance_uswap_model::AggrTradesEvent>),
-    BinanceUSwapIndexPriceEvent(BnWsRx<binance_uswap_model::IndexPriceEvent>),
+    // before the index price, whose fields mark prices have.
     BinanceUSwapMarkPriceEvent(BnWsRx<binance_uswap_model::MarkPriceEvent>),
+    BinanceUSwapIndexPriceEvent(BnWsRx<binance_uswap_model::IndexPriceEvent>),
     BinanceUSwapVecMarkPriceEvent(BnWsRx<Vec<binance_uswap_model::MarkPriceEvent>>),
     BinanceUSwapTradeEvent(BnWsRx<binance_uswap_model::TradeEvent>),
     BinanceUSwapKlineEvent(BnWsRx<binance_uswap_model::KlineEvent>),
@@ -170,6 +423,7 @@ pub enum BinanceUSwapWebsocketEvent {
 pub enum HuobiUSwapWebsocketEvent {
     //Ping,Sub,Op
     HuobiUSwapMarketPing(huobi_uswap_model::MarketPing),
+    HuobiUSwapDepthSnapshot(huobi_uswap_model::WSReqResponse<huobi_uswap_model::DepthSnapshot>),
     HuobiUSwapSubStatus(huobi_uswap_model::SubStatus),
     //Market
     HuobiUSwapIncrementalOrderBook(huobi_uswap_model::WSMarketResponse<huobi_uswap_model::IncrementalOrderBook>),
@@ -0,0 +1,1544 @@
+//! Exchange-agnostic market data and account events.
+//!
+//! Every type carries the `Exchange` it came from and the symbol as the exchange spells it,
+//! prices and quantities are `Decimal` and times `DateTime<Utc>`. Build a client with
+//! `Websocket::new_normalized` to receive `NormalizedEvent`s instead of `WebsocketEvent`s, or
+//! convert single events with `WebsocketEvent::normalized`.
+//!
+//! An item with a price, quantity or time that is not valid is not converted: the
+//! conversions fail with `Error::InvalidNumber` or `Error::InvalidTime`, which `normalized`
+//! yields in its place and `new_normalized` handles according to the `DecodePolicy`.
+//!
+//! Account events share one vocabulary across venues: `OrderStatus`, `Side`, `PositionSide`
+//! and `Liquidity`. Quantities are in base asset, or contracts on Huobi and OKX derivatives.
+//! Fees are what was paid, negative for rebates.
+use crate::{
+    binance_model,
+    error::{Error, InvalidNumberSnafu, InvalidSideSnafu, InvalidTimeSnafu, Result},
+    binance_orderbook,
+    binance_uswap_model,
+    ftx_model,
+    huobi_orderbook,
+    huobi_uswap_model,
+    models::{BnWsRx, ConnectionEvent, Subscription, WebsocketEvent},
+    okex_model,
+    okex_orderbook,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use rust_decimal::prelude::*;
+use snafu::OptionExt;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Exchange {
+    BinanceSpot,
+    BinanceUSwap,
+    HuobiUSwap,
+    Okex,
+    Ftx,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// `(price, quantity)` levels, best first.
+pub type Levels = Vec<(Decimal, Decimal)>;
+
+#[derive(Debug, Clone)]
+pub struct Trade {
+    pub exchange: Exchange,
+    pub symbol: String,
+    pub trade_id: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    /// Side of the taker.
+    pub side: Side,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BookTicker {
+    pub exchange: Exchange,
+    pub symbol: String,
+    pub bid_price: Decimal,
+    pub bid_quantity: Decimal,
+    pub ask_price: Decimal,
+    pub ask_quantity: Decimal,
+    /// Not sent by Binance spot.
+    pub time: Option<DateTime<Utc>>,
+}
+
+/// The full book, or its top levels for partial depth streams.
+#[derive(Debug, Clone)]
+pub struct L2Snapshot {
+    pub exchange: Exchange,
+    pub symbol: String,
+    pub bids: Levels,
+    pub asks: Levels,
+    /// Not sent by Binance partial depth streams.
+    pub time: Option<DateTime<Utc>>,
+}
+
+/// Changed levels, a quantity of 0 removes the level.
+#[derive(Debug, Clone)]
+pub struct L2Update {
+    pub exchange: Exchange,
+    pub symbol: String,
+    pub bids: Levels,
+    pub asks: Levels,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Kline {
+    pub exchange: Exchange,
+    pub symbol: String,
+    /// As spelled by the exchange, e.g. `1m` or `1min`.
+    pub interval: String,
+    pub open_time: DateTime<Utc>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    /// In base asset, or contracts on Huobi.
+    pub volume: Decimal,
+    /// Whether the bar is complete, `None` when the exchange does not tell.
+    pub closed: Option<bool>,
+}
+
+/// Rolling 24h statistics.
+#[derive(Debug, Clone)]
+pub struct Ticker {
+    pub exchange: Exchange,
+    pub symbol: String,
+    pub last: Decimal,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub volume: Decimal,
+    pub quote_volume: Decimal,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OrderStatus {
+    /// Accepted and resting, nothing filled yet.
+    New,
+    PartiallyFilled,
+    Filled,
+    /// A cancel was requested but not confirmed yet.
+    PendingCancel,
+    /// Canceled by the user or by the venue, possibly after a partial fill.
+    Canceled,
+    Rejected,
+    /// Canceled by the rules of the order, e.g. an IOC or FOK order that did not fill.
+    Expired,
+}
+
+impl OrderStatus {
+    /// Whether the order can still change.
+    #[must_use]
+    pub const fn is_open(self) -> bool {
+        matches!(self, Self::New | Self::PartiallyFilled | Self::PendingCancel)
+    }
+}
+
+/// Side of a position, `Net` for one-way (Binance `BOTH`, OKX `net`) positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PositionSide {
+    Long,
+    Short,
+    Net,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+/// State of an order after a change.
+#[derive(Debug, Clone)]
+pub struct OrderUpdate {
+    pub exchange: Exchange,
+    pub symbol: String,
+    pub order_id: String,
+    pub client_order_id: Option<String>,
+    pub side: Side,
+    /// `None` on spot markets.
+    pub position_side: Option<PositionSide>,
+    pub status: OrderStatus,
+    /// `None` for market orders.
+    pub price: Option<Decimal>,
+    pub quantity: Decimal,
+    pub filled_quantity: Decimal,
+    /// `None` until something filled.
+    pub average_price: Option<Decimal>,
+    pub time: DateTime<Utc>,
+}
+
+/// One execution of an order.
+#[derive(Debug, Clone)]
+pub struct Fill {
+    pub exchange: Exchange,
+    pub symbol: String,
+    pub order_id: String,
+    pub client_order_id: Option<String>,
+    pub trade_id: String,
+    pub side: Side,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    /// Paid fee, negative for rebates. `None` when the venue does not send it with the fill.
+    pub fee: Option<Decimal>,
+    pub fee_asset: Option<String>,
+    pub liquidity: Option<Liquidity>,
+    pub time: DateTime<Utc>,
+}
+
+/// Balance of one asset. Venues send either the new balance or the change, the other
+/// fields are `None`.
+#[derive(Debug, Clone)]
+pub struct BalanceUpdate {
+    pub exchange: Exchange,
+    pub asset: String,
+    /// Total balance, including what is locked.
+    pub balance: Option<Decimal>,
+    pub available: Option<Decimal>,
+    pub locked: Option<Decimal>,
+    pub delta: Option<Decimal>,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PositionUpdate {
+    pub exchange: Exchange,
+    pub symbol: String,
+    pub side: PositionSide,
+    /// Positive for `Long` and `Short`, negative for a short `Net` position.
+    pub quantity: Decimal,
+    pub entry_price: Option<Decimal>,
+    pub unrealized_pnl: Option<Decimal>,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub enum NormalizedEvent {
+    Trade(Trade),
+    BookTicker(BookTicker),
+    L2Snapshot(L2Snapshot),
+    L2Update(L2Update),
+    Kline(Kline),
+    Ticker(Ticker),
+    OrderUpdate(OrderUpdate),
+    Fill(Fill),
+    BalanceUpdate(BalanceUpdate),
+    PositionUpdate(PositionUpdate),
+    Connection(ConnectionEvent),
+    /// A frame that could not be decoded, or an event that could not be converted, see
+    /// `DecodePolicy`.
+    Unparsed { subscription: Subscription, raw: String, error: String },
+}
+
+fn decimal(value: &str) -> Result<Decimal> {
+    Decimal::from_str(value).ok().context(InvalidNumberSnafu { value })
+}
+
+fn decimal_f64(value: f64) -> Result<Decimal> {
+    Decimal::from_f64(value).context(InvalidNumberSnafu { value: value.to_string() })
+}
+
+fn millis(ms: u64) -> Result<DateTime<Utc>> {
+    millis_i64(i64::try_from(ms).ok().context(InvalidTimeSnafu { value: ms.to_string() })?)
+}
+
+fn millis_i64(ms: i64) -> Result<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(ms).single().context(InvalidTimeSnafu { value: ms.to_string() })
+}
+
+fn levels_f64(levels: impl Iterator<Item = (f64, f64)>) -> Result<Levels> {
+    levels.map(|(price, qty)| Ok((decimal_f64(price)?, decimal_f64(qty)?))).collect()
+}
+
+fn book_levels(bids: &BTreeMap<Decimal, Decimal>, asks: &BTreeMap<Decimal, Decimal>) -> (Levels, Levels) {
+    (
+        bids.iter().rev().map(|(price, qty)| (*price, *qty)).collect(),
+        asks.iter().map(|(price, qty)| (*price, *qty)).collect(),
+    )
+}
+
+/// Binance partial depth events have no symbol, it is the prefix of the stream name.
+fn stream_symbol(stream: &str) -> String {
+    stream.split('@').next().unwrap_or_default().to_uppercase()
+}
+
+/// `market.BTC-USDT.kline.1min` -> `BTC-USDT`
+fn huobi_symbol(ch: &str) -> String {
+    ch.split('.').nth(1).unwrap_or_default().to_string()
+}
+
+const fn taker_side(is_buyer_maker: bool) -> Side {
+    if is_buyer_maker { Side::Sell } else { Side::Buy }
+}
+
+const fn liquidity(is_maker: bool) -> Liquidity {
+    if is_maker { Liquidity::Maker } else { Liquidity::Taker }
+}
+
+/// Venues send 0 for prices that do not apply, e.g. the price of a market order.
+fn positive(value: Decimal) -> Option<Decimal> {
+    (value > Decimal::ZERO).then_some(value)
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// `None` for the empty text OKX sends for values that do not apply.
+fn optional_decimal(value: &str) -> Result<Option<Decimal>> {
+    (!value.is_empty()).then(|| decimal(value)).transpose()
+}
+
+/// Executions of nothing are not fills.
+fn filled(fill: Fill) -> Option<Fill> {
+    (!fill.quantity.is_zero()).then_some(fill)
+}
+
+fn millis_str(ms: &str) -> Result<DateTime<Utc>> {
+    millis(ms.parse().ok().context(InvalidTimeSnafu { value: ms })?)
+}
+
+/// `BUY`, `buy`, `SELL` or `sell`.
+fn side(value: &str) -> Result<Side> {
+    if value.eq_ignore_ascii_case("buy") {
+        Ok(Side::Buy)
+    } else if value.eq_ignore_ascii_case("sell") {
+        Ok(Side::Sell)
+    } else {
+        InvalidSideSnafu { value }.fail()
+    }
+}
+
+/// `LONG`/`SHORT`/`BOTH` on Binance, `long`/`short`/`net` on OKX, `None` for anything else,
+/// e.g. the empty `posSide` of OKX spot orders.
+fn position_side(value: &str) -> Option<PositionSide> {
+    match value.to_ascii_lowercase().as_str() {
+        "long" => Some(PositionSide::Long),
+        "short" => Some(PositionSide::Short),
+        "both" | "net" => Some(PositionSide::Net),
+        _ => None,
+    }
+}
+
+impl TryFrom<&binance_model::TradeEvent> for Trade {
+    type Error = Error;
+
+    fn try_from(event: &binance_model::TradeEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceSpot,
+            symbol: event.symbol.clone(),
+            trade_id: event.trade_id.to_string(),
+            price: decimal(&event.price)?,
+            quantity: decimal(&event.qty)?,
+            side: taker_side(event.is_buyer_maker),
+            time: millis(event.trade_order_time)?,
+        })
+    }
+}
+
+impl TryFrom<&binance_model::AggrTradesEvent> for Trade {
+    type Error = Error;
+
+    fn try_from(event: &binance_model::AggrTradesEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceSpot,
+            symbol: event.symbol.clone(),
+            trade_id: event.aggregated_trade_id.to_string(),
+            price: decimal(&event.price)?,
+            quantity: decimal(&event.qty)?,
+            side: taker_side(event.is_buyer_maker),
+            time: millis(event.trade_order_time)?,
+        })
+    }
+}
+
+impl TryFrom<&binance_uswap_model::TradeEvent> for Trade {
+    type Error = Error;
+
+    fn try_from(event: &binance_uswap_model::TradeEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceUSwap,
+            symbol: event.symbol.clone(),
+            trade_id: event.trade_id.to_string(),
+            price: decimal(&event.price)?,
+            quantity: decimal(&event.qty)?,
+            side: taker_side(event.is_buyer_maker),
+            time: millis(event.trade_order_time)?,
+        })
+    }
+}
+
+impl TryFrom<&binance_uswap_model::AggrTradesEvent> for Trade {
+    type Error = Error;
+
+    fn try_from(event: &binance_uswap_model::AggrTradesEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceUSwap,
+            symbol: event.symbol.clone(),
+            trade_id: event.aggregated_trade_id.to_string(),
+            price: decimal(&event.price)?,
+            quantity: decimal(&event.qty)?,
+            side: taker_side(event.is_buyer_maker),
+            time: millis(event.trade_order_time)?,
+        })
+    }
+}
+
+impl TryFrom<&okex_model::Trade> for Trade {
+    type Error = Error;
+
+    fn try_from(trade: &okex_model::Trade) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::Okex,
+            symbol: trade.inst_id.clone(),
+            trade_id: trade.trade_id.clone(),
+            price: decimal_f64(trade.price)?,
+            quantity: decimal_f64(trade.size)?,
+            side: side(&trade.side)?,
+            time: trade.ts,
+        })
+    }
+}
+
+/// Huobi trades carry no symbol, it comes from the topic of the push.
+impl From<&huobi_uswap_model::WSMarketResponse<huobi_uswap_model::TradeDetail>> for Vec<Result<Trade>> {
+    fn from(msg: &huobi_uswap_model::WSMarketResponse<huobi_uswap_model::TradeDetail>) -> Self {
+        let symbol = huobi_symbol(&msg.ch);
+        msg.tick.data.iter()
+            .map(|trade| Ok(Trade {
+                exchange: Exchange::HuobiUSwap,
+                symbol: symbol.clone(),
+                trade_id: trade.id.to_string(),
+                price: decimal_f64(trade.price)?,
+                quantity: decimal_f64(trade.quantity)?,
+                side: side(&trade.direction)?,
+                time: millis(trade.ts)?,
+            }))
+            .collect()
+    }
+}
+
+impl From<(&str, &ftx_model::Trade)> for Trade {
+    fn from((market, trade): (&str, &ftx_model::Trade)) -> Self {
+        Self {
+            exchange: Exchange::Ftx,
+            symbol: market.to_string(),
+            trade_id: trade.id.to_string(),
+            price: trade.price,
+            quantity: trade.size,
+            side: trade.side.into(),
+            time: trade.time,
+        }
+    }
+}
+
+impl From<ftx_model::Side> for Side {
+    fn from(side: ftx_model::Side) -> Self {
+        match side {
+            ftx_model::Side::Buy => Self::Buy,
+            ftx_model::Side::Sell => Self::Sell,
+        }
+    }
+}
+
+impl TryFrom<&binance_model::BookTickerEvent> for BookTicker {
+    type Error = Error;
+
+    fn try_from(event: &binance_model::BookTickerEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceSpot,
+            symbol: event.symbol.clone(),
+            bid_price: decimal(&event.best_bid)?,
+            bid_quantity: decimal(&event.best_bid_qty)?,
+            ask_price: decimal(&event.best_ask)?,
+            ask_quantity: decimal(&event.best_ask_qty)?,
+            time: None,
+        })
+    }
+}
+
+impl TryFrom<&binance_uswap_model::BookTickerEvent> for BookTicker {
+    type Error = Error;
+
+    fn try_from(event: &binance_uswap_model::BookTickerEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceUSwap,
+            symbol: event.symbol.clone(),
+            bid_price: decimal(&event.best_bid)?,
+            bid_quantity: decimal(&event.best_bid_qty)?,
+            ask_price: decimal(&event.best_ask)?,
+            ask_quantity: decimal(&event.best_ask_qty)?,
+            time: Some(millis(event.event_time)?),
+        })
+    }
+}
+
+impl TryFrom<&huobi_uswap_model::WSMarketResponse<huobi_uswap_model::BBO>> for BookTicker {
+    type Error = Error;
+
+    fn try_from(msg: &huobi_uswap_model::WSMarketResponse<huobi_uswap_model::BBO>) -> Result<Self> {
+        let (bid_price, bid_quantity) = msg.tick.bid.unwrap_or_default();
+        let (ask_price, ask_quantity) = msg.tick.ask.unwrap_or_default();
+        Ok(Self {
+            exchange: Exchange::HuobiUSwap,
+            symbol: huobi_symbol(&msg.ch),
+            bid_price: decimal_f64(bid_price)?,
+            bid_quantity: decimal_f64(bid_quantity)?,
+            ask_price: decimal_f64(ask_price)?,
+            ask_quantity: decimal_f64(ask_quantity)?,
+            time: Some(millis(msg.tick.ts)?),
+        })
+    }
+}
+
+impl From<(&str, &ftx_model::Ticker)> for BookTicker {
+    fn from((market, ticker): (&str, &ftx_model::Ticker)) -> Self {
+        Self {
+            exchange: Exchange::Ftx,
+            symbol: market.to_string(),
+            bid_price: ticker.bid,
+            bid_quantity: ticker.bid_size,
+            ask_price: ticker.ask,
+            ask_quantity: ticker.ask_size,
+            time: Some(ticker.time),
+        }
+    }
+}
+
+impl TryFrom<&BnWsRx<binance_model::OrderBook>> for L2Snapshot {
+    type Error = Error;
+
+    fn try_from(msg: &BnWsRx<binance_model::OrderBook>) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceSpot,
+            symbol: stream_symbol(&msg.stream),
+            bids: levels_f64(msg.data.bids.iter().map(|bid| (bid.price, bid.qty)))?,
+            asks: levels_f64(msg.data.asks.iter().map(|ask| (ask.price, ask.qty)))?,
+            time: None,
+        })
+    }
+}
+
+impl TryFrom<&BnWsRx<binance_uswap_model::OrderBook>> for L2Snapshot {
+    type Error = Error;
+
+    fn try_from(msg: &BnWsRx<binance_uswap_model::OrderBook>) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceUSwap,
+            symbol: stream_symbol(&msg.stream),
+            bids: levels_f64(msg.data.bids.iter().map(|bid| (bid.price, bid.qty)))?,
+            asks: levels_f64(msg.data.asks.iter().map(|ask| (ask.price, ask.qty)))?,
+            time: None,
+        })
+    }
+}
+
+impl TryFrom<&huobi_uswap_model::WSMarketResponse<huobi_uswap_model::OrderBook>> for L2Snapshot {
+    type Error = Error;
+
+    fn try_from(msg: &huobi_uswap_model::WSMarketResponse<huobi_uswap_model::OrderBook>) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::HuobiUSwap,
+            symbol: huobi_symbol(&msg.ch),
+            bids: levels_f64(msg.tick.bids.iter().copied())?,
+            asks: levels_f64(msg.tick.asks.iter().copied())?,
+            time: Some(millis(msg.tick.ts)?),
+        })
+    }
+}
+
+impl TryFrom<&huobi_uswap_model::WSMarketResponse<huobi_uswap_model::IncrementalOrderBook>> for L2Snapshot {
+    type Error = Error;
+
+    fn try_from(msg: &huobi_uswap_model::WSMarketResponse<huobi_uswap_model::IncrementalOrderBook>) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::HuobiUSwap,
+            symbol: huobi_symbol(&msg.ch),
+            bids: levels_f64(msg.tick.bids.iter().copied())?,
+            asks: levels_f64(msg.tick.asks.iter().copied())?,
+            time: Some(millis(msg.tick.ts)?),
+        })
+    }
+}
+
+impl TryFrom<(&str, &okex_model::OrderBook)> for L2Snapshot {
+    type Error = Error;
+
+    fn try_from((inst_id, book): (&str, &okex_model::OrderBook)) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::Okex,
+            symbol: inst_id.to_string(),
+            bids: levels_f64(book.bids.iter().map(|level| (level.0, level.1)))?,
+            asks: levels_f64(book.asks.iter().map(|level| (level.0, level.1)))?,
+            time: Some(book.ts),
+        })
+    }
+}
+
+impl TryFrom<&binance_orderbook::LocalOrderBook> for L2Snapshot {
+    type Error = Error;
+
+    fn try_from(book: &binance_orderbook::LocalOrderBook) -> Result<Self> {
+        let (bids, asks) = book_levels(&book.bids, &book.asks);
+        Ok(Self {
+            exchange: match book.market {
+                binance_orderbook::DepthMarket::Spot => Exchange::BinanceSpot,
+                binance_orderbook::DepthMarket::USwap => Exchange::BinanceUSwap,
+            },
+            symbol: book.symbol.clone(),
+            bids,
+            asks,
+            time: (book.event_time > 0).then(|| millis(book.event_time)).transpose()?,
+        })
+    }
+}
+
+impl From<&okex_orderbook::LocalOrderBook> for L2Snapshot {
+    fn from(book: &okex_orderbook::LocalOrderBook) -> Self {
+        let (bids, asks) = book_levels(&book.bids, &book.asks);
+        Self {
+            exchange: Exchange::Okex,
+            symbol: book.inst_id.clone(),
+            bids,
+            asks,
+            time: Some(book.ts),
+        }
+    }
+}
+
+impl TryFrom<&huobi_orderbook::LocalOrderBook> for L2Snapshot {
+    type Error = Error;
+
+    fn try_from(book: &huobi_orderbook::LocalOrderBook) -> Result<Self> {
+        let (bids, asks) = book_levels(&book.bids, &book.asks);
+        Ok(Self {
+            exchange: Exchange::HuobiUSwap,
+            symbol: book.contract_code.clone(),
+            bids,
+            asks,
+            time: Some(millis(book.ts)?),
+        })
+    }
+}
+
+impl From<&ftx_model::Orderbook> for L2Snapshot {
+    fn from(book: &ftx_model::Orderbook) -> Self {
+        let (bids, asks) = book_levels(&book.bids, &book.asks);
+        Self {
+            exchange: Exchange::Ftx,
+            symbol: book.symbol.clone(),
+            bids,
+            asks,
+            time: None,
+        }
+    }
+}
+
+impl TryFrom<&binance_model::DepthOrderBookEvent> for L2Update {
+    type Error = Error;
+
+    fn try_from(event: &binance_model::DepthOrderBookEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceSpot,
+            symbol: event.symbol.clone(),
+            bids: levels_f64(event.bids.iter().map(|bid| (bid.price, bid.qty)))?,
+            asks: levels_f64(event.asks.iter().map(|ask| (ask.price, ask.qty)))?,
+            time: millis(event.event_time)?,
+        })
+    }
+}
+
+impl TryFrom<&binance_uswap_model::DepthOrderBookEvent> for L2Update {
+    type Error = Error;
+
+    fn try_from(event: &binance_uswap_model::DepthOrderBookEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceUSwap,
+            symbol: event.symbol.clone(),
+            bids: levels_f64(event.bids.iter().map(|bid| (bid.price, bid.qty)))?,
+            asks: levels_f64(event.asks.iter().map(|ask| (ask.price, ask.qty)))?,
+            time: millis(event.event_time)?,
+        })
+    }
+}
+
+impl TryFrom<&huobi_uswap_model::WSMarketResponse<huobi_uswap_model::IncrementalOrderBook>> for L2Update {
+    type Error = Error;
+
+    fn try_from(msg: &huobi_uswap_model::WSMarketResponse<huobi_uswap_model::IncrementalOrderBook>) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::HuobiUSwap,
+            symbol: huobi_symbol(&msg.ch),
+            bids: levels_f64(msg.tick.bids.iter().copied())?,
+            asks: levels_f64(msg.tick.asks.iter().copied())?,
+            time: millis(msg.tick.ts)?,
+        })
+    }
+}
+
+impl TryFrom<(&str, &okex_model::OrderBook)> for L2Update {
+    type Error = Error;
+
+    fn try_from((inst_id, book): (&str, &okex_model::OrderBook)) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::Okex,
+            symbol: inst_id.to_string(),
+            bids: levels_f64(book.bids.iter().map(|level| (level.0, level.1)))?,
+            asks: levels_f64(book.asks.iter().map(|level| (level.0, level.1)))?,
+            time: book.ts,
+        })
+    }
+}
+
+impl From<(&str, &ftx_model::OrderbookData)> for L2Update {
+    fn from((market, data): (&str, &ftx_model::OrderbookData)) -> Self {
+        Self {
+            exchange: Exchange::Ftx,
+            symbol: market.to_string(),
+            bids: data.bids.clone(),
+            asks: data.asks.clone(),
+            time: data.time,
+        }
+    }
+}
+
+impl From<(&str, &ftx_model::OrderbookData)> for L2Snapshot {
+    fn from((market, data): (&str, &ftx_model::OrderbookData)) -> Self {
+        Self {
+            exchange: Exchange::Ftx,
+            symbol: market.to_string(),
+            bids: data.bids.clone(),
+            asks: data.asks.clone(),
+            time: Some(data.time),
+        }
+    }
+}
+
+impl TryFrom<&binance_model::KlineEvent> for Kline {
+    type Error = Error;
+
+    fn try_from(event: &binance_model::KlineEvent) -> Result<Self> {
+        let kline = &event.kline;
+        Ok(Self {
+            exchange: Exchange::BinanceSpot,
+            symbol: event.symbol.clone(),
+            interval: kline.interval.clone(),
+            open_time: millis_i64(kline.start_time)?,
+            open: decimal(&kline.open)?,
+            high: decimal(&kline.high)?,
+            low: decimal(&kline.low)?,
+            close: decimal(&kline.close)?,
+            volume: decimal(&kline.volume)?,
+            closed: Some(kline.is_final_bar),
+        })
+    }
+}
+
+impl TryFrom<&binance_uswap_model::KlineEvent> for Kline {
+    type Error = Error;
+
+    fn try_from(event: &binance_uswap_model::KlineEvent) -> Result<Self> {
+        let kline = &event.kline;
+        Ok(Self {
+            exchange: Exchange::BinanceUSwap,
+            symbol: event.symbol.clone(),
+            interval: kline.interval.clone(),
+            open_time: millis_i64(kline.start_time)?,
+            open: decimal(&kline.open)?,
+            high: decimal(&kline.high)?,
+            low: decimal(&kline.low)?,
+            close: decimal(&kline.close)?,
+            volume: decimal(&kline.volume)?,
+            closed: Some(kline.is_final_bar),
+        })
+    }
+}
+
+impl TryFrom<&huobi_uswap_model::WSMarketResponse<huobi_uswap_model::Kline>> for Kline {
+    type Error = Error;
+
+    fn try_from(msg: &huobi_uswap_model::WSMarketResponse<huobi_uswap_model::Kline>) -> Result<Self> {
+        let kline = &msg.tick;
+        Ok(Self {
+            exchange: Exchange::HuobiUSwap,
+            symbol: huobi_symbol(&msg.ch),
+            interval: msg.ch.rsplit('.').next().unwrap_or_default().to_string(),
+            open_time: millis(kline.timestamp.saturating_mul(1000))?,
+            open: decimal_f64(kline.open)?,
+            high: decimal_f64(kline.high)?,
+            low: decimal_f64(kline.low)?,
+            close: decimal_f64(kline.close)?,
+            volume: decimal_f64(kline.volume)?,
+            closed: None,
+        })
+    }
+}
+
+impl TryFrom<&binance_model::DayTickerEvent> for Ticker {
+    type Error = Error;
+
+    fn try_from(event: &binance_model::DayTickerEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceSpot,
+            symbol: event.symbol.clone(),
+            last: decimal(&event.current_close)?,
+            open: decimal(&event.open)?,
+            high: decimal(&event.high)?,
+            low: decimal(&event.low)?,
+            volume: decimal(&event.volume)?,
+            quote_volume: decimal(&event.quote_volume)?,
+            time: millis(event.event_time)?,
+        })
+    }
+}
+
+impl TryFrom<&binance_uswap_model::DayTickerEvent> for Ticker {
+    type Error = Error;
+
+    fn try_from(event: &binance_uswap_model::DayTickerEvent) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceUSwap,
+            symbol: event.symbol.clone(),
+            last: decimal(&event.current_close)?,
+            open: decimal(&event.open)?,
+            high: decimal(&event.high)?,
+            low: decimal(&event.low)?,
+            volume: decimal(&event.volume)?,
+            quote_volume: decimal(&event.quote_volume)?,
+            time: millis(event.event_time)?,
+        })
+    }
+}
+
+impl From<&binance_model::OrderStatus> for OrderStatus {
+    fn from(status: &binance_model::OrderStatus) -> Self {
+        match status {
+            binance_model::OrderStatus::New => Self::New,
+            binance_model::OrderStatus::PartiallyFilled | binance_model::OrderStatus::Trade => Self::PartiallyFilled,
+            binance_model::OrderStatus::Filled => Self::Filled,
+            binance_model::OrderStatus::PendingCancel => Self::PendingCancel,
+            binance_model::OrderStatus::Canceled => Self::Canceled,
+            binance_model::OrderStatus::Rejected => Self::Rejected,
+            binance_model::OrderStatus::Expired => Self::Expired,
+        }
+    }
+}
+
+impl From<&binance_model::OrderSide> for Side {
+    fn from(side: &binance_model::OrderSide) -> Self {
+        match side {
+            binance_model::OrderSide::Buy => Self::Buy,
+            binance_model::OrderSide::Sell => Self::Sell,
+        }
+    }
+}
+
+impl TryFrom<&binance_model::OrderTradeEvent> for OrderUpdate {
+    type Error = Error;
+
+    fn try_from(event: &binance_model::OrderTradeEvent) -> Result<Self> {
+        let filled_quantity = decimal_f64(event.cumulative_filled_qty)?;
+        let quote_quantity = decimal_f64(event.cumulative_quote_asset_transacted_qty)?;
+        // a cancel carries the id of the cancel request in `c` and the one of the order in `C`.
+        let client_order_id = event.origin_client_id.as_deref()
+            .and_then(non_empty)
+            .or_else(|| non_empty(&event.client_order_id));
+        Ok(Self {
+            exchange: Exchange::BinanceSpot,
+            symbol: event.symbol.clone(),
+            order_id: event.order_id.to_string(),
+            client_order_id,
+            side: (&event.side).into(),
+            position_side: None,
+            status: (&event.current_order_status).into(),
+            price: positive(decimal_f64(event.price)?),
+            quantity: decimal_f64(event.qty)?,
+            filled_quantity,
+            average_price: positive(filled_quantity)
+                .and_then(|filled| quote_quantity.checked_div(filled)),
+            time: millis(event.trade_order_time)?,
+        })
+    }
+}
+
+/// `None` unless the execution type is `TRADE` and something was executed.
+impl TryFrom<&binance_model::OrderTradeEvent> for Option<Fill> {
+    type Error = Error;
+
+    fn try_from(event: &binance_model::OrderTradeEvent) -> Result<Self> {
+        if event.execution_type != binance_model::OrderStatus::Trade {
+            return Ok(None);
+        }
+        Ok(filled(Fill {
+            exchange: Exchange::BinanceSpot,
+            symbol: event.symbol.clone(),
+            order_id: event.order_id.to_string(),
+            client_order_id: non_empty(&event.client_order_id),
+            trade_id: event.trade_id.to_string(),
+            side: (&event.side).into(),
+            price: decimal_f64(event.last_executed_price)?,
+            quantity: decimal_f64(event.qty_last_executed)?,
+            fee: Some(decimal_f64(event.commission)?),
+            fee_asset: event.commission_asset.clone(),
+            // `m` is whether this side of the trade is the maker.
+            liquidity: Some(liquidity(event.is_buyer_maker)),
+            time: millis(event.trade_order_time)?,
+        }))
+    }
+}
+
+impl From<&binance_model::AccountUpdateEvent> for Vec<Result<BalanceUpdate>> {
+    fn from(event: &binance_model::AccountUpdateEvent) -> Self {
+        event.balances.iter()
+            .map(|balance| {
+                let available = decimal(&balance.free)?;
+                let locked = decimal(&balance.locked)?;
+                Ok(BalanceUpdate {
+                    exchange: Exchange::BinanceSpot,
+                    asset: balance.asset.clone(),
+                    balance: Some(available + locked),
+                    available: Some(available),
+                    locked: Some(locked),
+                    delta: None,
+                    time: millis(event.last_update_time)?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<&binance_model::BalanceUpdate> for BalanceUpdate {
+    type Error = Error;
+
+    fn try_from(event: &binance_model::BalanceUpdate) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::BinanceSpot,
+            asset: event.asset.clone(),
+            balance: None,
+            available: None,
+            locked: None,
+            delta: Some(decimal_f64(event.delta)?),
+            time: millis(event.clear_time)?,
+        })
+    }
+}
+
+fn binance_uswap_status(status: &str) -> OrderStatus {
+    match status {
+        "PARTIALLY_FILLED" => OrderStatus::PartiallyFilled,
+        "FILLED" => OrderStatus::Filled,
+        "CANCELED" => OrderStatus::Canceled,
+        "REJECTED" => OrderStatus::Rejected,
+        "EXPIRED" | "EXPIRED_IN_MATCH" => OrderStatus::Expired,
+        _ => OrderStatus::New,
+    }
+}
+
+impl TryFrom<&binance_uswap_model::OrderTradeEvent> for OrderUpdate {
+    type Error = Error;
+
+    fn try_from(event: &binance_uswap_model::OrderTradeEvent) -> Result<Self> {
+        let order = &event.order;
+        Ok(Self {
+            exchange: Exchange::BinanceUSwap,
+            symbol: order.symbol.clone(),
+            order_id: order.order_id.to_string(),
+            client_order_id: non_empty(&order.client_order_id),
+            side: side(&order.side)?,
+            position_side: position_side(&order.position_side),
+            status: binance_uswap_status(&order.order_status),
+            price: positive(decimal(&order.price)?),
+            quantity: decimal(&order.qty)?,
+            filled_quantity: decimal(&order.order_filled_accumulated_quantity)?,
+            average_price: positive(decimal(&order.avg_price)?),
+            time: millis(order.order_trade_time)?,
+        })
+    }
+}
+
+/// `None` unless the execution type is `TRADE`, or `CALCULATED` for liquidations, and
+/// something was executed. The commission is not part of the model, `fee` is `None`.
+impl TryFrom<&binance_uswap_model::OrderTradeEvent> for Option<Fill> {
+    type Error = Error;
+
+    fn try_from(event: &binance_uswap_model::OrderTradeEvent) -> Result<Self> {
+        let order = &event.order;
+        if order.execute_type != "TRADE" && order.execute_type != "CALCULATED" {
+            return Ok(None);
+        }
+        Ok(filled(Fill {
+            exchange: Exchange::BinanceUSwap,
+            symbol: order.symbol.clone(),
+            order_id: order.order_id.to_string(),
+            client_order_id: non_empty(&order.client_order_id),
+            trade_id: order.trade_id.to_string(),
+            side: side(&order.side)?,
+            price: decimal(&order.order_last_filled_price)?,
+            quantity: decimal(&order.order_last_filled_quantity)?,
+            fee: None,
+            fee_asset: None,
+            liquidity: Some(liquidity(order.is_buyer_maker)),
+            time: millis(order.order_trade_time)?,
+        }))
+    }
+}
+
+impl From<&binance_uswap_model::AccountUpdateEvent> for Vec<Result<BalanceUpdate>> {
+    fn from(event: &binance_uswap_model::AccountUpdateEvent) -> Self {
+        event.account_event.balance.iter()
+            .map(|balance| Ok(BalanceUpdate {
+                exchange: Exchange::BinanceUSwap,
+                asset: balance.asset.clone(),
+                balance: Some(decimal(&balance.balance)?),
+                available: None,
+                locked: None,
+                delta: Some(decimal(&balance.balance_change)?),
+                time: millis(event.event_time)?,
+            }))
+            .collect()
+    }
+}
+
+impl From<&binance_uswap_model::AccountUpdateEvent> for Vec<Result<PositionUpdate>> {
+    fn from(event: &binance_uswap_model::AccountUpdateEvent) -> Self {
+        event.account_event.position.iter()
+            .flatten()
+            .map(|position| Ok(PositionUpdate {
+                exchange: Exchange::BinanceUSwap,
+                symbol: position.asset.clone(),
+                side: position_side(&position.position_side).unwrap_or(PositionSide::Net),
+                quantity: decimal(&position.position_amount)?,
+                entry_price: positive(decimal(&position.entry_price)?),
+                unrealized_pnl: Some(decimal(&position.unrealized_pnl)?),
+                time: millis(event.event_time)?,
+            }))
+            .collect()
+    }
+}
+
+/// Huobi order status codes, 1 and 2 are orders being prepared.
+const fn huobi_status(status: u32) -> OrderStatus {
+    match status {
+        4 => OrderStatus::PartiallyFilled,
+        5 | 7 => OrderStatus::Canceled,
+        6 => OrderStatus::Filled,
+        11 => OrderStatus::PendingCancel,
+        _ => OrderStatus::New,
+    }
+}
+
+/// Opening buys and closing sells are on the long position, `both` is the offset of
+/// one-way mode.
+fn huobi_position_side(direction: &str, offset: &str) -> PositionSide {
+    match (direction, offset) {
+        (_, "both") => PositionSide::Net,
+        ("buy", "open") | ("sell", "close") => PositionSide::Long,
+        _ => PositionSide::Short,
+    }
+}
+
+fn huobi_fills(contract_code: &str, order_id: &str, client_order_id: Option<u64>, direction: &str, trades: &[huobi_uswap_model::TradeSubItem]) -> Result<Vec<Fill>> {
+    trades.iter()
+        .map(|trade| Ok(filled(Fill {
+            exchange: Exchange::HuobiUSwap,
+            symbol: contract_code.to_string(),
+            order_id: order_id.to_string(),
+            client_order_id: client_order_id.map(|id| id.to_string()),
+            trade_id: trade.id.clone(),
+            side: side(direction)?,
+            price: decimal_f64(trade.trade_price)?,
+            quantity: Decimal::from(trade.trade_volume),
+            // Huobi sends the fee negative.
+            fee: trade.trade_fee.map(decimal_f64).transpose()?.map(|fee| -fee),
+            fee_asset: trade.fee_asset.clone(),
+            liquidity: Some(liquidity(trade.role == "maker")),
+            time: millis(trade.created_at)?,
+        })))
+        .filter_map(Result::transpose)
+        .collect()
+}
+
+impl TryFrom<&huobi_uswap_model::OrderWSResponse> for OrderUpdate {
+    type Error = Error;
+
+    fn try_from(msg: &huobi_uswap_model::OrderWSResponse) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::HuobiUSwap,
+            symbol: msg.contract_code.clone(),
+            order_id: msg.order_id_str.clone(),
+            client_order_id: msg.client_order_id.map(|id| id.to_string()),
+            side: side(&msg.direction)?,
+            position_side: Some(huobi_position_side(&msg.direction, &msg.offset)),
+            status: huobi_status(msg.status),
+            price: positive(decimal_f64(msg.price)?),
+            quantity: Decimal::from(msg.volume),
+            filled_quantity: Decimal::from(msg.trade_volume),
+            average_price: positive(decimal_f64(msg.trade_avg_price)?),
+            time: millis(msg.ts)?,
+        })
+    }
+}
+
+impl TryFrom<&huobi_uswap_model::OrderWSResponse> for Vec<Fill> {
+    type Error = Error;
+
+    fn try_from(msg: &huobi_uswap_model::OrderWSResponse) -> Result<Self> {
+        huobi_fills(&msg.contract_code, &msg.order_id_str, msg.client_order_id, &msg.direction, &msg.trade)
+    }
+}
+
+/// `matchOrders` pushes carry no average price.
+impl TryFrom<&huobi_uswap_model::MatchOrderWSResponse> for OrderUpdate {
+    type Error = Error;
+
+    fn try_from(msg: &huobi_uswap_model::MatchOrderWSResponse) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::HuobiUSwap,
+            symbol: msg.contract_code.clone(),
+            order_id: msg.order_id_str.clone(),
+            client_order_id: msg.client_order_id.map(|id| id.to_string()),
+            side: side(&msg.direction)?,
+            position_side: Some(huobi_position_side(&msg.direction, &msg.offset)),
+            status: huobi_status(msg.status),
+            price: positive(decimal_f64(msg.price)?),
+            quantity: Decimal::from(msg.volume),
+            filled_quantity: Decimal::from(msg.trade_volume),
+            average_price: None,
+            time: millis(msg.ts)?,
+        })
+    }
+}
+
+impl TryFrom<&huobi_uswap_model::MatchOrderWSResponse> for Vec<Fill> {
+    type Error = Error;
+
+    fn try_from(msg: &huobi_uswap_model::MatchOrderWSResponse) -> Result<Self> {
+        huobi_fills(&msg.contract_code, &msg.order_id_str, msg.client_order_id, &msg.direction, &msg.trade)
+    }
+}
+
+impl From<&huobi_uswap_model::WSAccountResponse<Vec<huobi_uswap_model::Account>>> for Vec<Result<BalanceUpdate>> {
+    fn from(msg: &huobi_uswap_model::WSAccountResponse<Vec<huobi_uswap_model::Account>>) -> Self {
+        msg.data.iter()
+            .map(|account| Ok(BalanceUpdate {
+                exchange: Exchange::HuobiUSwap,
+                asset: account.margin_asset.clone(),
+                balance: Some(decimal_f64(account.margin_balance)?),
+                available: Some(decimal_f64(account.margin_available)?),
+                locked: Some(decimal_f64(account.margin_frozen)?),
+                delta: None,
+                time: millis(msg.ts)?,
+            }))
+            .collect()
+    }
+}
+
+impl From<&huobi_uswap_model::WSAccountResponse<Vec<huobi_uswap_model::Position>>> for Vec<Result<PositionUpdate>> {
+    fn from(msg: &huobi_uswap_model::WSAccountResponse<Vec<huobi_uswap_model::Position>>) -> Self {
+        msg.data.iter()
+            .map(|position| Ok(PositionUpdate {
+                exchange: Exchange::HuobiUSwap,
+                symbol: position.contract_code.clone(),
+                side: if position.direction == "sell" { PositionSide::Short } else { PositionSide::Long },
+                quantity: decimal_f64(position.volume)?,
+                entry_price: positive(decimal_f64(position.cost_open)?),
+                unrealized_pnl: Some(decimal_f64(position.profit_unreal)?),
+                time: millis(msg.ts)?,
+            }))
+            .collect()
+    }
+}
+
+impl From<okex_model::OrdState> for OrderStatus {
+    fn from(state: okex_model::OrdState) -> Self {
+        match state {
+            okex_model::OrdState::Live => Self::New,
+            okex_model::OrdState::PartiallyFilled => Self::PartiallyFilled,
+            okex_model::OrdState::Filled => Self::Filled,
+            okex_model::OrdState::Canceled => Self::Canceled,
+        }
+    }
+}
+
+impl TryFrom<&okex_model::Order> for OrderUpdate {
+    type Error = Error;
+
+    fn try_from(order: &okex_model::Order) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::Okex,
+            symbol: order.inst_id.clone(),
+            order_id: order.ord_id.clone(),
+            client_order_id: order.cl_ord_id.clone(),
+            side: side(&order.side)?,
+            position_side: position_side(&order.pos_side),
+            status: order.state.into(),
+            price: positive(decimal_f64(order.px)?),
+            quantity: decimal_f64(order.sz)?,
+            filled_quantity: decimal_f64(order.acc_fill_sz)?,
+            average_price: optional_decimal(&order.avg_px)?.and_then(positive),
+            time: order.u_time,
+        })
+    }
+}
+
+/// `None` unless the update is a trade of the order.
+impl TryFrom<&okex_model::Order> for Option<Fill> {
+    type Error = Error;
+
+    fn try_from(order: &okex_model::Order) -> Result<Self> {
+        if order.trade_id.is_empty() {
+            return Ok(None);
+        }
+        let Some(price) = order.fill_px else {
+            return Ok(None);
+        };
+        Ok(filled(Fill {
+            exchange: Exchange::Okex,
+            symbol: order.inst_id.clone(),
+            order_id: order.ord_id.clone(),
+            client_order_id: order.cl_ord_id.clone(),
+            trade_id: order.trade_id.clone(),
+            side: side(&order.side)?,
+            price: decimal_f64(price)?,
+            quantity: decimal_f64(order.fill_sz)?,
+            // OKX sends the fee negative.
+            fee: Some(-decimal_f64(order.fill_fee)?),
+            fee_asset: order.fill_fee_ccy.clone(),
+            liquidity: order.exec_type.map(|exec_type| match exec_type {
+                okex_model::ExecType::M => Liquidity::Maker,
+                okex_model::ExecType::T => Liquidity::Taker,
+            }),
+            time: millis_str(&order.fill_time)?,
+        }))
+    }
+}
+
+impl From<&okex_model::BalanceData> for Vec<Result<BalanceUpdate>> {
+    fn from(data: &okex_model::BalanceData) -> Self {
+        data.details.iter()
+            .map(|detail| Ok(BalanceUpdate {
+                exchange: Exchange::Okex,
+                asset: detail.ccy.clone(),
+                balance: optional_decimal(&detail.cash_bal)?,
+                available: optional_decimal(&detail.avail_bal)?,
+                locked: optional_decimal(&detail.frozen_bal)?,
+                delta: None,
+                time: millis_str(&detail.u_time)?,
+            }))
+            .collect()
+    }
+}
+
+impl TryFrom<&okex_model::PositionData> for PositionUpdate {
+    type Error = Error;
+
+    fn try_from(position: &okex_model::PositionData) -> Result<Self> {
+        Ok(Self {
+            exchange: Exchange::Okex,
+            symbol: position.inst_id.clone(),
+            side: position_side(&position.pos_side).unwrap_or(PositionSide::Net),
+            quantity: decimal(&position.pos)?,
+            entry_price: optional_decimal(&position.avg_px)?.and_then(positive),
+            unrealized_pnl: optional_decimal(&position.upl)?,
+            time: millis_str(&position.u_time)?,
+        })
+    }
+}
+
+impl From<&okex_model::BalancePositionData> for Vec<Result<BalanceUpdate>> {
+    fn from(data: &okex_model::BalancePositionData) -> Self {
+        data.bal_data.iter()
+            .flatten()
+            .map(|balance| Ok(BalanceUpdate {
+                exchange: Exchange::Okex,
+                asset: balance.ccy.clone(),
+                balance: optional_decimal(&balance.cash_bal)?,
+                available: None,
+                locked: None,
+                delta: None,
+                time: balance.u_time,
+            }))
+            .collect()
+    }
+}
+
+impl From<&okex_model::BalancePositionData> for Vec<Result<PositionUpdate>> {
+    fn from(data: &okex_model::BalancePositionData) -> Self {
+        data.pos_data.iter()
+            .flatten()
+            .map(|position| Ok(PositionUpdate {
+                exchange: Exchange::Okex,
+                symbol: position.inst_id.clone(),
+                side: position_side(&position.pos_side).unwrap_or(PositionSide::Net),
+                quantity: decimal(&position.pos)?,
+                entry_price: optional_decimal(&position.avg_px)?.and_then(positive),
+                unrealized_pnl: None,
+                time: position.u_time,
+            }))
+            .collect()
+    }
+}
+
+/// FTX only tells open from closed orders, a closed order that did not fill completely was
+/// canceled or rejected and is reported as `Canceled`.
+impl TryFrom<&ftx_model::OrderInfo> for OrderUpdate {
+    type Error = Error;
+
+    fn try_from(order: &ftx_model::OrderInfo) -> Result<Self> {
+        let status = match order.status {
+            ftx_model::OrderStatus::Closed if order.remaining_size <= 0.0 && order.filled_size > 0.0 => OrderStatus::Filled,
+            ftx_model::OrderStatus::Closed => OrderStatus::Canceled,
+            ftx_model::OrderStatus::New | ftx_model::OrderStatus::Open if order.filled_size > 0.0 => OrderStatus::PartiallyFilled,
+            ftx_model::OrderStatus::New | ftx_model::OrderStatus::Open => OrderStatus::New,
+        };
+        Ok(Self {
+            exchange: Exchange::Ftx,
+            symbol: order.market.clone(),
+            order_id: order.id.to_string(),
+            client_order_id: order.client_id.clone(),
+            side: order.side.into(),
+            position_side: None,
+            status,
+            price: order.price.map(decimal_f64).transpose()?,
+            quantity: decimal_f64(order.size)?,
+            filled_quantity: decimal_f64(order.filled_size)?,
+            average_price: order.avg_fill_price.map(decimal_f64).transpose()?,
+            // FTX sends no time of the update.
+            time: order.created_at,
+        })
+    }
+}
+
+/// `None` for fills of size 0.
+impl From<&ftx_model::Fill> for Option<Fill> {
+    fn from(fill: &ftx_model::Fill) -> Self {
+        filled(Fill {
+            exchange: Exchange::Ftx,
+            symbol: fill.market.clone(),
+            order_id: fill.order_id.to_string(),
+            client_order_id: None,
+            trade_id: fill.trade_id.to_string(),
+            side: fill.side.into(),
+            price: fill.price,
+            quantity: fill.size,
+            fee: Some(fill.fee),
+            fee_asset: Some(fill.fee_currency.clone()),
+            liquidity: Some(match fill.liquidity {
+                ftx_model::Liquidity::Maker => Liquidity::Maker,
+                ftx_model::Liquidity::Taker => Liquidity::Taker,
+            }),
+            time: fill.time,
+        })
+    }
+}
+
+/// The fills of an order update first, then the new state of the order. An order that cannot
+/// be converted yields its error alone.
+fn order_events(order: Result<OrderUpdate>, fills: Result<impl IntoIterator<Item = Fill>>) -> Vec<Result<NormalizedEvent>> {
+    match (order, fills) {
+        (Ok(order), Ok(fills)) => fills.into_iter()
+            .map(NormalizedEvent::Fill)
+            .chain(std::iter::once(NormalizedEvent::OrderUpdate(order)))
+            .map(Ok)
+            .collect(),
+        (Err(e), _) | (_, Err(e)) => vec![Err(e)],
+    }
+}
+
+/// The event of each converted item.
+fn each<T>(items: Vec<Result<T>>, event: fn(T) -> NormalizedEvent) -> impl Iterator<Item = Result<NormalizedEvent>> {
+    items.into_iter().map(move |item| item.map(event))
+}
+
+/// FTX market data, by the `market` of the response, and account events.
+fn ftx_events(rsp: &ftx_model::Response) -> Vec<Result<NormalizedEvent>> {
+    let market = rsp.market.as_deref().unwrap_or_default();
+    match rsp.data {
+        Some(ftx_model::ResponseData::Trades(ref trades)) => trades.iter()
+            .map(|trade| Ok(NormalizedEvent::Trade((market, trade).into())))
+            .collect(),
+        Some(ftx_model::ResponseData::Ticker(ref ticker)) => vec![Ok(NormalizedEvent::BookTicker((market, ticker).into()))],
+        Some(ftx_model::ResponseData::OrderbookData(ref data)) => vec![Ok(match data.action {
+            ftx_model::OrderbookAction::Partial => NormalizedEvent::L2Snapshot((market, data).into()),
+            ftx_model::OrderbookAction::Update => NormalizedEvent::L2Update((market, data).into()),
+        })],
+        Some(ftx_model::ResponseData::Fill(ref fill)) => Option::<Fill>::from(fill).into_iter().map(|fill| Ok(NormalizedEvent::Fill(fill))).collect(),
+        Some(ftx_model::ResponseData::Order(ref order)) => vec![order.try_into().map(NormalizedEvent::OrderUpdate)],
+        _ => Vec::new(),
+    }
+}
+
+/// OKX books, the action tells snapshots from updates.
+fn okex_books(rsp: &okex_model::WsRsp<okex_model::OrderBook>) -> Vec<Result<NormalizedEvent>> {
+    let inst_id = rsp.arg.inst_id().unwrap_or_default();
+    rsp.data.iter()
+        .map(|book| match rsp.action {
+            Some(okex_model::Action::Update) => (inst_id, book).try_into().map(NormalizedEvent::L2Update),
+            Some(okex_model::Action::Snapshot) | None => (inst_id, book).try_into().map(NormalizedEvent::L2Snapshot),
+        })
+        .collect()
+}
+
+impl WebsocketEvent {
+    /// Converts the market data or account events carried by this event, an empty `Vec` for
+    /// everything else. Some events hold several trades, books or balances, hence the `Vec`.
+    ///
+    /// Order updates that report executions yield their `Fill`s before the `OrderUpdate`. Huobi
+    /// sends the trades both on `orders` and `matchOrders`, subscribe to one of them to get
+    /// each fill once.
+    ///
+    /// Each item is converted on its own: one with a value that is not valid yields its error
+    /// in place of its events, and the others are still converted.
+    #[must_use]
+    pub fn normalized(&self) -> Vec<Result<NormalizedEvent>> {
+        match self {
+            Self::BinanceSpotTrade(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::Trade)],
+            Self::BinanceSpotAggrTrades(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::Trade)],
+            Self::BinanceSpotBookTicker(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::BookTicker)],
+            Self::BinanceSpotOrderBook(msg) => vec![msg.try_into().map(NormalizedEvent::L2Snapshot)],
+            Self::BinanceSpotDepthOrderBook(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::L2Update)],
+            Self::BinanceSpotKline(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::Kline)],
+            Self::BinanceSpotDayTicker(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::Ticker)],
+            Self::BinanceSpotDayTickerAll(msg) => msg.data.iter()
+                .map(|ticker| ticker.try_into().map(NormalizedEvent::Ticker))
+                .collect(),
+            Self::BinanceSpotOrderTrade(msg) => order_events((&msg.data).try_into(), Option::<Fill>::try_from(&msg.data)),
+            Self::BinanceSpotAccountUpdate(msg) => each((&msg.data).into(), NormalizedEvent::BalanceUpdate).collect(),
+            Self::BinanceSpotBalanceUpdate(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::BalanceUpdate)],
+
+            Self::BinanceUSwapTradeEvent(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::Trade)],
+            Self::BinanceUSwapAggrTradesEvent(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::Trade)],
+            Self::BinanceUSwapBookTickerEvent(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::BookTicker)],
+            Self::BinanceUSwapOrderBook(msg) => vec![msg.try_into().map(NormalizedEvent::L2Snapshot)],
+            Self::BinanceUSwapDepthOrderBookEvent(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::L2Update)],
+            Self::BinanceUSwapKlineEvent(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::Kline)],
+            Self::BinanceUSwapDayTickerEvent(msg) => vec![(&msg.data).try_into().map(NormalizedEvent::Ticker)],
+            Self::BinanceUSwapVec(msg) => msg.data.iter()
+                .map(|ticker| ticker.try_into().map(NormalizedEvent::Ticker))
+                .collect(),
+            Self::BinanceUSwapOrderTradeEvent(event) => order_events(event.try_into(), Option::<Fill>::try_from(event)),
+            Self::BinanceUSwapAccountUpdateEvent(event) => each(event.into(), NormalizedEvent::BalanceUpdate)
+                .chain(each(event.into(), NormalizedEvent::PositionUpdate))
+                .collect(),
+
+            Self::HuobiUSwapTradeDetail(msg) => each(msg.into(), NormalizedEvent::Trade).collect(),
+            Self::HuobiUSwapBBO(msg) => vec![msg.try_into().map(NormalizedEvent::BookTicker)],
+            Self::HuobiUSwapOrderBook(msg) => vec![msg.try_into().map(NormalizedEvent::L2Snapshot)],
+            Self::HuobiUSwapIncrementalOrderBook(msg) if msg.tick.event == "snapshot" => vec![msg.try_into().map(NormalizedEvent::L2Snapshot)],
+            Self::HuobiUSwapIncrementalOrderBook(msg) => vec![msg.try_into().map(NormalizedEvent::L2Update)],
+            Self::HuobiUSwapKline(msg) => vec![msg.try_into().map(NormalizedEvent::Kline)],
+            Self::HuobiUSwapOrder(msg) => order_events(msg.try_into(), Vec::<Fill>::try_from(msg)),
+            Self::HuobiUSwapMatchOrder(msg) => order_events(msg.try_into(), Vec::<Fill>::try_from(msg)),
+            Self::HuobiUSwapAccount(msg) => each(msg.into(), NormalizedEvent::BalanceUpdate).collect(),
+            Self::HuobiUSwapPosition(msg) => each(msg.into(), NormalizedEvent::PositionUpdate).collect(),
+
+            Self::OkexTrade(msg) => msg.data.iter()
+                .map(|trade| trade.try_into().map(NormalizedEvent::Trade))
+                .collect(),
+            Self::OkexOrderBook(msg) => okex_books(msg),
+            Self::OkexOrder(msg) => msg.data.iter()
+                .flat_map(|order| order_events(order.try_into(), Option::<Fill>::try_from(order)))
+                .collect(),
+            Self::OkexAccount(msg) => msg.data.iter()
+                .flat_map(|data| each(data.into(), NormalizedEvent::BalanceUpdate))
+                .collect(),
+            Self::OkexPosition(msg) => msg.data.iter()
+                .map(|position| position.try_into().map(NormalizedEvent::PositionUpdate))
+                .collect(),
+            Self::OkexAccountPosition(msg) => msg.data.iter()
+                .flat_map(|data| each(data.into(), NormalizedEvent::BalanceUpdate).chain(each(data.into(), NormalizedEvent::PositionUpdate)))
+                .collect(),
+
+            Self::FtxRsp(msg) => ftx_events(msg),
+
+            Self::BinanceLocalOrderBook(book) => vec![book.try_into().map(NormalizedEvent::L2Snapshot)],
+            Self::OkexLocalOrderBook(book) => vec![Ok(NormalizedEvent::L2Snapshot(book.into()))],
+            Self::HuobiUSwapLocalOrderBook(book) => vec![book.try_into().map(NormalizedEvent::L2Snapshot)],
+            Self::FtxLocalOrderBook(book) => vec![Ok(NormalizedEvent::L2Snapshot(book.into()))],
+
+            Self::Connection(event) => vec![Ok(NormalizedEvent::Connection(event.clone()))],
+            Self::Unparsed { subscription, raw, error } => vec![Ok(NormalizedEvent::Unparsed {
+                subscription: subscription.clone(),
+                raw: raw.clone(),
+                error: error.clone(),
+            })],
+
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRADE: &str = r#"{"e":"trade","E":1672531200123,"s":"BTCUSDT","t":2483920157,"p":"16530.10000000","q":"0.25000000","b":18472356201,"a":18472356198,"T":1672531200120,"m":true,"M":true}"#;
+
+    fn trade(raw: &str) -> binance_model::TradeEvent {
+        serde_json::from_str(raw).unwrap()
+    }
+
+    const ORDER_TRADE: &str = r#"{"e":"executionReport","E":1672531200123,"s":"BTCUSDT","c":"mUvoqJxFIILMdfAW5iGSOW","S":"BUY","o":"LIMIT","f":"GTC","q":"0.25000000","p":"16530.10000000","P":"0.00000000","F":"0.00000000","g":-1,"C":"","x":"TRADE","X":"PARTIALLY_FILLED","r":"NONE","i":18472356201,"l":"0.10000000","z":"0.10000000","L":"16530.10000000","n":"0.00010000","N":"BNB","T":1672531200120,"t":2483920157,"I":38472310920,"w":false,"m":false,"M":true,"O":1672531199870,"Z":"1653.01000000","Y":"1653.01000000","Q":"0.00000000"}"#;
+    const OKEX_ORDER: &str = r#"{"instType":"SWAP","instId":"BTC-USDT-SWAP","ccy":"","ordId":"312269865356374016","clOrdId":"b1","tag":"","px":"16530.1","sz":"25","notionalUsd":"4132.525","ordType":"limit","side":"buy","posSide":"long","tdMode":"cross","tgtCcy":"","fillSz":"10","fillPx":"16530.1","tradeId":"130639474","accFillSz":"10","fillNotionalUsd":"1653.01","fillTime":"1672531200120","fillFee":"-0.826505","fillFeeCcy":"USDT","execType":"T","source":"","state":"partially_filled","avgPx":"16530.1","lever":"5","tpTriggerPx":"","tpTriggerPxType":"","tpOrdPx":"","slTriggerPx":"","slTriggerPxType":"","slOrdPx":"","feeCcy":"USDT","fee":"-0.826505","rebateCcy":"USDT","rebate":"0","pnl":"0","category":"normal","uTime":"1672531200120","cTime":"1672531199870","reqId":"","amendResult":"","code":"0","msg":""}"#;
+
+    #[test]
+    fn valid_values_convert() {
+        let trade = Trade::try_from(&trade(TRADE)).unwrap();
+        assert_eq!(trade.price, Decimal::from_str("16530.1").unwrap());
+        assert_eq!(trade.time.timestamp_millis(), 1_672_531_200_120);
+    }
+
+    #[test]
+    fn invalid_values_are_not_converted() {
+        let bad_price = trade(&TRADE.replace("16530.10000000", "n/a"));
+        assert!(matches!(Trade::try_from(&bad_price), Err(Error::InvalidNumber { .. })));
+
+        let bad_time = trade(&TRADE.replace("1672531200120", "18446744073709551615"));
+        assert!(matches!(Trade::try_from(&bad_time), Err(Error::InvalidTime { .. })));
+
+        assert!(decimal_f64(f64::NAN).is_err());
+    }
+    #[test]
+    fn executions_of_nothing_are_not_fills() {
+        let order: binance_model::OrderTradeEvent = serde_json::from_str(ORDER_TRADE).unwrap();
+        let fill = Option::<Fill>::try_from(&order).unwrap().unwrap();
+        assert_eq!(fill.quantity, Decimal::from_str("0.1").unwrap());
+
+        let nothing: binance_model::OrderTradeEvent = serde_json::from_str(&ORDER_TRADE.replace(r#""l":"0.10000000""#, r#""l":"0.00000000""#)).unwrap();
+        assert!(Option::<Fill>::try_from(&nothing).unwrap().is_none());
+    }
+
+    #[test]
+    fn private_values_are_checked() {
+        let unfilled: okex_model::Order = serde_json::from_str(&OKEX_ORDER.replace(r#""avgPx":"16530.1""#, r#""avgPx":"""#)).unwrap();
+        assert_eq!(OrderUpdate::try_from(&unfilled).unwrap().average_price, None);
+
+        let bad_time: okex_model::Order = serde_json::from_str(&OKEX_ORDER.replace(r#""fillTime":"1672531200120""#, r#""fillTime":"soon""#)).unwrap();
+        assert!(matches!(Option::<Fill>::try_from(&bad_time), Err(Error::InvalidTime { .. })));
+
+        let bad_side: okex_model::Order = serde_json::from_str(&OKEX_ORDER.replace(r#""side":"buy""#, r#""side":"hold""#)).unwrap();
+        assert!(matches!(OrderUpdate::try_from(&bad_side), Err(Error::InvalidSide { .. })));
+    }
+
+    #[test]
+    fn items_are_converted_one_by_one() {
+        let raw = r#"{"arg":{"channel":"trades","instId":"BTC-USDT"},"data":[{"instId":"BTC-USDT","tradeId":"130639474","px":"NaN","sz":"0.25","side":"buy","ts":"1672531200120","count":"1"},{"instId":"BTC-USDT","tradeId":"130639475","px":"16530.1","sz":"0.25","side":"buy","ts":"1672531200120","count":"1"}]}"#;
+        let event = WebsocketEvent::OkexTrade(serde_json::from_str(raw).unwrap());
+
+        let events = event.normalized();
+        assert!(matches!(events[0], Err(Error::InvalidNumber { .. })));
+        assert!(matches!(&events[1], Ok(NormalizedEvent::Trade(trade)) if trade.trade_id == "130639475"));
+    }
+}
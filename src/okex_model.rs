@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use crate::parser::ts_milliseconds;
 use chrono::{DateTime, Utc};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
 use serde_json::Value;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -110,6 +111,21 @@ impl Channel {
             inst_id: inst_id.into(),
         }
     }
+
+    #[must_use]
+    pub fn inst_id(&self) -> Option<&str> {
+        match self {
+            Self::Books { inst_id }
+            | Self::Books5 { inst_id }
+            | Self::Books50L2Tbt { inst_id }
+            | Self::BooksL2Tbt { inst_id }
+            | Self::PriceLimit { inst_id }
+            | Self::Tickers { inst_id }
+            | Self::Trades { inst_id } => Some(inst_id),
+            Self::Orders { inst_id, .. } => inst_id.as_deref(),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -164,6 +180,38 @@ pub struct OrderBook {
     pub checksum: i64,
 }
 
+/// Same as `OrderBook`, with prices and sizes kept exactly as sent since the checksum is
+/// computed over their text. Levels are `(price, size, liquidated orders, orders)`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BookData {
+    pub asks: Vec<(Decimal, Decimal, String, String)>,
+    pub bids: Vec<(Decimal, Decimal, String, String)>,
+    #[serde(deserialize_with = "ts_milliseconds")]
+    pub ts: DateTime<Utc>,
+    pub checksum: i64,
+}
+
+impl WsRsp<BookData> {
+    /// The message as `OkexOrderBook` carries it, `None` if an order count is not a number.
+    #[must_use]
+    pub fn order_book(&self) -> Option<WsRsp<OrderBook>> {
+        let levels = |levels: &[(Decimal, Decimal, String, String)]| {
+            levels.iter()
+                .map(|(price, size, liquidated, orders)| Some(DepthInfo(
+                    price.to_f64()?,
+                    size.to_f64()?,
+                    liquidated.parse().ok()?,
+                    orders.parse().ok()?,
+                )))
+                .collect::<Option<Vec<_>>>()
+        };
+        let data = self.data.iter()
+            .map(|data| Some(OrderBook { asks: levels(&data.asks)?, bids: levels(&data.bids)?, ts: data.ts, checksum: data.checksum }))
+            .collect::<Option<Vec<_>>>()?;
+        Some(WsRsp { arg: self.arg.clone(), action: self.action.clone(), data })
+    }
+}
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -0,0 +1,292 @@
+//! Local order books built from the OKX `books` channels (`books50-l2-tbt` as subscribed by
+//! `OkexMarketStream`, `books`, `books-l2-tbt`).
+//!
+//! The first message of a channel is a snapshot, the following ones are updates. OKX sends a
+//! CRC32 of the top 25 levels with every message, the book is checked against it after each
+//! one and the channel is subscribed again on a mismatch, which starts over with a snapshot.
+use crate::{
+    models::{ConnectionId, WebsocketEvent},
+    okex_model::{Action, BookData, Channel, WsRsp},
+    websocket::Websocket,
+};
+use chrono::{DateTime, Utc};
+use crc32fast::Hasher;
+use crate::error::Result;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use tracing::warn;
+
+/// Number of levels per side covered by the checksum.
+const CHECKSUM_DEPTH: usize = 25;
+
+#[derive(Debug, Clone)]
+pub struct LocalOrderBook {
+    pub inst_id: String,
+    /// Time of the last applied message.
+    pub ts: DateTime<Utc>,
+    pub bids: BTreeMap<Decimal, Decimal>,
+    pub asks: BTreeMap<Decimal, Decimal>,
+}
+
+impl LocalOrderBook {
+    #[must_use]
+    pub fn new(inst_id: &str, ts: DateTime<Utc>) -> Self {
+        Self {
+            inst_id: inst_id.to_string(),
+            ts,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        }
+    }
+
+    /// Applies the levels of an update, a size of 0 removes the level.
+    pub fn apply(&mut self, data: &BookData) {
+        for (price, size, _, _) in &data.bids {
+            if *size == dec!(0) {
+                self.bids.remove(price);
+            } else {
+                self.bids.insert(*price, *size);
+            }
+        }
+        for (price, size, _, _) in &data.asks {
+            if *size == dec!(0) {
+                self.asks.remove(price);
+            } else {
+                self.asks.insert(*price, *size);
+            }
+        }
+        self.ts = data.ts;
+    }
+
+    /// CRC32 of the top 25 levels, alternating bids and asks as `price:size`, as a signed
+    /// 32 bit integer like the `checksum` sent by OKX.
+    #[must_use]
+    pub fn checksum(&self) -> i64 {
+        let mut fields: Vec<String> = Vec::with_capacity(4 * CHECKSUM_DEPTH);
+
+        let mut bids_iter = self.bids.iter().rev();
+        let mut asks_iter = self.asks.iter();
+
+        for _ in 0..CHECKSUM_DEPTH {
+            if let Some((price, size)) = bids_iter.next() {
+                fields.push(price.to_string());
+                fields.push(size.to_string());
+            }
+            if let Some((price, size)) = asks_iter.next() {
+                fields.push(price.to_string());
+                fields.push(size.to_string());
+            }
+        }
+
+        let mut hasher = Hasher::new();
+        hasher.update(fields.join(":").as_bytes());
+        let hash = hasher.finalize();
+        i64::from(i32::from_ne_bytes(hash.to_ne_bytes()))
+    }
+
+    /// Returns the price of the best bid
+    #[must_use]
+    pub fn bid_price(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().copied()
+    }
+
+    /// Returns the price of the best ask
+    #[must_use]
+    pub fn ask_price(&self) -> Option<Decimal> {
+        self.asks.keys().next().copied()
+    }
+
+    /// Returns the midpoint between the best bid price and best ask price.
+    /// Output is not rounded to the smallest price increment.
+    #[must_use]
+    pub fn mid_price(&self) -> Option<Decimal> {
+        Some((self.bid_price()? + self.ask_price()?) / dec!(2))
+    }
+
+    /// Returns the price and quantity of the best bid
+    /// `(bid_price, bid_quantity)`
+    #[must_use]
+    pub fn best_bid(&self) -> Option<(Decimal, Decimal)> {
+        let (price, quantity) = self.bids.iter().next_back()?;
+
+        Some((*price, *quantity))
+    }
+
+    /// Returns the price and quantity of the best ask
+    /// `(ask_price, ask_quantity)`
+    #[must_use]
+    pub fn best_ask(&self) -> Option<(Decimal, Decimal)> {
+        let (price, quantity) = self.asks.iter().next()?;
+
+        Some((*price, *quantity))
+    }
+}
+
+/// Outcome of applying a message to `OrderBooks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookSync {
+    /// The book changed and matches the checksum.
+    Updated,
+    /// An update for a book without snapshot, dropped.
+    Ignored,
+    /// The book does not match the checksum and was dropped.
+    ChecksumMismatch { expected: i64, computed: i64 },
+}
+
+/// Why a local book was dropped and is being synchronized again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResyncReason {
+    ChecksumMismatch { expected: i64, computed: i64 },
+    /// The connection of the market stream was lost.
+    Disconnected,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResyncEvent {
+    pub inst_id: String,
+    pub reason: ResyncReason,
+}
+
+/// The local books of one connection, by `instId`.
+#[derive(Debug, Default)]
+pub struct OrderBooks {
+    books: HashMap<String, LocalOrderBook>,
+}
+
+impl OrderBooks {
+    #[must_use]
+    pub fn book(&self, inst_id: &str) -> Option<&LocalOrderBook> {
+        self.books.get(inst_id)
+    }
+
+    /// Drops every book, returning their `instId`s.
+    pub fn clear(&mut self) -> Vec<String> {
+        self.books.drain().map(|(inst_id, _)| inst_id).collect()
+    }
+
+    /// Applies a snapshot (or a message without action) or an update to the book of `inst_id`.
+    pub fn apply(&mut self, inst_id: &str, action: Option<&Action>, data: &BookData) -> BookSync {
+        let book = match action {
+            Some(Action::Update) => match self.books.get_mut(inst_id) {
+                Some(book) => book,
+                None => return BookSync::Ignored,
+            },
+            Some(Action::Snapshot) | None => {
+                let book = self.books.entry(inst_id.to_string())
+                    .or_insert_with(|| LocalOrderBook::new(inst_id, data.ts));
+                book.bids.clear();
+                book.asks.clear();
+                book
+            }
+        };
+
+        book.apply(data);
+
+        let computed = book.checksum();
+        if computed != data.checksum {
+            self.books.remove(inst_id);
+            return BookSync::ChecksumMismatch { expected: data.checksum, computed };
+        }
+        BookSync::Updated
+    }
+}
+
+impl Websocket {
+    pub(crate) async fn on_okex_book(&mut self, connection: &ConnectionId, rsp: WsRsp<BookData>) -> Result<()> {
+        let Some(inst_id) = rsp.arg.inst_id().map(ToString::to_string) else {
+            return Ok(());
+        };
+
+        for data in &rsp.data {
+            let Some(books) = self.okex_books.get_mut(connection) else {
+                return Ok(());
+            };
+
+            match books.apply(&inst_id, rsp.action.as_ref(), data) {
+                BookSync::Updated => {
+                    if let Some(book) = books.book(&inst_id).cloned() {
+                        self.emit(connection, WebsocketEvent::OkexLocalOrderBook(book));
+                    }
+                }
+                BookSync::ChecksumMismatch { expected, computed } => {
+                    warn!("[Websocket] okex book {} checksum mismatch: expected {}, computed {}", inst_id, expected, computed);
+                    self.emit(connection, WebsocketEvent::OkexOrderBookResync(ResyncEvent {
+                        inst_id,
+                        reason: ResyncReason::ChecksumMismatch { expected, computed },
+                    }));
+                    self.okex_resubscribe(connection, &rsp.arg).await?;
+                    // the rest of the message belongs to the dropped book.
+                    return Ok(());
+                }
+                BookSync::Ignored => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the books of `connection` after its connection was lost, the new connection
+    /// starts them over from a snapshot.
+    pub(crate) fn reset_okex_books(&mut self, connection: &ConnectionId) {
+        let Some(books) = self.okex_books.get_mut(connection) else {
+            return;
+        };
+
+        for inst_id in books.clear() {
+            self.emit(connection, WebsocketEvent::OkexOrderBookResync(ResyncEvent {
+                inst_id,
+                reason: ResyncReason::Disconnected,
+            }));
+        }
+    }
+
+    /// Unsubscribes and subscribes `channel` again, OKX answers with a new snapshot.
+    async fn okex_resubscribe(&mut self, connection: &ConnectionId, channel: &Channel) -> Result<()> {
+        for op in ["unsubscribe", "subscribe"] {
+            let message = json!({
+                "op": op,
+                "args": [channel],
+            });
+            self.send(connection, message.to_string()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(bids: &[(&str, &str)], asks: &[(&str, &str)], checksum: i64) -> BookData {
+        let levels = |levels: &[(&str, &str)]| levels.iter()
+            .map(|(price, size)| (price.parse().unwrap(), size.parse().unwrap(), "0".to_string(), "1".to_string()))
+            .collect();
+        BookData { bids: levels(bids), asks: levels(asks), ts: Utc::now(), checksum }
+    }
+
+    // the two examples of the OKX docs, "Checksum" of the order book channel.
+    #[test]
+    fn checksum_matches_the_okex_samples() {
+        let mut book = LocalOrderBook::new("BTC-USDT", Utc::now());
+        book.apply(&data(&[("3366.1", "7"), ("3366", "6")], &[("3366.8", "9"), ("3368", "8")], 0));
+        assert_eq!(book.checksum(), -1_881_014_294);
+
+        let mut book = LocalOrderBook::new("BTC-USDT", Utc::now());
+        book.apply(&data(&[("3366.1", "7")], &[("3366.8", "9"), ("3368", "8"), ("3372", "8")], 0));
+        assert_eq!(book.checksum(), 831_078_360);
+    }
+
+    #[test]
+    fn mismatch_drops_the_book() {
+        let mut books = OrderBooks::default();
+        let snapshot = data(&[("3366.1", "7"), ("3366", "6")], &[("3366.8", "9"), ("3368", "8")], -1_881_014_294);
+        assert_eq!(books.apply("BTC-USDT", Some(&Action::Snapshot), &snapshot), BookSync::Updated);
+
+        let update = data(&[("3366", "0")], &[], 12345);
+        assert!(matches!(books.apply("BTC-USDT", Some(&Action::Update), &update), BookSync::ChecksumMismatch { expected: 12345, .. }));
+        assert!(books.book("BTC-USDT").is_none());
+        assert_eq!(books.apply("BTC-USDT", Some(&Action::Update), &update), BookSync::Ignored);
+    }
+}
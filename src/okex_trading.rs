@@ -0,0 +1,328 @@
+//! Order entry on the OKX private connection.
+//!
+//! The `OkexOrderStream` connection is logged in, `Websocket::okex_trading` returns a handle
+//! sending the trading ops on it: `order`, `batch-orders`, `cancel-order`,
+//! `batch-cancel-orders`, `amend-order` and `mass-cancel`. Each returns a future of the
+//! answer, see `rpc` for how answers are delivered. OKX refuses requests sent before the
+//! login is acknowledged.
+//!
+//! Every order of an answer carries its own `sCode` and `sMsg`, a refused order is an
+//! `Error::RequestRejected` with them. Batches answer with one result per order, in the
+//! order they were sent, and only fail as a whole when OKX refuses the request itself.
+use crate::{
+    error::{Error, Result},
+    models::{ConnectionId, Subscription},
+    okex_model::{InstType, OrdType, PosSide, Side, TdMode},
+    rpc::{ResponseFuture, RpcSender},
+    websocket::{decode, Websocket},
+};
+use chrono::{DateTime, TimeZone, Utc};
+use rust_decimal::Decimal;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+/// An order to place, the `args` of `order` and `batch-orders`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaceOrder {
+    pub inst_id: String,
+    pub td_mode: TdMode,
+    pub side: Side,
+    /// Required in long/short position mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pos_side: Option<PosSide>,
+    pub ord_type: OrdType,
+    pub sz: Decimal,
+    /// Not used by market orders.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub px: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cl_ord_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reduce_only: Option<bool>,
+    /// Margin currency of cross margin orders on margin accounts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ccy: Option<String>,
+}
+
+impl PlaceOrder {
+    #[must_use]
+    pub fn limit(inst_id: &str, td_mode: TdMode, side: Side, sz: Decimal, px: Decimal) -> Self {
+        Self { px: Some(px), ..Self::new(inst_id, td_mode, side, OrdType::Limit, sz) }
+    }
+
+    #[must_use]
+    pub fn market(inst_id: &str, td_mode: TdMode, side: Side, sz: Decimal) -> Self {
+        Self::new(inst_id, td_mode, side, OrdType::Market, sz)
+    }
+
+    #[must_use]
+    pub fn new(inst_id: &str, td_mode: TdMode, side: Side, ord_type: OrdType, sz: Decimal) -> Self {
+        Self {
+            inst_id: inst_id.to_string(),
+            td_mode,
+            side,
+            pos_side: None,
+            ord_type,
+            sz,
+            px: None,
+            cl_ord_id: None,
+            tag: None,
+            reduce_only: None,
+            ccy: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_pos_side(mut self, pos_side: PosSide) -> Self {
+        self.pos_side = Some(pos_side);
+        self
+    }
+
+    #[must_use]
+    pub fn with_cl_ord_id(mut self, cl_ord_id: &str) -> Self {
+        self.cl_ord_id = Some(cl_ord_id.to_string());
+        self
+    }
+}
+
+/// An order to cancel, by `ord_id` or `cl_ord_id`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelOrder {
+    pub inst_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ord_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cl_ord_id: Option<String>,
+}
+
+impl CancelOrder {
+    #[must_use]
+    pub fn by_ord_id(inst_id: &str, ord_id: &str) -> Self {
+        Self { inst_id: inst_id.to_string(), ord_id: Some(ord_id.to_string()), cl_ord_id: None }
+    }
+
+    #[must_use]
+    pub fn by_cl_ord_id(inst_id: &str, cl_ord_id: &str) -> Self {
+        Self { inst_id: inst_id.to_string(), ord_id: None, cl_ord_id: Some(cl_ord_id.to_string()) }
+    }
+}
+
+/// A change of the size or price of an order, by `ord_id` or `cl_ord_id`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AmendOrder {
+    pub inst_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ord_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cl_ord_id: Option<String>,
+    /// The new total size, including what already filled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_sz: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_px: Option<Decimal>,
+    /// Cancel the order if the amendment fails.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cxl_on_fail: Option<bool>,
+    /// Identifies the amendment in the `orders` channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub req_id: Option<String>,
+}
+
+impl AmendOrder {
+    #[must_use]
+    pub fn by_ord_id(inst_id: &str, ord_id: &str) -> Self {
+        Self {
+            inst_id: inst_id.to_string(),
+            ord_id: Some(ord_id.to_string()),
+            cl_ord_id: None,
+            new_sz: None,
+            new_px: None,
+            cxl_on_fail: None,
+            req_id: None,
+        }
+    }
+
+    #[must_use]
+    pub fn by_cl_ord_id(inst_id: &str, cl_ord_id: &str) -> Self {
+        Self { ord_id: None, cl_ord_id: Some(cl_ord_id.to_string()), ..Self::by_ord_id(inst_id, "") }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MassCancel {
+    inst_type: InstType,
+    inst_family: String,
+}
+
+/// An order OKX accepted. Empty fields of the answer are `None`.
+#[derive(Debug, Clone)]
+pub struct OrderAck {
+    pub ord_id: String,
+    pub cl_ord_id: Option<String>,
+    pub tag: Option<String>,
+    /// Set by `amend-order`.
+    pub req_id: Option<String>,
+    pub ts: Option<DateTime<Utc>>,
+}
+
+/// One order of an answer.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrderData {
+    #[serde(default)]
+    ord_id: String,
+    #[serde(default)]
+    cl_ord_id: String,
+    #[serde(default)]
+    tag: String,
+    #[serde(default)]
+    req_id: String,
+    #[serde(default)]
+    ts: String,
+    s_code: String,
+    s_msg: String,
+}
+
+#[derive(Deserialize)]
+struct MassCancelData {
+    result: bool,
+}
+
+/// `{"id":"1","op":"order","code":"0","msg":"","data":[...]}`.
+#[derive(Deserialize)]
+struct TradeResponse<T> {
+    code: String,
+    msg: String,
+    #[serde(default = "Vec::new")]
+    data: Vec<T>,
+}
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+impl OrderData {
+    fn into_ack(self, subscription: &str) -> Result<OrderAck> {
+        if self.s_code != "0" {
+            return Err(Error::RequestRejected { subscription: subscription.to_string(), code: self.s_code, msg: self.s_msg });
+        }
+        let ts = self.ts.parse().ok().and_then(|ms| Utc.timestamp_millis_opt(ms).single());
+        Ok(OrderAck {
+            ord_id: self.ord_id,
+            cl_ord_id: non_empty(self.cl_ord_id),
+            tag: non_empty(self.tag),
+            req_id: non_empty(self.req_id),
+            ts,
+        })
+    }
+}
+
+/// Places, amends and cancels orders on one `OkexOrderStream` connection, see
+/// `Websocket::okex_trading`. It can be cloned and used from other tasks.
+#[derive(Debug, Clone)]
+pub struct OkexTrading {
+    rpc: RpcSender,
+}
+
+impl OkexTrading {
+    /// `order`.
+    #[must_use]
+    pub fn place_order(&self, order: &PlaceOrder) -> ResponseFuture<OrderAck> {
+        self.single("order", order)
+    }
+
+    /// `batch-orders`, up to 20 orders.
+    #[must_use]
+    pub fn place_orders(&self, orders: &[PlaceOrder]) -> ResponseFuture<Vec<Result<OrderAck>>> {
+        self.batch("batch-orders", orders)
+    }
+
+    /// `cancel-order`.
+    #[must_use]
+    pub fn cancel_order(&self, order: &CancelOrder) -> ResponseFuture<OrderAck> {
+        self.single("cancel-order", order)
+    }
+
+    /// `batch-cancel-orders`, up to 20 orders.
+    #[must_use]
+    pub fn cancel_orders(&self, orders: &[CancelOrder]) -> ResponseFuture<Vec<Result<OrderAck>>> {
+        self.batch("batch-cancel-orders", orders)
+    }
+
+    /// `amend-order`.
+    #[must_use]
+    pub fn amend_order(&self, order: &AmendOrder) -> ResponseFuture<OrderAck> {
+        self.single("amend-order", order)
+    }
+
+    /// `mass-cancel`, the orders of `inst_type` (only `Option` is accepted by OKX) and
+    /// `inst_family`, e.g. `BTC-USD`.
+    #[must_use]
+    pub fn mass_cancel(&self, inst_type: InstType, inst_family: &str) -> ResponseFuture<()> {
+        let args = MassCancel { inst_type, inst_family: inst_family.to_string() };
+        let answer = self.call::<_, MassCancelData>("mass-cancel", std::slice::from_ref(&args));
+        let subscription = self.rpc.connection.to_string();
+        Box::pin(async move {
+            match answer.await?.first() {
+                Some(data) if data.result => Ok(()),
+                _ => Err(Error::RequestRejected { subscription, code: String::new(), msg: "mass-cancel failed".to_string() }),
+            }
+        })
+    }
+
+    fn single<A: Serialize>(&self, op: &str, args: &A) -> ResponseFuture<OrderAck> {
+        let answer = self.call::<_, OrderData>(op, std::slice::from_ref(args));
+        let subscription = self.rpc.connection.to_string();
+        Box::pin(async move {
+            let data = answer.await?.into_iter().next().ok_or_else(|| Error::RequestRejected {
+                subscription: subscription.clone(),
+                code: String::new(),
+                msg: "no order in the answer".to_string(),
+            })?;
+            data.into_ack(&subscription)
+        })
+    }
+
+    fn batch<A: Serialize>(&self, op: &str, args: &[A]) -> ResponseFuture<Vec<Result<OrderAck>>> {
+        let answer = self.call::<_, OrderData>(op, args);
+        let subscription = self.rpc.connection.to_string();
+        Box::pin(async move {
+            Ok(answer.await?.into_iter().map(|data| data.into_ack(&subscription)).collect())
+        })
+    }
+
+    /// Sends `op` and resolves to the `data` of the answer. Fails if OKX refused the request
+    /// without answering for any order.
+    fn call<A: Serialize, T: DeserializeOwned + Send + 'static>(&self, op: &str, args: &[A]) -> ResponseFuture<Vec<T>> {
+        let id = self.rpc.next_id();
+        let message = json!({ "id": id, "op": op, "args": args }).to_string();
+        let answer = self.rpc.send(id, message);
+        let subscription = self.rpc.connection.to_string();
+
+        Box::pin(async move {
+            let rsp: TradeResponse<T> = decode(&answer.await?)?;
+            if rsp.code != "0" && rsp.data.is_empty() {
+                return Err(Error::RequestRejected { subscription, code: rsp.code, msg: rsp.msg });
+            }
+            Ok(rsp.data)
+        })
+    }
+}
+
+impl Websocket {
+    /// Returns a handle trading on `connection`, an `OkexOrderStream` connection. Open the
+    /// connection before sending.
+    pub fn okex_trading(&self, connection: impl Into<ConnectionId>) -> Result<OkexTrading> {
+        let connection = connection.into();
+        match connection.subscription {
+            Subscription::OkexOrderStream => Ok(OkexTrading { rpc: self.rpc_sender(connection) }),
+            _ => Err(Error::NoRequests { subscription: connection.to_string() }),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Backoff schedule used when a single connection drops and has to be re-established.
+///
+/// The delay doubles on every failed attempt, starting at `initial_delay` and capped at
+/// `max_delay`. Half of each delay is randomized ("equal jitter") so that several
+/// connections dropped by the same network event do not reconnect in lockstep.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Give up on a connection after this many failed attempts, `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Returns the delay to wait before the given attempt, attempts are counted from 1.
+    #[must_use]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt.saturating_sub(1));
+        let ceiling = self.initial_delay.saturating_mul(factor).min(self.max_delay);
+        let half = ceiling / 2;
+        let half_ms = u64::try_from(half.as_millis()).unwrap_or(u64::MAX);
+        half + Duration::from_millis(rand::thread_rng().gen_range(0..=half_ms))
+    }
+
+    /// Returns `true` once `attempt` exceeds the configured number of attempts.
+    #[must_use]
+    pub fn exhausted(&self, attempt: u32) -> bool {
+        self.max_attempts.is_some_and(|max| attempt > max)
+    }
+}
@@ -0,0 +1,377 @@
+//! Records the raw frames of every connection to disk.
+//!
+//! A `Recorder` given to `Websocket::set_recorder` receives every frame read from or written
+//! to a socket, with its connection, direction and local time. Huobi frames are recorded
+//! gunzipped. The depth snapshots of the Binance local books are recorded with the frames,
+//! so that a replay needs no network. The frames are written by a thread of their own as one
+//! JSON object per line, `RecordedFrame`, to a gzip file per UTC day named
+//! `<prefix>-<YYYY-MM-DD>.ndjson.gz`.
+//!
+//! The receive loop never waits for the disk: frames go through a bounded buffer and are
+//! dropped, and counted in `RecorderStats::dropped`, when it is full. A file written again
+//! after a restart gets a new gzip member appended, `zcat` and `flate2::read::MultiGzDecoder`
+//! read all of them.
+//!
+//! The credentials of the frames sent, the API key, signature and passphrase of the login
+//! and auth requests and of the Binance API requests, are recorded as `REDACTED`.
+use crate::{
+    error::{Error, Result},
+    models::{ConnectionId, Subscription},
+    websocket::Websocket,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use tracing::{error, warn};
+
+/// Recorded in place of the credentials of a frame sent.
+pub const REDACTED: &str = "<redacted>";
+
+/// The fields of the frames sent that carry credentials: OKX and FTX `op: login`, Huobi
+/// `op: auth` and the Binance API requests.
+const CREDENTIALS: [&str; 7] = ["apiKey", "passphrase", "sign", "key", "signature", "AccessKeyId", "Signature"];
+
+/// Whether a frame was read from or written to the socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "in")]
+    Inbound,
+    #[serde(rename = "out")]
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameKind {
+    Text,
+    /// The payload is gunzipped, or the bytes as UTF-8 if they are not gzip.
+    Binary,
+    Ping,
+    Pong,
+    /// The connection was lost, the payload is the reason: the close frame of the peer, or
+    /// the error.
+    Close,
+    /// A depth snapshot of a Binance local book, fetched by the client rather than read from
+    /// the socket. The payload is the symbol and the snapshot as JSON.
+    Snapshot,
+}
+
+/// One line of a recording.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// When the frame was read, the `received_at` of its events, or written.
+    pub ts: DateTime<Utc>,
+    #[serde(with = "subscription_name")]
+    pub subscription: Subscription,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub direction: Direction,
+    pub kind: FrameKind,
+    pub payload: String,
+}
+
+impl RecordedFrame {
+    /// The connection the frame was read or written on.
+    #[must_use]
+    pub fn connection(&self) -> ConnectionId {
+        ConnectionId { subscription: self.subscription.clone(), account: self.account.clone() }
+    }
+}
+
+/// `Subscription` is untagged, the recording spells out its name.
+mod subscription_name {
+    use crate::models::Subscription;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(subscription: &Subscription, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{subscription:?}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Subscription, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Where and how a `Recorder` writes.
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    /// Created if missing.
+    pub dir: PathBuf,
+    pub prefix: String,
+    /// Frames waiting for the writer, more are dropped.
+    pub capacity: usize,
+    /// How often the file is flushed while frames keep coming, bounding what a crash loses.
+    pub flush_interval: Duration,
+}
+
+impl RecorderConfig {
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            prefix: "frames".to_string(),
+            capacity: 65536,
+            flush_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    recorded: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// The counters of a `Recorder`, readable from any thread.
+#[derive(Debug, Clone, Default)]
+pub struct RecorderStats {
+    counters: Arc<Counters>,
+}
+
+impl RecorderStats {
+    /// Frames written to the file.
+    #[must_use]
+    pub fn recorded(&self) -> u64 {
+        self.counters.recorded.load(Ordering::Relaxed)
+    }
+
+    /// Frames lost because the buffer was full or the file could not be written.
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.counters.dropped.load(Ordering::Relaxed)
+    }
+
+    fn drop_one(&self) {
+        self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Hands frames to the writer thread, see the module documentation.
+#[derive(Debug)]
+pub struct Recorder {
+    frames: SyncSender<RecordedFrame>,
+    stats: RecorderStats,
+    writer: JoinHandle<()>,
+}
+
+impl Recorder {
+    /// Creates the directory and starts the writer thread.
+    pub fn start(config: RecorderConfig) -> Result<Self> {
+        let path = config.dir.display().to_string();
+        std::fs::create_dir_all(&config.dir).map_err(|source| Error::Record { path: path.clone(), source })?;
+
+        let (frames, received) = mpsc::sync_channel(config.capacity);
+        let stats = RecorderStats::default();
+        let mut writer = Writer { config, stats: stats.clone(), day: None, file: None, failing: false };
+        let writer = std::thread::Builder::new()
+            .name("frame-recorder".to_string())
+            .spawn(move || writer.run(&received))
+            .map_err(|source| Error::Record { path, source })?;
+
+        Ok(Self { frames, stats, writer })
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> RecorderStats {
+        self.stats.clone()
+    }
+
+    /// Queues `frame`, or drops it if the buffer is full.
+    pub fn record(&self, frame: RecordedFrame) {
+        match self.frames.try_send(frame) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => self.stats.drop_one(),
+        }
+    }
+
+    /// Writes the frames still buffered and completes the file. Dropping the recorder does
+    /// the same in the background, which may not finish before the process exits.
+    pub fn finish(self) {
+        drop(self.frames);
+        if self.writer.join().is_err() {
+            error!("[Recorder] the writer thread panicked");
+        }
+    }
+}
+
+type DayFile = GzEncoder<BufWriter<File>>;
+
+struct Writer {
+    config: RecorderConfig,
+    stats: RecorderStats,
+    day: Option<NaiveDate>,
+    file: Option<DayFile>,
+    /// Set while writing fails, to log the failure once.
+    failing: bool,
+}
+
+impl Writer {
+    fn run(&mut self, frames: &mpsc::Receiver<RecordedFrame>) {
+        let mut flushed = Instant::now();
+        loop {
+            match frames.recv_timeout(self.config.flush_interval) {
+                Ok(frame) => self.write(&frame),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if flushed.elapsed() >= self.config.flush_interval {
+                if let Some(Err(e)) = self.file.as_mut().map(Write::flush) {
+                    warn!("[Recorder] cannot flush the recording: {}", e);
+                }
+                flushed = Instant::now();
+            }
+        }
+        if let Err(e) = self.close() {
+            error!("[Recorder] cannot complete the recording: {}", e);
+        }
+    }
+
+    fn write(&mut self, frame: &RecordedFrame) {
+        match self.try_write(frame) {
+            Ok(()) => {
+                self.failing = false;
+                self.stats.counters.recorded.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                if !self.failing {
+                    error!("[Recorder] cannot write to {}: {}", self.config.dir.display(), e);
+                }
+                self.failing = true;
+                self.stats.drop_one();
+            }
+        }
+    }
+
+    fn try_write(&mut self, frame: &RecordedFrame) -> io::Result<()> {
+        let day = frame.ts.naive_utc().date();
+        if self.day != Some(day) {
+            self.close()?;
+            self.day = Some(day);
+        }
+        let file = match self.file.take() {
+            Some(file) => file,
+            None => self.open(day)?,
+        };
+        let file = self.file.insert(file);
+        serde_json::to_writer(&mut *file, frame)?;
+        file.write_all(b"\n")
+    }
+
+    fn open(&self, day: NaiveDate) -> io::Result<DayFile> {
+        let path = self.config.dir.join(format!("{}-{}.ndjson.gz", self.config.prefix, day.format("%Y-%m-%d")));
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(GzEncoder::new(BufWriter::new(file), Compression::default()))
+    }
+
+    /// Completes the file of the current day.
+    fn close(&mut self) -> io::Result<()> {
+        match self.file.take() {
+            Some(file) => file.finish()?.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Websocket {
+    /// Records every frame of every connection with `recorder` from now on, replacing the
+    /// recorder in use, see `recorder`.
+    pub const fn set_recorder(&mut self, recorder: Recorder) -> Option<Recorder> {
+        self.recorder.replace(recorder)
+    }
+
+    /// Stops recording. Call `Recorder::finish` on the recorder to complete its file.
+    pub const fn take_recorder(&mut self) -> Option<Recorder> {
+        self.recorder.take()
+    }
+
+    /// Records a frame of `connection` if a recorder is set. Frames read are stamped with
+    /// the receive time of the loop.
+    pub(crate) fn record(&self, connection: &ConnectionId, direction: Direction, kind: FrameKind, payload: &str) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+        let ts = match direction {
+            Direction::Inbound => self.received.1,
+            Direction::Outbound => Utc::now(),
+        };
+        recorder.record(RecordedFrame {
+            ts,
+            subscription: connection.subscription.clone(),
+            account: connection.account.clone(),
+            direction,
+            kind,
+            payload: match (direction, kind) {
+                (Direction::Outbound, FrameKind::Text) => redact(payload),
+                _ => payload.to_string(),
+            },
+        });
+    }
+}
+
+/// `payload` with the values of its `CREDENTIALS` replaced by `REDACTED`, unchanged if it is
+/// not JSON or carries none.
+fn redact(payload: &str) -> String {
+    fn redact_value(value: &mut serde_json::Value) -> bool {
+        let mut redacted = false;
+        match value {
+            serde_json::Value::Object(fields) => {
+                for (name, value) in fields {
+                    if CREDENTIALS.contains(&name.as_str()) && !value.is_object() && !value.is_array() {
+                        *value = serde_json::Value::from(REDACTED);
+                        redacted = true;
+                    } else {
+                        redacted |= redact_value(value);
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    redacted |= redact_value(value);
+                }
+            }
+            _ => {}
+        }
+        redacted
+    }
+
+    let Ok(mut value) = serde_json::from_str::<serde_json::Value>(payload) else {
+        return payload.to_string();
+    };
+    if redact_value(&mut value) { value.to_string() } else { payload.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_are_redacted() {
+        let okex = r#"{"op":"login","args":[{"apiKey":"k","passphrase":"p","timestamp":"1","sign":"s"}]}"#;
+        assert_eq!(redact(okex), r#"{"args":[{"apiKey":"<redacted>","passphrase":"<redacted>","sign":"<redacted>","timestamp":"1"}],"op":"login"}"#);
+        let ftx = r#"{"op":"login","args":{"key":"k","sign":"s","time":1,"subaccount":"a"}}"#;
+        assert_eq!(redact(ftx), r#"{"args":{"key":"<redacted>","sign":"<redacted>","subaccount":"a","time":1},"op":"login"}"#);
+        let huobi = r#"{"op":"auth","type":"api","AccessKeyId":"k","Signature":"s","Timestamp":"t"}"#;
+        assert!(!redact(huobi).contains("\"k\"") && !redact(huobi).contains("\"s\""));
+        let binance = r#"{"id":1,"method":"account.status","params":{"apiKey":"k","signature":"s","timestamp":"1"}}"#;
+        assert!(!redact(binance).contains("\"k\"") && !redact(binance).contains("\"s\""));
+    }
+
+    #[test]
+    fn other_frames_are_unchanged() {
+        let subscribe = r#"{"op":"subscribe","args":[{"channel":"books","instId":"BTC-USDT"}]}"#;
+        assert_eq!(redact(subscribe), subscribe);
+        assert_eq!(redact("ping"), "ping");
+    }
+}
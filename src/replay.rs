@@ -0,0 +1,194 @@
+//! Replays recorded frames through the receive loop.
+//!
+//! `Websocket::replay` hands the frames read in a recording, see `recorder`, to the same
+//! parsing, local books and handler as live frames, in recorded order, and returns at the
+//! end of the recording. `replay_envelopes` yields the events instead. No connection is
+//! opened: the frames sent are skipped, and so is whatever the client would send in answer,
+//! like pongs or subscriptions after a login. A recorded close resets the connection like a
+//! lost one and delivers `Disconnected`, without reconnecting.
+//!
+//! The events carry the recorded time as `Envelope::received_at` and are the same on every
+//! replay. The Binance local books start from the depth snapshots in the recording, the
+//! `SnapshotProvider` is not asked for any, so a book whose snapshot was not recorded is
+//! never synchronized.
+use crate::{
+    binance_orderbook::RecordedSnapshot,
+    error::{self, Error, Result},
+    models::{ConnectionState, Envelope},
+    recorder::{Direction, FrameKind, RecordedFrame},
+    websocket::{decode, Websocket},
+};
+use chrono::{DateTime, Utc};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use futures::{prelude::*, stream::BoxStream};
+use snafu::ResultExt;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    time::Duration,
+};
+use tokio::time::Instant;
+use tungstenite::Message;
+
+/// How fast a `Replay` goes.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReplaySpeed {
+    /// With the gaps between frames as recorded.
+    Recorded,
+    /// `Accelerated(10.0)` replays ten times faster than recorded.
+    Accelerated(f64),
+    /// Without waiting between frames.
+    #[default]
+    Unpaced,
+}
+
+/// The frames of a recording, read as they are replayed.
+pub struct Replay {
+    frames: Box<dyn Iterator<Item = Result<RecordedFrame>> + Send>,
+    speed: ReplaySpeed,
+    /// The recorded time of the first frame and when it was replayed.
+    origin: Option<(DateTime<Utc>, Instant)>,
+}
+
+impl Replay {
+    /// Opens a file written by a `Recorder`, or the same decompressed if `path` does not end
+    /// in `.gz`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let file = File::open(path).context(error::ReplaySnafu { path: name.clone() })?;
+        let reader: Box<dyn Read + Send> = if path.extension().is_some_and(|ext| ext == "gz") {
+            Box::new(MultiGzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+
+        let frames = BufReader::new(reader).lines()
+            .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .map(move |line| {
+                let line = line.context(error::ReplaySnafu { path: name.clone() })?;
+                decode::<RecordedFrame>(&line)
+            });
+        Ok(Self::from_results(frames))
+    }
+
+    /// Replays `frames`, in that order.
+    pub fn from_frames<I>(frames: I) -> Self
+    where
+        I: IntoIterator<Item = RecordedFrame>,
+        I::IntoIter: Send + 'static,
+    {
+        Self::from_results(frames.into_iter().map(Ok))
+    }
+
+    fn from_results(frames: impl Iterator<Item = Result<RecordedFrame>> + Send + 'static) -> Self {
+        Self { frames: Box::new(frames), speed: ReplaySpeed::default(), origin: None }
+    }
+
+    /// `ReplaySpeed::Unpaced` by default.
+    #[must_use]
+    pub const fn with_speed(mut self, speed: ReplaySpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// When the frame recorded at `ts` is due, `None` if it is due now.
+    fn deadline(&mut self, ts: DateTime<Utc>) -> Option<Instant> {
+        let factor = match self.speed {
+            ReplaySpeed::Recorded => 1.0,
+            ReplaySpeed::Accelerated(factor) => factor,
+            ReplaySpeed::Unpaced => return None,
+        };
+        let (first, start) = *self.origin.get_or_insert_with(|| (ts, Instant::now()));
+        let recorded = (ts - first).to_std().unwrap_or_default();
+        let scaled = Duration::try_from_secs_f64(recorded.as_secs_f64() / factor).ok()?;
+        Some(start + scaled)
+    }
+}
+
+impl std::fmt::Debug for Replay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Replay")
+            .field("speed", &self.speed)
+            .field("origin", &self.origin)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Compresses a recorded Huobi payload back into the frame it was received as.
+fn gzip(payload: &str) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(payload.as_bytes()).context(error::GzipSnafu)?;
+    encoder.finish().context(error::GzipSnafu)
+}
+
+impl Websocket {
+    /// Hands the events of `replay` to the handler, like `connect` does with live frames.
+    /// Returns at the end of the recording, or with the first error of the recording or the
+    /// handler. Frames that cannot be decoded are handled according to the `DecodePolicy`.
+    pub async fn replay(&mut self, mut replay: Replay) -> Result<()> {
+        while let Some(envelope) = self.next_replayed(&mut replay).await {
+            (self.handler)(envelope?)?;
+        }
+        Ok(())
+    }
+
+    /// `replay` as a stream of the events, which ends with the recording.
+    pub fn replay_envelopes(&mut self, replay: Replay) -> BoxStream<'_, Result<Envelope>> {
+        stream::unfold((self, replay), |(ws, mut replay)| async move {
+            let envelope = ws.next_replayed(&mut replay).await?;
+            Some((envelope, (ws, replay)))
+        }).boxed()
+    }
+
+    async fn next_replayed(&mut self, replay: &mut Replay) -> Option<Result<Envelope>> {
+        loop {
+            if let Some(envelope) = self.deliver() {
+                return Some(Ok(envelope));
+            }
+
+            let frame = match replay.frames.next()? {
+                Ok(frame) => frame,
+                Err(e) => return Some(Err(e)),
+            };
+            if frame.direction == Direction::Outbound {
+                continue;
+            }
+            if let Some(deadline) = replay.deadline(frame.ts) {
+                tokio::time::sleep_until(deadline).await;
+            }
+            if let Err(e) = self.replay_frame(frame).await {
+                return Some(Err(e));
+            }
+        }
+    }
+
+    async fn replay_frame(&mut self, frame: RecordedFrame) -> Result<()> {
+        let connection = frame.connection();
+        self.received = (std::time::Instant::now(), frame.ts);
+        let message = match frame.kind {
+            FrameKind::Text => Message::Text(frame.payload),
+            FrameKind::Binary => Message::Binary(gzip(&frame.payload)?),
+            FrameKind::Ping => Message::Ping(frame.payload.into_bytes()),
+            FrameKind::Pong => Message::Pong(frame.payload.into_bytes()),
+            FrameKind::Close => {
+                self.reset_connection(&connection);
+                self.emit_connection_state(&connection, ConnectionState::Disconnected(frame.payload));
+                return Ok(());
+            }
+            FrameKind::Snapshot => {
+                let recorded: RecordedSnapshot = decode(&frame.payload)?;
+                self.replaying = true;
+                self.on_depth_snapshot(connection, recorded.symbol, Ok(recorded.snapshot));
+                self.replaying = false;
+                return Ok(());
+            }
+        };
+
+        self.replaying = true;
+        let handled = self.handle_message(connection.clone(), message).await;
+        self.replaying = false;
+        handled.or_else(|e: Error| self.on_decode_error(&connection, e))
+    }
+}
@@ -0,0 +1,110 @@
+//! Requests answered on a websocket, matched with their answer by id.
+//!
+//! Trading handles like `BinanceApi` send their requests through a channel to the receive
+//! loop, which writes them to the socket of their connection and hands the answer carrying
+//! the same id back. The answers only arrive while the loop runs, through `connect`, the
+//! event stream or `next_event`. Requests in flight when their connection drops fail with
+//! `Error::NotConnected`, they are not sent again after reconnecting.
+use crate::{
+    error::{Error, Result},
+    models::ConnectionId,
+    websocket::Websocket,
+};
+use futures::prelude::*;
+use serde::Deserialize;
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use tracing::warn;
+
+/// The typed answer to a request.
+pub type ResponseFuture<T> = Pin<Box<dyn Future<Output = Result<T>> + Send>>;
+
+/// Receives the raw answer to a request.
+pub(crate) type Responder = oneshot::Sender<Result<String>>;
+
+#[derive(Debug)]
+pub(crate) struct RpcRequest {
+    connection: ConnectionId,
+    id: String,
+    message: String,
+    responder: Responder,
+}
+
+#[derive(Deserialize)]
+struct IdProbe {
+    id: Option<String>,
+}
+
+/// Sends the requests of one connection, shared by the trading handles.
+#[derive(Debug, Clone)]
+pub(crate) struct RpcSender {
+    pub connection: ConnectionId,
+    requests: UnboundedSender<RpcRequest>,
+    ids: Arc<AtomicU64>,
+}
+
+impl RpcSender {
+    /// A request id, unique for the client.
+    pub fn next_id(&self) -> String {
+        self.ids.fetch_add(1, Ordering::Relaxed).to_string()
+    }
+
+    /// Queues `message`, which carries `id`, and resolves to the raw answer.
+    pub fn send(&self, id: String, message: String) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (responder, answer) = oneshot::channel();
+        let request = RpcRequest { connection: self.connection.clone(), id, message, responder };
+        let sent = self.requests.send(request).map_err(|_| Error::WebsocketDropped);
+        async move {
+            sent?;
+            answer.await.map_err(|_| Error::WebsocketDropped)?
+        }
+    }
+}
+
+impl Websocket {
+    pub(crate) fn rpc_sender(&self, connection: ConnectionId) -> RpcSender {
+        RpcSender { connection, requests: self.rpc_tx.clone(), ids: self.rpc_ids.clone() }
+    }
+
+    pub(crate) async fn on_rpc_request(&mut self, request: RpcRequest) {
+        let RpcRequest { connection, id, message, responder } = request;
+        if !self.sinks.contains_key(&connection) {
+            let _ = responder.send(Err(Error::NotConnected { subscription: connection.to_string() }));
+            return;
+        }
+
+        self.rpc_pending.entry(connection.clone()).or_default().insert(id, responder);
+        if let Err(e) = self.send(&connection, message).await {
+            self.connection_lost(&connection, e.to_string());
+        }
+    }
+
+    /// Hands `raw` to the request it answers. Returns `false` if it answers none.
+    pub(crate) fn on_rpc_answer(&mut self, connection: &ConnectionId, raw: &str) -> bool {
+        let Some(pending) = self.rpc_pending.get_mut(connection).filter(|pending| !pending.is_empty()) else {
+            return false;
+        };
+        let Some(id) = serde_json::from_str::<IdProbe>(raw).ok().and_then(|probe| probe.id) else {
+            return false;
+        };
+        let Some(responder) = pending.remove(&id) else {
+            warn!("[Websocket] answer to the unknown request {} on {:?}", id, connection);
+            return false;
+        };
+        let _ = responder.send(Ok(raw.to_string()));
+        true
+    }
+
+    /// Fails the requests in flight on the lost `connection`.
+    pub(crate) fn fail_rpc(&mut self, connection: &ConnectionId) {
+        for (_, responder) in self.rpc_pending.remove(connection).unwrap_or_default() {
+            let _ = responder.send(Err(Error::NotConnected { subscription: connection.to_string() }));
+        }
+    }
+}
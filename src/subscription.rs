@@ -2,29 +2,33 @@
     clippy::let_unit_value,
     clippy::missing_panics_doc)]
 use crate::{
-    models::*, 
+    control::BinanceResponse,
+    dispatch::is_okex_book,
+    error::{self, Error, Result},
     ftx_model,
-    websocket::*,
+    models::{
+        BinanceSpotWebsocketEvent, BinanceUSwapWebsocketEvent, ConnectionEvent, ConnectionId, ConnectionState,
+        DecodePolicy, Envelope, FtxWebsocketEvent, HuobiUSwapWebsocketEvent, OkexWebsocketEvent, Subscription, WebsocketEvent,
+    },
+    okex_model,
+    recorder::{Direction, FrameKind},
+    topic::{self, NativeTopic, Topic},
+    websocket::{decode, gunzip, Websocket},
 };
 use std::{
+    borrow::Cow,
     collections::HashMap,
     collections::BTreeMap,
 };
-use chrono;
-use failure::Fallible;
-use futures::prelude::*;
-use tracing::*;
-use std::thread;
+use snafu::ResultExt;
+use tracing::{debug, error, info, trace, warn};
 use tungstenite::Message;
-use serde_json::{json, from_str};
-use ring::{digest, hmac};
-use flate2::read::GzDecoder;
-use std::io::Read;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde_json::json;
+use ring::hmac;
 use tokio::net::TcpStream;
-use streamunordered::{StreamUnordered, StreamYield};
+use streamunordered::StreamYield;
 use futures::{prelude::*, stream::SplitStream, stream::SplitSink};
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 
 
 pub const WS_URL: &str = "wss://api.hbdm.vn";
@@ -32,61 +36,135 @@ pub const WS_HOST: &str = "api.hbdm.vn";
 
 type WSStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
+const fn is_binance(subscription: &Subscription) -> bool {
+    matches!(subscription,
+        Subscription::BinanceSpotMStream
+        | Subscription::BinanceSpotOrder
+        | Subscription::BinanceUSwapMStream
+        | Subscription::BinanceUSwapOrder
+        | Subscription::BinanceBLVTStream)
+}
+
 pub type StoredStream = SplitStream<WSStream>;
 pub type StoredSink = SplitSink<WSStream, tungstenite::Message>;
 
 
 impl Websocket {
 
-    pub async fn connect(
+    /// Opens every connection in `subs` and runs the receive loop, delivering events to the
+    /// handler. Only returns on error.
+    ///
+    /// The topics are spelled as each exchange expects them, see `topic::legacy`.
+    /// `connect_topics` takes typed topics instead.
+    ///
+    /// The keys are `Subscription`s for the default account, or the `ConnectionId`s returned
+    /// by `add_account`.
+    pub async fn connect<C: Into<ConnectionId>>(
         &mut self,
-        subs: HashMap<Subscription, Vec<&str>>,
-    ) -> Fallible<()> {
-        for (subscription, topics) in &subs {
-            if *subscription == Subscription::BinanceSpotMStream {
-                self.subscribe(Subscription::BinanceSpotMStream, topics).await?;
-            }
-            if *subscription == Subscription::BinanceSpotOrder {
-                self.subscribe(Subscription::BinanceSpotOrder, topics).await?;
-            }
-            if *subscription == Subscription::BinanceUSwapMStream {
-                self.subscribe(Subscription::BinanceUSwapMStream, topics).await?;
-            }
-            if *subscription == Subscription::BinanceBLVTStream {
-                self.subscribe(Subscription::BinanceBLVTStream, topics).await?;
-            }
-            if *subscription == Subscription::BinanceUSwapOrder {
-                self.subscribe(Subscription::BinanceUSwapOrder, topics).await?;
+        subs: HashMap<C, Vec<&str>>,
+    ) -> Result<()> {
+        self.open(subs).await?;
+
+        self.rx_handler().await?;
+
+        Ok(())
+    }
+
+    /// `connect` with typed topics. Fails with `Error::UnsupportedTopic` before opening any
+    /// connection if a topic is not available on its subscription.
+    pub async fn connect_topics<C: Into<ConnectionId>>(
+        &mut self,
+        subs: HashMap<C, Vec<Topic>>,
+    ) -> Result<()> {
+        self.open_topics(subs).await?;
+
+        self.rx_handler().await?;
+
+        Ok(())
+    }
+
+    /// Opens every connection in `subs` and sends the login and subscribe requests, without
+    /// reading from them. Consume the events with `events` or `into_stream`.
+    pub async fn open<C: Into<ConnectionId>>(
+        &mut self,
+        subs: HashMap<C, Vec<&str>>,
+    ) -> Result<()> {
+        let subs = subs.into_iter()
+            .map(|(connection, topics)| {
+                let connection: ConnectionId = connection.into();
+                let topics = topic::legacy(&connection.subscription, &topics);
+                (connection, topics)
+            })
+            .collect();
+
+        self.open_native(subs).await
+    }
+
+    /// `open` with typed topics, see `connect_topics`.
+    pub async fn open_topics<C: Into<ConnectionId>>(
+        &mut self,
+        subs: HashMap<C, Vec<Topic>>,
+    ) -> Result<()> {
+        let mut native = HashMap::new();
+        for (connection, topics) in subs {
+            let connection: ConnectionId = connection.into();
+            let mut channels = Vec::new();
+            for topic in &topics {
+                channels.extend(topic.native(&connection.subscription)?);
             }
-            if *subscription == Subscription::HuobiUSwapMarketStream {
-                self.subscribe(Subscription::HuobiUSwapMarketStream, topics).await?;
-                self.huobi_sub_market(Subscription::HuobiUSwapMarketStream, topics).await?;
+            native.insert(connection, channels);
+        }
+
+        self.open_native(native).await
+    }
+
+    async fn open_native(&mut self, subs: HashMap<ConnectionId, Vec<NativeTopic>>) -> Result<()> {
+        for (connection, topics) in subs {
+            let topics = self.listen_key_topics(&connection, topics).await?;
+            self.open_socket(connection.clone(), &topics).await?;
+            self.topics.insert(connection.clone(), topics);
+            self.handshake(&connection).await?;
+            self.stamp();
+            self.emit_connection_state(&connection, ConnectionState::Connected);
+        }
+
+        Ok(())
+    }
+
+    /// Drops `connection` and lets the supervisor re-establish it, replaying the login and
+    /// topic subscriptions. Other connections are not affected.
+    pub fn reconnect(&mut self, connection: impl Into<ConnectionId>) {
+        let connection = connection.into();
+        if self.drop_connection(&connection) {
+            self.schedule_reconnect(connection, 1);
+        }
+    }
+
+    /// Sends the login/auth request and the topic subscriptions of a freshly opened connection.
+    async fn handshake(&mut self, connection: &ConnectionId) -> Result<()> {
+        match connection.subscription {
+            Subscription::HuobiUSwapMarketStream | Subscription::OkexMarketStream | Subscription::FtxMarketStream => {
+                self.sub_topics(connection).await?;
             }
-            if *subscription == Subscription::HuobiUSwapOrderStream {
-                self.subscribe(Subscription::HuobiUSwapOrderStream, topics).await?;
+            Subscription::HuobiUSwapOrderStream => {
                 let mut params: BTreeMap<String, String> = BTreeMap::new();
-                let signature = self.generate_signature(subscription.clone(), "api.hbdm.vn", "/linear-swap-notification", &mut params);
+                let host = self.endpoint_of(connection).host()?;
+                let signature = self.generate_signature(connection, &host, "/linear-swap-notification", &mut params)?;
                 let message = json!({
-                    "AccessKeyId": params.get(&"AccessKeyId".to_string()),
-                    "SignatureMethod": params.get(&"SignatureMethod".to_string()),
-                    "SignatureVersion": params.get(&"SignatureVersion".to_string()),
-                    "Timestamp": params.get(&"Timestamp".to_string()),
+                    "AccessKeyId": params.get("AccessKeyId"),
+                    "SignatureMethod": params.get("SignatureMethod"),
+                    "SignatureVersion": params.get("SignatureVersion"),
+                    "Timestamp": params.get("Timestamp"),
                     "Signature": signature,
                     "op": "auth".to_string(),
                     "type": "api".to_string(),   
                 });
 
-                let sink = self.sinks.get_mut(&Subscription::HuobiUSwapOrderStream).unwrap();
-                sink.send(tungstenite::Message::Text(message.to_string())).await?;
+                self.send(connection, message.to_string()).await?;
             }
-            if *subscription == Subscription::OkexMarketStream {
-                self.subscribe(Subscription::OkexMarketStream, topics).await?;
-                self.okex_sub_market(Subscription::OkexMarketStream, topics).await?;
-            }
-            if *subscription == Subscription::OkexOrderStream {
-                self.subscribe(Subscription::OkexOrderStream, topics).await?;
+            Subscription::OkexOrderStream => {
                 let timestamp = chrono::Utc::now().timestamp().to_string();
-                let (key,passphrase, signature) = self.okex_generate_signature(subscription.clone(), &timestamp, "GET", "/users/self/verify");
+                let (key,passphrase, signature) = self.okex_generate_signature(connection, &timestamp, "GET", "/users/self/verify")?;
 
                 let message = json!({
                     "op": "login",
@@ -98,19 +176,11 @@ impl Websocket {
                     }]
                 });
 
-                let sink = self.sinks.get_mut(&Subscription::OkexOrderStream).unwrap();
-                sink.send(tungstenite::Message::Text(message.to_string())).await?;
+                self.send(connection, message.to_string()).await?;
             }
-
-            if *subscription == Subscription::FtxMarketStream {
-                self.subscribe(Subscription::FtxMarketStream, topics).await?;
-                self.ftx_sub_market(Subscription::FtxMarketStream, topics).await?;
-
-            }
-            if *subscription == Subscription::FtxOrderStream {
-                self.subscribe(Subscription::FtxOrderStream, topics).await?;
+            Subscription::FtxOrderStream => {
                 let timestamp = chrono::Utc::now().timestamp_millis();
-                let (key, subaccount, signature) = self.ftx_generate_signature(subscription.clone(), &timestamp.to_string());
+                let (key, subaccount, signature) = self.ftx_generate_signature(connection, &timestamp.to_string())?;
                 
                 let message = json!({
                     "op": "login",
@@ -121,452 +191,584 @@ impl Websocket {
                         "subaccount": subaccount,
                     }
                 });
-                let sink = self.sinks.get_mut(&Subscription::FtxOrderStream).unwrap();
-                sink.send(tungstenite::Message::Text(message.to_string())).await?;
-
+                self.send(connection, message.to_string()).await?;
 
-                self.ftx_sub_account(subscription.clone(), &subs).await?;
+                self.sub_topics(connection).await?;
             }
+            // binance streams are selected by the url, nothing to send.
+            _ => self.sub_topics(connection).await?,
         }
 
-        self.rx_handler(&subs).await?;
-
         Ok(())
     }
 
+    pub(crate) async fn send(&mut self, connection: &ConnectionId, message: String) -> Result<()> {
+        // answers to replayed frames have nowhere to go.
+        if self.replaying {
+            return Ok(());
+        }
+        if self.sinks.contains_key(connection) {
+            self.record(connection, Direction::Outbound, FrameKind::Text, &message);
+        }
+        self.send_message(connection, Message::Text(message)).await
+    }
 
-    pub async fn reconnect(
-        &mut self, 
-        subs: HashMap<Subscription, Vec<&str>>,
-    ) -> Fallible<()> {
-        for (subscription, topics) in &subs {
-            self.unsubscribe(subscription);
+    /// `send` for the frames without text: pings and pongs.
+    pub(crate) async fn send_frame(&mut self, connection: &ConnectionId, kind: FrameKind, message: Message) -> Result<()> {
+        if self.replaying {
+            return Ok(());
+        }
+        if let Message::Ping(payload) | Message::Pong(payload) = &message {
+            if self.sinks.contains_key(connection) {
+                self.record(connection, Direction::Outbound, kind, &String::from_utf8_lossy(payload));
+            }
         }
-        thread::sleep(Duration::from_millis(5000));
-        self.connect(subs).await?;
+        self.send_message(connection, message).await
+    }
 
+    async fn send_message(&mut self, connection: &ConnectionId, message: Message) -> Result<()> {
+        let sink = self.sinks.get_mut(connection)
+            .ok_or_else(|| Error::NotConnected { subscription: connection.to_string() })?;
+        sink.send(message).await
+            .context(error::SocketSnafu { subscription: connection.to_string() })?;
         Ok(())
-
     }
 
-    async fn rx_handler(&mut self, subs: &HashMap<Subscription, Vec<&str>>) -> Fallible<()> {
-        loop {
-            tokio::select! {
-                _ = self.ping_timer.tick() => {
-                    for subscription in subs.keys() {
-                        if *subscription == Subscription::FtxOrderStream || *subscription == Subscription::FtxMarketStream {
-                            let _topics = subs.get(&subscription).unwrap();
-                            let message= json!({
-                                "op": "ping",
-                            });
-                            let sink = self.sinks.get_mut(&subscription).unwrap();
-                            sink.send(tungstenite::Message::Text(message.to_string())).await?;
-                        } 
-                    }
-                }
-
-                Some((msg, token)) = self.streams.next() => {
-                    match msg {
-                        StreamYield::Finished(_) => warn!("finished stream: {:?}", &token),
-                        StreamYield::Item(s) => {
-                            let message = s.unwrap_or(Message::Text("".to_string()));
-                            let subscription = self.tokens.get(&token).unwrap().clone();
-                            let bin = match message {
-                                Message::Text(message) => {
-                                    if subscription == Subscription::BinanceSpotMStream {
-                                        let msg: BinanceSpotWebsocketEvent = from_str(&message)?;
-                                        match msg {
-                                            BinanceSpotWebsocketEvent::BinanceSpotAggrTrades(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotAggrTrades(msg.clone()))?,
-                                            BinanceSpotWebsocketEvent::BinanceSpotTrade(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotTrade(msg.clone()))?,
-                                            BinanceSpotWebsocketEvent::BinanceSpotOrderBook(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotOrderBook(msg.clone()))?,
-                                            BinanceSpotWebsocketEvent::BinanceSpotDayTicker(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotDayTicker(msg.clone()))?,
-                                            BinanceSpotWebsocketEvent::BinanceSpotDayTickerAll(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotDayTickerAll(msg.clone()))?,
-                                            BinanceSpotWebsocketEvent::BinanceSpotKline(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotKline(msg.clone()))?,
-                                            BinanceSpotWebsocketEvent::BinanceSpotDepthOrderBook(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotDepthOrderBook(msg.clone()))?,
-                                            _ => (),
-                                        }
-                                    }
-                                    else if subscription == Subscription::BinanceSpotOrder {
-                                        let msg: BinanceSpotWebsocketEvent = from_str(&message)?;
-                                        match msg {
-                                            BinanceSpotWebsocketEvent::BinanceSpotAccountUpdate(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotAccountUpdate(msg.clone()))?,
-                                            BinanceSpotWebsocketEvent::BinanceSpotOrderTrade(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotOrderTrade(msg.clone()))?,
-                                            BinanceSpotWebsocketEvent::BinanceSpotBalanceUpdate(ref msg) => (self.handler)(WebsocketEvent::BinanceSpotBalanceUpdate(msg.clone()))?,
-                                            _ => (),
-                                        }
-                                    } 
-                                    else if subscription == Subscription::BinanceUSwapMStream || subscription == Subscription::BinanceBLVTStream {
-                                        let msg: BinanceUSwapWebsocketEvent = from_str(&message)?;
-                                        match msg {
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapBookTickerEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapBookTickerEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapAggrTradesEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapAggrTradesEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapDayTickerEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapDayTickerEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapMiniTickerEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapMiniTickerEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapVec(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapVec(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapIndexPriceEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapIndexPriceEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapMarkPriceEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapMarkPriceEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapVecMarkPriceEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapVecMarkPriceEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapTradeEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapTradeEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapContinuousKlineEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapContinuousKlineEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapKlineEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapKlineEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapIndexKlineEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapIndexKlineEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapLiquidationEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapLiquidationEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapOrderBook(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapOrderBook(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapDepthOrderBookEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapDepthOrderBookEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapEtpNavEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapEtpNavEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapEtpKlineEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapEtpKlineEvent(msg.clone()))?,
-                                            _ => (),
-                                        }
-                                    }
-                                    else if subscription == Subscription::BinanceUSwapOrder {
-                                        debug!("binance uswap websocket message:{:?}", message);
-                                        let msg: BinanceUSwapWebsocketEvent = from_str(&message)?;
-                                        match msg {
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapOrderTradeEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapOrderTradeEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapAccountUpdateEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapAccountUpdateEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapListenKeyEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapListenKeyEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapMarginCallEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapMarginCallEvent(msg.clone()))?,
-                                            BinanceUSwapWebsocketEvent::BinanceUSwapAccountConfigEvent(ref msg) => (self.handler)(WebsocketEvent::BinanceUSwapAccountConfigEvent(msg.clone()))?,
-                                            _ => (),
-                                        }
-                                    }
-        
-                                    else if subscription == Subscription::OkexMarketStream {
-                                        let msg: OkexWebsocketEvent = from_str(&message)?;
-                                        match msg {
-                                            OkexWebsocketEvent::OkexOrderBook(ref msg) => (self.handler)(WebsocketEvent::OkexOrderBook(msg.clone()))?,
-                                            OkexWebsocketEvent::OkexTrade(ref msg) => (self.handler)(WebsocketEvent::OkexTrade(msg.clone()))?,
-                                            _ => (),
-                                        }
-                                    }
-        
-                                    else if subscription == Subscription::OkexOrderStream {
-                                        let msg: OkexWebsocketEvent = from_str(&message)?;
-                                        match msg {
-                                            OkexWebsocketEvent::OkexAccount(ref msg) => (self.handler)(WebsocketEvent::OkexAccount(msg.clone()))?,
-                                            OkexWebsocketEvent::OkexPosition(ref msg) => (self.handler)(WebsocketEvent::OkexPosition(msg.clone()))?,
-                                            OkexWebsocketEvent::OkexOrder(ref msg) => (self.handler)(WebsocketEvent::OkexOrder(msg.clone()))?,
-                                            OkexWebsocketEvent::OkexAccountPosition(ref msg) => (self.handler)(WebsocketEvent::OkexAccountPosition(msg.clone()))?,
-                                            OkexWebsocketEvent::OkexSubRsp(ref msg) => info!("Okex Sub Rsp: {:?}", msg.clone()),
-                                            OkexWebsocketEvent::OkexSubEvent(ref msg) => {
-                                                info!("Okex Sub Event: {:?}", msg.clone());
-                                                if msg.event == "login" {
-                                                    if msg.code == "0" {
-                                                        //okex sub private topics
-                                                        self.okex_sub_account(subscription, subs).await?;
-                                                    }
-                                                    else {
-                                                        info!("Okex login fail: {:?}",msg.clone());
-                                                    }
-                                                }
-                                                else {
-                                                    info!("Okex sub status: {:?}",msg.clone());
-                                                }
-                                                
-                                            
-                                            }
-                                            _ => (),
-        
-                                        }
-                                    }
-
-                                    else if subscription == Subscription::FtxMarketStream {
-                                        let msg: FtxWebsocketEvent = from_str(&message)?;
-                                        match msg {
-                                            FtxWebsocketEvent::FtxRsp(ref msg) => {
-                                                info!("Ftx msg: {:?}", msg.clone());
-                                                match msg.r#type {
-                                                    ftx_model::Type::Update | ftx_model::Type::Partial => (self.handler)(WebsocketEvent::FtxRsp(msg.clone()))?,
-                                                    ftx_model::Type::Error => {
-                                                        error!("ftx websocket error:{:?}", msg.clone());
-                                                    },
-                                                    _ => {
-                                                        info!("ftx websocket info:{:?}", msg.clone());
-                                                    }
-                                                }
-                                            }
-                                        }
-
-                                    }
-                                    else if subscription == Subscription::FtxOrderStream {
-                                        let msg: FtxWebsocketEvent = from_str(&message)?;
-                                        match msg {
-                                            FtxWebsocketEvent::FtxRsp(ref msg) => {
-                                                info!("ftx private msg:{:?}", msg.clone());
-                                                match msg.r#type {
-                                                    ftx_model::Type::Update | ftx_model::Type::Partial => (self.handler)(WebsocketEvent::FtxRsp(msg.clone()))?,
-                                                    ftx_model::Type::Error => {
-                                                        error!("ftx websocket error:{:?}", msg.clone());
-                                                    },
-                                                    _ => {
-                                                        trace!("ftx websocket info:{:?}", msg.clone());
-                                                    }
-                                                }
-
-                                            }
-                                        }
-                                    }
-        
-                                    else {
-                                        return Ok(());
-                                    }
-        
-                                },
-                                Message::Binary(b) => {
-                                    if subscription == Subscription::HuobiUSwapMarketStream {
-                                        let mut d = GzDecoder::new(&*b);
-                                        let mut s = String::new();
-                                        d.read_to_string(&mut s).unwrap();
-        
-                                        trace!("Incoming websocket message {:?}", s);
-                                        
-                                        let msg: HuobiUSwapWebsocketEvent = from_str(&s)?;
-                                        match msg {
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapMarketPing(ref msg) => {
-                                                let ts = chrono::Local::now().timestamp_millis();
-                                                let message = json!({
-                                                "pong": ts,       
-                                                });
-                                                let sink = self.sinks.get_mut(&subscription).unwrap();
-                                                sink.send(tungstenite::Message::Text(message.to_string())).await?;
-        
-                                            },
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapSubStatus(ref msg) => {
-                                                info!("sub status:{:?}", msg.clone());
-                                            },
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapOrderBook(ref msg) => (self.handler)(WebsocketEvent::HuobiUSwapOrderBook(msg.clone()))?,
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapIncrementalOrderBook(ref msg) => (self.handler)(WebsocketEvent::HuobiUSwapIncrementalOrderBook(msg.clone()))?,
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapBBO(ref msg) => (self.handler)(WebsocketEvent::HuobiUSwapBBO(msg.clone()))?,
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapKline(ref msg) => (self.handler)(WebsocketEvent::HuobiUSwapKline(msg.clone()))?,
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapTradeDetail(ref msg) => (self.handler)(WebsocketEvent::HuobiUSwapTradeDetail(msg.clone()))?,
-                                            _ => (),
-                                        }
-                                    }
-                                    if subscription == Subscription::HuobiUSwapOrderStream {
-                                        let mut d = GzDecoder::new(&*b);
-                                        let mut s = String::new();
-                                        d.read_to_string(&mut s).unwrap();
-        
-                                        trace!("Incoming websocket message {:?}", s);
-                                        
-                                        let msg: HuobiUSwapWebsocketEvent = from_str(&s)?;
-                                        match msg {
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapOpStatus(ref msg) => {
-                                                if msg.op == "ping" {
-                                                    let ts = chrono::Local::now().timestamp_millis();
-                                                    let message = json!({
-                                                        "op": "pong",
-                                                        "ts": ts,       
-                                                    });
-                                                    debug!("### op pong: {:?}", message);
-                                                    let sink = self.sinks.get_mut(&subscription).unwrap();
-                                                    sink.send(tungstenite::Message::Text(message.to_string())).await?;
-                                
-                                                }
-                                                if msg.op == "auth" {
-                                                    if let Some(err_code) = msg.err_code {
-                                                        if err_code == 0 {
-                                                            self.huobi_sub_account(subscription, subs).await?;
-                                                        }
-                                                    }
-                                                }
-                                                if let Some(_err_code) = msg.err_code {
-                                                    info!("{:?}", msg);
-                                                }
-        
-                                            },
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapSubStatus(ref msg) => {
-                                                info!("sub status:{:?}", msg.clone());
-                                            },
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapAccount(ref msg) => (self.handler)(WebsocketEvent::HuobiUSwapAccount(msg.clone()))?,
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapOrder(ref msg) => (self.handler)(WebsocketEvent::HuobiUSwapOrder(msg.clone()))?,
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapMatchOrder(ref msg) => (self.handler)(WebsocketEvent::HuobiUSwapMatchOrder(msg.clone()))?,
-                                            HuobiUSwapWebsocketEvent::HuobiUSwapPosition(ref msg) => (self.handler)(WebsocketEvent::HuobiUSwapPosition(msg.clone()))?,
-                                            _ => (),
-                                        }
-        
-                                    }
-                                },
-        
-                                Message::Pong(c) => (),
-                                Message::Ping(d) => (),
-                                Message::Close(..) => return Err(failure::format_err!("Socket closed")),
-                                Message::Frame(_) => todo!(),
-                            };
-
-
-                        }
-                    };
-
-                    // huobi ws is gziped,so need to parse it again.
-                }
-    };
+    pub(crate) fn emit_connection_state(&mut self, connection: &ConnectionId, state: ConnectionState) {
+        let event = ConnectionEvent { connection: connection.clone(), state };
+        self.emit(connection, WebsocketEvent::Connection(event));
+    }
 
+    /// Tears down the dead `connection` and starts reconnecting it.
+    pub(crate) fn connection_lost(&mut self, connection: &ConnectionId, reason: String) {
+        if !self.drop_connection(connection) {
+            return;
+        }
+        warn!("[Websocket] connection {:?} lost: {}", connection, reason);
+        self.record(connection, Direction::Inbound, FrameKind::Close, &reason);
+        self.reset_connection(connection);
+        self.emit_connection_state(connection, ConnectionState::Disconnected(reason));
+        self.retry_connection(connection, 1);
     }
 
-        Ok(())
+    /// Drops what was received on a lost connection: local books, topic requests and the
+    /// requests waiting for an answer.
+    pub(crate) fn reset_connection(&mut self, connection: &ConnectionId) {
+        self.reset_order_books(connection);
+        self.reset_okex_books(connection);
+        self.reset_huobi_books(connection);
+        self.reset_ftx_books(connection);
+        self.reset_topics(connection);
+        self.fail_rpc(connection);
     }
 
+    fn retry_connection(&mut self, connection: &ConnectionId, attempt: u32) {
+        if self.reconnect_policy.exhausted(attempt) {
+            error!("[Websocket] giving up on {:?} after {} attempts", connection, attempt - 1);
+            self.emit_connection_state(connection, ConnectionState::GaveUp { attempts: attempt - 1 });
+            return;
+        }
+        let delay = self.schedule_reconnect(connection.clone(), attempt);
+        self.emit_connection_state(connection, ConnectionState::Reconnecting { attempt, delay });
+    }
 
-    async fn huobi_sub_market(&mut self, subscription: Subscription, topics: &[&str]) -> Fallible<()> {
-        for topic in topics {
-            let message = json!({
-                "sub": topic,
-                "id": "huobiusdtswap_rust"
-            });
-            let sink = self.sinks.get_mut(&subscription).unwrap();
-            sink.send(tungstenite::Message::Text(message.to_string())).await?;
+    async fn on_reconnect_attempt(&mut self, connection: ConnectionId, attempt: u32, topics: &[NativeTopic], ws_stream: Result<WSStream>) {
+        let ws_stream = match ws_stream {
+            Ok(ws_stream) => ws_stream,
+            Err(e) => {
+                warn!("[Websocket] reconnect {:?} attempt {} failed: {}", connection, attempt, e);
+                self.retry_connection(&connection, attempt + 1);
+                return;
+            }
+        };
+
+        // the streams of Binance are part of the url, a socket opened before they changed,
+        // e.g. by a new listen key, is replaced with one opened with the current ones.
+        let current = self.topics.get(&connection).map(Vec::as_slice).unwrap_or_default();
+        if topic::binance_streams(topics) != topic::binance_streams(current) {
+            info!("[Websocket] streams of {:?} changed while reconnecting, reconnecting again", connection);
+            drop(ws_stream);
+            self.start_reconnect(connection, attempt);
+            return;
+        }
 
+        self.register(connection.clone(), ws_stream);
+        if let Err(e) = self.handshake(&connection).await {
+            warn!("[Websocket] handshake of {:?} failed after reconnect: {}", connection, e);
+            self.drop_connection(&connection);
+            self.retry_connection(&connection, attempt + 1);
+            return;
         }
-        
-        Ok(())
 
+        info!("[Websocket] {:?} reconnected after {} attempts", connection, attempt);
+        self.emit_connection_state(&connection, ConnectionState::Reconnected { attempts: attempt });
     }
 
-    async fn ftx_sub_market(&mut self, subscription: Subscription, topics: &[&str]) -> Fallible<()> {
-
-        for symbol in topics {
-            let message = json!({
-                "op": "subscribe",
-                "channel": "orderbook",
-                "market": symbol
-            });
-            let sink = self.sinks.get_mut(&subscription).unwrap();
-            sink.send(tungstenite::Message::Text(message.to_string())).await?;
-
-            let message = json!({
-                "op": "subscribe",
-                "channel": "ticker",
-                "market": symbol
-            });
-            let sink = self.sinks.get_mut(&subscription).unwrap();
-            sink.send(tungstenite::Message::Text(message.to_string())).await?;
-
-            let message = json!({
-                "op": "subscribe",
-                "channel": "trades",
-                "market": symbol
-            });
-            let sink = self.sinks.get_mut(&subscription).unwrap();
-            sink.send(tungstenite::Message::Text(message.to_string())).await?;
+    async fn rx_handler(&mut self) -> Result<()> {
+        loop {
+            let envelope = self.next_event().await?;
+            if self.normalized_handler.is_some() {
+                self.handle_normalized(&envelope)?;
+            } else {
+                (self.handler)(envelope)?;
+            }
+        }
+    }
 
+    /// Waits for the next event of any connection, keeping the connections alive and
+    /// answering logins and reconnects along the way.
+    pub(crate) async fn next_event(&mut self) -> Result<Envelope> {
+        loop {
+            if let Some(envelope) = self.deliver() {
+                return Ok(envelope);
+            }
 
-        }
+            tokio::select! {
+                _ = self.ping_timer.tick() => {
+                    self.stamp();
+                    self.check_heartbeats().await;
+                }
 
+                Some((msg, token)) = self.streams.next() => {
+                    self.stamp();
+                    let connection = match self.tokens.get(&token) {
+                        Some(connection) => connection.clone(),
+                        None => continue,
+                    };
+                    match msg {
+                        StreamYield::Finished(_) => self.connection_lost(&connection, "stream finished".to_string()),
+                        StreamYield::Item(Err(e)) => self.connection_lost(&connection, e.to_string()),
+                        StreamYield::Item(Ok(message)) => {
+                            self.on_frame_received(&connection);
+                            if let Err(e) = self.handle_message(connection.clone(), message).await {
+                                self.on_connection_error(&connection, e)?;
+                            }
+                        }
+                    }
+                }
 
-        Ok(())
+                Some((connection, attempt)) = self.reconnect_delays.next() => {
+                    self.stamp();
+                    self.start_reconnect(connection, attempt);
+                }
 
-    }
+                Some((connection, attempt, topics, ws_stream)) = self.reconnects.next() => {
+                    self.stamp();
+                    self.on_reconnect_attempt(connection, attempt, &topics, ws_stream).await;
+                }
 
-    async fn okex_sub_market(&mut self, subscription: Subscription, topics: &[&str]) -> Fallible<()> {
+                Some((connection, symbol, snapshot)) = self.snapshots.next() => {
+                    self.stamp();
+                    self.record_snapshot(&connection, &symbol, &snapshot);
+                    self.on_depth_snapshot(connection, symbol, snapshot);
+                }
 
-        let mut market_topics = vec![HashMap::new()];
-        for symbol in topics {
-            let mut orderbook_topic = HashMap::new();
-            orderbook_topic.insert("channel".to_string(), "books50-l2-tbt".to_string());
-            orderbook_topic.insert("instId".to_string(), (*symbol).to_string());
+                Some((connection, ch)) = self.huobi_snapshot_retries.next() => {
+                    self.stamp();
+                    self.on_huobi_snapshot_retry(&connection, &ch).await;
+                }
 
-            market_topics.push(orderbook_topic);
+                _ = self.listen_key_timer.tick() => {
+                    self.stamp();
+                    self.keep_listen_keys_alive();
+                }
 
-            let mut pricelimit_topic = HashMap::new();
-            pricelimit_topic.insert("channel".to_string(), "price-limit".to_string());
-            pricelimit_topic.insert("instId".to_string(), (*symbol).to_string());
+                Some((connection, request, result)) = self.listen_key_requests.next() => {
+                    self.stamp();
+                    self.on_listen_key(&connection, request, result);
+                }
+
+                Some(request) = self.rpc_requests.recv() => {
+                    self.stamp();
+                    self.on_rpc_request(request).await;
+                }
 
-            market_topics.push(pricelimit_topic);
+                Some(command) = self.commands.recv() => {
+                    self.stamp();
+                    self.on_command(command).await;
+                }
+            }
+        }
+    }
 
-            let mut trade_topic = HashMap::new();
-            trade_topic.insert("channel".to_string(), "trades".to_string());
-            trade_topic.insert("instId".to_string(), (*symbol).to_string());
+    /// Handles an error of `connection` without affecting the others: frames that cannot be
+    /// decoded go by the `DecodePolicy`, a refused login leaves the connection down, anything
+    /// else, like a failed send, reconnects it. Only `DecodePolicy::Fatal` returns the error.
+    pub(crate) fn on_connection_error(&mut self, connection: &ConnectionId, e: Error) -> Result<()> {
+        match e {
+            _ if self.decode_policy == DecodePolicy::Fatal => Err(e),
+            Error::Decode { .. } | Error::Gzip { .. } => self.on_decode_error(connection, e),
+            Error::AuthRejected { code, msg, .. } => {
+                self.login_rejected(connection, code, msg);
+                Ok(())
+            }
+            e => {
+                self.connection_lost(connection, e.to_string());
+                Ok(())
+            }
+        }
+    }
 
-            market_topics.push(trade_topic);
-            
+    /// Drops `connection` for good after its login was refused.
+    fn login_rejected(&mut self, connection: &ConnectionId, code: String, msg: String) {
+        if !self.drop_connection(connection) {
+            return;
         }
+        error!("[Websocket] {:?} rejected the login: {}: {}", connection, code, msg);
+        self.record(connection, Direction::Inbound, FrameKind::Close, &msg);
+        self.reset_connection(connection);
+        self.emit_connection_state(connection, ConnectionState::LoginRejected { code, msg });
+    }
 
-        let message = json!({
-            "op": "subscribe",
-            "args": market_topics,
-        });
-        let sink = self.sinks.get_mut(&subscription).unwrap();
-        sink.send(tungstenite::Message::Text(message.to_string())).await?;
+    /// Applies the `DecodePolicy` to a failed message, other errors are returned as they are.
+    pub(crate) fn on_decode_error(&mut self, connection: &ConnectionId, e: Error) -> Result<()> {
+        let raw = match &e {
+            Error::Decode { raw, .. } => raw.clone(),
+            Error::Gzip { .. } => String::new(),
+            _ => return Err(e),
+        };
+        self.skip_message(connection, raw, e)
+    }
 
+    /// Hands the normalized events of `envelope` to the normalized handler. Items that cannot
+    /// be converted go by the `DecodePolicy`, with the event in place of the frame.
+    fn handle_normalized(&mut self, envelope: &Envelope) -> Result<()> {
+        for event in envelope.event.normalized() {
+            match (event, self.normalized_handler.as_mut()) {
+                (Ok(event), Some(handler)) => handler(envelope.connection.clone(), event)?,
+                (Ok(_), None) => (),
+                (Err(e), _) => self.skip_message(&envelope.connection, format!("{:?}", envelope.event), e)?,
+            }
+        }
         Ok(())
     }
 
-    async fn huobi_sub_account(&mut self, subscription: Subscription,subs: &HashMap<Subscription, Vec<&str> >) -> Fallible<()> {
-        let topics = subs.get(&subscription).unwrap();
-        for topic in topics {
-            let message = json!({
-                "op": "sub",
-                "cid": "huobiusdtswap_rust",
-                "topic": topic,
-                });
-            let sink = self.sinks.get_mut(&subscription).unwrap();
-            sink.send(tungstenite::Message::Text(message.to_string())).await?;
+    /// Skips `raw`, which failed with `e`, unless the policy is `DecodePolicy::Fatal`.
+    fn skip_message(&mut self, connection: &ConnectionId, raw: String, e: Error) -> Result<()> {
+        if self.decode_policy == DecodePolicy::Fatal {
+            return Err(e);
         }
 
+        self.unparsed += 1;
+        warn!("[Websocket] skipping a message of {:?}: {}", connection, e);
+        if self.decode_policy == DecodePolicy::Emit {
+            let subscription = connection.subscription.clone();
+            self.emit(connection, WebsocketEvent::Unparsed { subscription, raw, error: e.to_string() });
+        }
         Ok(())
     }
 
-    async fn ftx_sub_account(&mut self, subscription: Subscription, subs: &HashMap<Subscription, Vec<&str> >) -> Fallible<()> {
-        let topics = subs.get(&subscription).unwrap();
-        let message= json!({
-            "op": "subscribe",
-            "channel": "fills",
-        });
-        let sink = self.sinks.get_mut(&subscription).unwrap();
-        sink.send(tungstenite::Message::Text(message.to_string())).await?;
-
-        let message= json!({
-            "op": "subscribe",
-            "channel": "orders",
-        });
-        let sink = self.sinks.get_mut(&subscription).unwrap();
-        sink.send(tungstenite::Message::Text(message.to_string())).await?;
+    pub(crate) async fn handle_message(&mut self, connection: ConnectionId, message: Message) -> Result<()> {
+        match message {
+            Message::Text(message) => {
+                self.record(&connection, Direction::Inbound, FrameKind::Text, &message);
+                if self.heartbeat_policy(&connection.subscription).is_pong(&message) {
+                    return Ok(());
+                }
+                if self.on_rpc_answer(&connection, &message) {
+                    return Ok(());
+                }
 
-        Ok(())
+                if is_binance(&connection.subscription) && self.has_topic_requests(&connection) {
+                    if let Ok(rsp) = serde_json::from_str::<BinanceResponse>(&message) {
+                        self.on_binance_response(&connection, rsp);
+                        return Ok(());
+                    }
+                }
 
-    }
+                if self.on_borrowed(&connection, &message)? {
+                    return Ok(());
+                }
 
-    async fn okex_sub_account(&mut self, subscription: Subscription, subs: &HashMap<Subscription, Vec<&str> >) -> Fallible<()> {
-        let topics = subs.get(&subscription).unwrap();
-        let mut order_topics = vec![HashMap::new()];
+                if connection.subscription == Subscription::BinanceSpotMStream {
+                    let msg = BinanceSpotWebsocketEvent::parse(&message)?;
+                    match msg {
+                        BinanceSpotWebsocketEvent::BinanceSpotAggrTrades(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotAggrTrades(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotTrade(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotTrade(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotOrderBook(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotOrderBook(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotDayTicker(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotDayTicker(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotDayTickerAll(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotDayTickerAll(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotKline(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotKline(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotBookTicker(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotBookTicker(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotDepthOrderBook(msg) => {
+                            let update = self.depth_books.contains_key(&connection).then(|| (&msg.data).into());
+                            self.emit(&connection, WebsocketEvent::BinanceSpotDepthOrderBook(msg));
+                            if let Some(update) = update {
+                                self.on_depth_update(&connection, update);
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                else if connection.subscription == Subscription::BinanceSpotOrder {
+                    let msg = BinanceSpotWebsocketEvent::parse(&message)?;
+                    match msg {
+                        BinanceSpotWebsocketEvent::BinanceSpotAccountUpdate(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotAccountUpdate(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotOrderTrade(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotOrderTrade(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotBalanceUpdate(msg) => self.emit(&connection, WebsocketEvent::BinanceSpotBalanceUpdate(msg)),
+                        BinanceSpotWebsocketEvent::BinanceSpotListenKeyEvent(msg) => {
+                            self.emit(&connection, WebsocketEvent::BinanceSpotListenKeyEvent(msg));
+                            self.rotate_listen_key(&connection, 1);
+                        }
+                        _ => (),
+                    }
+                } 
+                else if connection.subscription == Subscription::BinanceUSwapMStream || connection.subscription == Subscription::BinanceBLVTStream {
+                    let msg = BinanceUSwapWebsocketEvent::parse(&message)?;
+                    match msg {
+                        BinanceUSwapWebsocketEvent::BinanceUSwapBookTickerEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapBookTickerEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapAggrTradesEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapAggrTradesEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapDayTickerEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapDayTickerEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapMiniTickerEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapMiniTickerEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapVec(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapVec(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapVecMiniTickerEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapVecMiniTickerEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapIndexPriceEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapIndexPriceEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapMarkPriceEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapMarkPriceEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapVecMarkPriceEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapVecMarkPriceEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapTradeEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapTradeEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapContinuousKlineEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapContinuousKlineEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapKlineEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapKlineEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapIndexKlineEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapIndexKlineEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapLiquidationEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapLiquidationEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapOrderBook(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapOrderBook(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapDepthOrderBookEvent(msg) => {
+                            let update = self.depth_books.contains_key(&connection).then(|| (&msg.data).into());
+                            self.emit(&connection, WebsocketEvent::BinanceUSwapDepthOrderBookEvent(msg));
+                            if let Some(update) = update {
+                                self.on_depth_update(&connection, update);
+                            }
+                        }
+                        BinanceUSwapWebsocketEvent::BinanceUSwapEtpNavEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapEtpNavEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapEtpKlineEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapEtpKlineEvent(msg)),
+                        _ => (),
+                    }
+                }
+                else if connection.subscription == Subscription::BinanceUSwapOrder {
+                    debug!("binance uswap websocket message:{:?}", message);
+                    let msg = BinanceUSwapWebsocketEvent::parse(&message)?;
+                    match msg {
+                        BinanceUSwapWebsocketEvent::BinanceUSwapOrderTradeEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapOrderTradeEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapAccountUpdateEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapAccountUpdateEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapListenKeyEvent(msg) => {
+                            self.emit(&connection, WebsocketEvent::BinanceUSwapListenKeyEvent(msg));
+                            self.rotate_listen_key(&connection, 1);
+                        }
+                        BinanceUSwapWebsocketEvent::BinanceUSwapMarginCallEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapMarginCallEvent(msg)),
+                        BinanceUSwapWebsocketEvent::BinanceUSwapAccountConfigEvent(msg) => self.emit(&connection, WebsocketEvent::BinanceUSwapAccountConfigEvent(msg)),
+                        _ => (),
+                    }
+                }
 
-        let mut account_topics = HashMap::new();
-        account_topics.insert("channel".to_string(), "balance_and_position".to_string()); 
-        order_topics.push(account_topics);
+                else if connection.subscription == Subscription::OkexMarketStream {
+                    if self.okex_books.contains_key(&connection) && is_okex_book(&message) {
+                        // decoded once with the levels as sent, which the checksum needs.
+                        let rsp: okex_model::WsRsp<okex_model::BookData> = decode(&message)?;
+                        let msg = match rsp.order_book() {
+                            Some(msg) => msg,
+                            None => decode(&message)?,
+                        };
+                        self.emit(&connection, WebsocketEvent::OkexOrderBook(msg));
+                        return self.on_okex_book(&connection, rsp).await;
+                    }
+                    let msg = OkexWebsocketEvent::parse(&message)?;
+                    match msg {
+                        OkexWebsocketEvent::OkexOrderBook(msg) => self.emit(&connection, WebsocketEvent::OkexOrderBook(msg)),
+                        OkexWebsocketEvent::OkexTrade(msg) => self.emit(&connection, WebsocketEvent::OkexTrade(msg)),
+                        OkexWebsocketEvent::OkexSubRsp(_) | OkexWebsocketEvent::OkexSubEvent(_) => self.on_okex_event(&connection, &message),
+                        _ => (),
+                    }
+                }
 
-        for inst_type in topics {
-            let mut order_topic = HashMap::new();
-            order_topic.insert("channel".to_string(), "orders".to_string());
-            order_topic.insert("instType".to_string(), (*inst_type).to_string());
-            order_topics.push(order_topic);
-        }
+                else if connection.subscription == Subscription::OkexOrderStream {
+                    let msg = OkexWebsocketEvent::parse(&message)?;
+                    match msg {
+                        OkexWebsocketEvent::OkexAccount(msg) => self.emit(&connection, WebsocketEvent::OkexAccount(msg)),
+                        OkexWebsocketEvent::OkexPosition(msg) => self.emit(&connection, WebsocketEvent::OkexPosition(msg)),
+                        OkexWebsocketEvent::OkexOrder(msg) => self.emit(&connection, WebsocketEvent::OkexOrder(msg)),
+                        OkexWebsocketEvent::OkexAccountPosition(msg) => self.emit(&connection, WebsocketEvent::OkexAccountPosition(msg)),
+                        OkexWebsocketEvent::OkexSubRsp(ref msg) => {
+                            info!("Okex Sub Rsp: {:?}", msg);
+                            self.on_okex_event(&connection, &message);
+                        }
+                        OkexWebsocketEvent::OkexSubEvent(ref msg) => {
+                            info!("Okex Sub Event: {:?}", msg);
+                            if msg.event == "login" {
+                                if msg.code == "0" {
+                                    //okex sub private topics
+                                    self.sub_topics(&connection).await?;
+                                }
+                                else {
+                                    info!("Okex login fail: {:?}", msg);
+                                    return Err(Error::AuthRejected {
+                                        subscription: connection.to_string(),
+                                        code: msg.code.clone(),
+                                        msg: msg.msg.clone(),
+                                    });
+                                }
+                            }
+                            else {
+                                info!("Okex sub status: {:?}", msg);
+                                self.on_okex_event(&connection, &message);
+                            }
+                            
+                        
+                        }
+                        _ => (),
+
+                    }
+                }
+
+                else if connection.subscription == Subscription::FtxMarketStream {
+                    let msg = FtxWebsocketEvent::parse(&message)?;
+                    match msg {
+                        FtxWebsocketEvent::FtxRsp(msg) => {
+                            info!("Ftx msg: {:?}", msg);
+                            match msg.r#type {
+                                ftx_model::Type::Update | ftx_model::Type::Partial => {
+                                    let at = self.pending.len();
+                                    let result = match (&msg.market, &msg.data) {
+                                        (Some(market), Some(ftx_model::ResponseData::OrderbookData(data))) => self.on_ftx_book(&connection, market, data).await,
+                                        _ => Ok(()),
+                                    };
+                                    self.emit_at(at, &connection, WebsocketEvent::FtxRsp(msg));
+                                    result?;
+                                }
+                                ftx_model::Type::Error => {
+                                    error!("ftx websocket error:{:?}", msg);
+                                    self.on_ftx_response(&connection, &msg);
+                                },
+                                _ => {
+                                    info!("ftx websocket info:{:?}", msg);
+                                    self.on_ftx_response(&connection, &msg);
+                                }
+                            }
+                        }
+                    }
 
-        let mut acc_topics = HashMap::new();
-        acc_topics.insert("channel".to_string(), "account".to_string());
-        order_topics.push(acc_topics);
+                }
+                else if connection.subscription == Subscription::FtxOrderStream {
+                    let msg = FtxWebsocketEvent::parse(&message)?;
+                    match msg {
+                        FtxWebsocketEvent::FtxRsp(msg) => {
+                            info!("ftx private msg:{:?}", msg);
+                            match msg.r#type {
+                                ftx_model::Type::Update | ftx_model::Type::Partial => self.emit(&connection, WebsocketEvent::FtxRsp(msg)),
+                                ftx_model::Type::Error => {
+                                    error!("ftx websocket error:{:?}", msg);
+                                    self.on_ftx_response(&connection, &msg);
+                                },
+                                _ => {
+                                    trace!("ftx websocket info:{:?}", msg);
+                                    self.on_ftx_response(&connection, &msg);
+                                }
+                            }
 
-        let mut pos_topics = HashMap::new();
-        pos_topics.insert("channel".to_string(), "positions".to_string());
-        pos_topics.insert("instType".to_string(), "ANY".to_string());
-        order_topics.push(pos_topics);
+                        }
+                    }
+                }
 
+                else {
+                    return Ok(());
+                }
 
-        let message = json!({
-            "op": "subscribe",
-            "args": order_topics,
-        });
+            },
+            Message::Binary(b) => {
+                let s = gunzip(&b);
+                let payload = s.as_deref().map_or_else(|_| String::from_utf8_lossy(&b), Cow::Borrowed);
+                self.record(&connection, Direction::Inbound, FrameKind::Binary, &payload);
+                if connection.subscription == Subscription::HuobiUSwapMarketStream {
+                    let s = s?;
+
+                    trace!("Incoming websocket message {:?}", s);
+                    
+                    let msg = HuobiUSwapWebsocketEvent::parse(&s)?;
+                    match msg {
+                        HuobiUSwapWebsocketEvent::HuobiUSwapMarketPing(_) => {
+                            let ts = chrono::Local::now().timestamp_millis();
+                            let message = json!({
+                            "pong": ts,       
+                            });
+                            self.send(&connection, message.to_string()).await?;
+
+                        },
+                        HuobiUSwapWebsocketEvent::HuobiUSwapSubStatus(ref msg) => {
+                            info!("sub status:{:?}", msg);
+                            self.on_huobi_sub_status(&connection, msg);
+                        },
+                        HuobiUSwapWebsocketEvent::HuobiUSwapOrderBook(msg) => self.emit(&connection, WebsocketEvent::HuobiUSwapOrderBook(msg)),
+                        HuobiUSwapWebsocketEvent::HuobiUSwapIncrementalOrderBook(msg) => self.on_huobi_book(&connection, msg).await?,
+                        HuobiUSwapWebsocketEvent::HuobiUSwapDepthSnapshot(ref msg) => self.on_huobi_depth_snapshot(&connection, msg).await?,
+                        HuobiUSwapWebsocketEvent::HuobiUSwapBBO(msg) => self.emit(&connection, WebsocketEvent::HuobiUSwapBBO(msg)),
+                        HuobiUSwapWebsocketEvent::HuobiUSwapKline(msg) => self.emit(&connection, WebsocketEvent::HuobiUSwapKline(msg)),
+                        HuobiUSwapWebsocketEvent::HuobiUSwapTradeDetail(msg) => self.emit(&connection, WebsocketEvent::HuobiUSwapTradeDetail(msg)),
+                        _ => (),
+                    }
+                }
+                else if connection.subscription == Subscription::HuobiUSwapOrderStream {
+                    let s = s?;
 
-        let sink = self.sinks.get_mut(&subscription).unwrap();
-        sink.send(tungstenite::Message::Text(message.to_string())).await?;
+                    trace!("Incoming websocket message {:?}", s);
+                    
+                    let msg = HuobiUSwapWebsocketEvent::parse(&s)?;
+                    match msg {
+                        HuobiUSwapWebsocketEvent::HuobiUSwapOpStatus(ref msg) => {
+                            if msg.op == "ping" {
+                                let ts = chrono::Local::now().timestamp_millis();
+                                let message = json!({
+                                    "op": "pong",
+                                    "ts": ts,       
+                                });
+                                debug!("### op pong: {:?}", message);
+                                self.send(&connection, message.to_string()).await?;
+            
+                            }
+                            if msg.op == "auth" {
+                                match msg.err_code {
+                                    Some(0) => self.sub_topics(&connection).await?,
+                                    Some(code) => return Err(Error::AuthRejected {
+                                        subscription: connection.to_string(),
+                                        code: code.to_string(),
+                                        msg: msg.err_msg.clone().unwrap_or_default(),
+                                    }),
+                                    None => (),
+                                }
+                            }
+                            if let Some(_err_code) = msg.err_code {
+                                info!("{:?}", msg);
+                            }
+                            self.on_huobi_op_status(&connection, msg);
+
+                        },
+                        HuobiUSwapWebsocketEvent::HuobiUSwapSubStatus(ref msg) => {
+                            info!("sub status:{:?}", msg);
+                        },
+                        HuobiUSwapWebsocketEvent::HuobiUSwapAccount(msg) => self.emit(&connection, WebsocketEvent::HuobiUSwapAccount(msg)),
+                        HuobiUSwapWebsocketEvent::HuobiUSwapOrder(msg) => self.emit(&connection, WebsocketEvent::HuobiUSwapOrder(msg)),
+                        HuobiUSwapWebsocketEvent::HuobiUSwapMatchOrder(msg) => self.emit(&connection, WebsocketEvent::HuobiUSwapMatchOrder(msg)),
+                        HuobiUSwapWebsocketEvent::HuobiUSwapPosition(msg) => self.emit(&connection, WebsocketEvent::HuobiUSwapPosition(msg)),
+                        _ => (),
+                    }
+
+                }
+            },
+
+            Message::Ping(b) => self.on_ping(&connection, &b),
+            Message::Pong(b) => self.record(&connection, Direction::Inbound, FrameKind::Pong, &String::from_utf8_lossy(&b)),
+            // raw frames are only produced when writing.
+            Message::Frame(_) => (),
+            Message::Close(frame) => {
+                let reason = frame.map_or_else(|| "closed by peer".to_string(), |frame| frame.to_string());
+                self.connection_lost(&connection, reason);
+            }
+        }
 
         Ok(())
     }
 
 
-    fn generate_signature(&mut self, subscription: Subscription, wspoint: &str, suffix: &str, params: & mut BTreeMap<String, String>) -> String
+    fn generate_signature(&self, connection: &ConnectionId, wspoint: &str, suffix: &str, params: &mut BTreeMap<String, String>) -> Result<String>
     {
-        let (key, secret) = self.check_key(&subscription).expect("no key");
+        let (key, secret) = self.check_key(connection)?;
 
         params.insert("AccessKeyId".to_string(), key.to_string());
         params.insert("SignatureMethod".to_string(), "HmacSHA256".to_string());
@@ -579,29 +781,29 @@ impl Websocket {
 
         let format_str = format!("{}\n{}\n{}\n{}", "GET", wspoint, suffix, build_params,); 
 
-        sign_hmac_sha256_base64(
+        Ok(sign_hmac_sha256_base64(
                     secret,
                     &format_str,
-            )
+            ))
 
     }
 
-    fn okex_generate_signature(&mut self, subscription: Subscription, timestamp: &str, method: &str, url: &str) -> (String, String, String) {
+    fn okex_generate_signature(&self, connection: &ConnectionId, timestamp: &str, method: &str, url: &str) -> Result<(String, String, String)> {
         // sign=CryptoJS.enc.Base64.stringify(CryptoJS.HmacSHA256(timestamp + 'GET' + '/users/self/verify' + body, SecretKey))
         use data_encoding::BASE64;
-        let (key, secret, passphrase) = self.okex_check_key(&subscription).expect("no key");
+        let (key, secret, passphrase) = self.okex_check_key(connection)?;
         let sign_message = format!("{}{}{}", timestamp, method, url);
         let signed_key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
         let signature = BASE64.encode(hmac::sign(&signed_key, sign_message.as_bytes()).as_ref());
-        (key.to_string(), passphrase.to_string(), signature)
+        Ok((key.to_string(), passphrase.to_string(), signature))
     }
 
-    fn ftx_generate_signature(&mut self, subscription: Subscription, timestamp: &str) -> (String, String, String) {
-        let (key, secret, subaccount) = self.ftx_check_key(&subscription).expect("no key");
+    fn ftx_generate_signature(&self, connection: &ConnectionId, timestamp: &str) -> Result<(String, String, String)> {
+        let (key, secret, subaccount) = self.ftx_check_key(connection)?;
         let sign_message = format!("{}websocket_login", timestamp);
         let signed_key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
         let signature = hex::encode(hmac::sign(&signed_key, sign_message.as_bytes()).as_ref());
-        (key.to_string(), subaccount.to_string(), signature) 
+        Ok((key.to_string(), subaccount.to_string(), signature))
     }
 
 }
@@ -614,6 +816,12 @@ pub fn build_query_string(parameters: BTreeMap<String, String>) -> String {
         .join("&")
 }
 
+#[must_use]
+pub fn sign_hmac_sha256_hex(secret: &str, digest: &str) -> String {
+    let signed_key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hex::encode(hmac::sign(&signed_key, digest.as_bytes()).as_ref())
+}
+
 pub fn sign_hmac_sha256_base64(secret: &str, digest: &str) -> String {
     use data_encoding::BASE64;
 
@@ -0,0 +1,539 @@
+//! Typed topics, translated into the stream names and subscribe requests of each exchange.
+//!
+//! `Websocket::connect` takes topics as the exchanges spell them, which means something
+//! different per `Subscription`. `Websocket::connect_topics` takes `Topic`s instead and
+//! rejects the combinations an exchange does not offer, or this crate does not decode, with
+//! `Error::UnsupportedTopic` before any connection is opened.
+use crate::{error::Error, models::Subscription};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{fmt, time::Duration};
+
+const HUOBI_ID: &str = "huobiusdtswap_rust";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interval {
+    Minute1,
+    Minute5,
+    Minute15,
+    Minute30,
+    Hour1,
+    Hour4,
+    Day1,
+    Week1,
+    Month1,
+}
+
+impl Interval {
+    const fn binance(self) -> &'static str {
+        match self {
+            Self::Minute1 => "1m",
+            Self::Minute5 => "5m",
+            Self::Minute15 => "15m",
+            Self::Minute30 => "30m",
+            Self::Hour1 => "1h",
+            Self::Hour4 => "4h",
+            Self::Day1 => "1d",
+            Self::Week1 => "1w",
+            Self::Month1 => "1M",
+        }
+    }
+
+    const fn huobi(self) -> &'static str {
+        match self {
+            Self::Minute1 => "1min",
+            Self::Minute5 => "5min",
+            Self::Minute15 => "15min",
+            Self::Minute30 => "30min",
+            Self::Hour1 => "60min",
+            Self::Hour4 => "4hour",
+            Self::Day1 => "1day",
+            Self::Week1 => "1week",
+            Self::Month1 => "1mon",
+        }
+    }
+}
+
+/// A stream of one exchange, independent of how the exchange names it.
+///
+/// Symbols are passed as the exchange spells them (`btcusdt` or `BTCUSDT` on Binance,
+/// `BTC-USDT` on Huobi and OKX, `BTC-PERP` on FTX).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Trades(String),
+    /// Best bid and ask.
+    BookTicker(String),
+    /// Rolling 24h statistics.
+    Ticker(String),
+    /// `levels: None` is the full book as a snapshot followed by updates, `Some(n)` the top
+    /// `n` levels pushed as snapshots. `speed` is the push interval, `None` for the default.
+    Depth { symbol: String, levels: Option<u32>, speed: Option<Duration> },
+    Kline(String, Interval),
+    /// Order updates, including their fills. The argument is the `instType` on OKX, the
+    /// contract code on Huobi (cross margin) and is ignored on FTX.
+    Orders(String),
+    /// The `instType` on OKX, the contract code on Huobi (cross margin).
+    Positions(String),
+    /// The currency on OKX (empty for every currency), the margin account on Huobi (cross
+    /// margin, e.g. `USDT`).
+    Account(String),
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Trades(symbol) => write!(f, "trades of {symbol}"),
+            Self::BookTicker(symbol) => write!(f, "book ticker of {symbol}"),
+            Self::Ticker(symbol) => write!(f, "ticker of {symbol}"),
+            Self::Depth { symbol, levels, speed } => write!(f, "depth of {symbol} (levels {levels:?}, speed {speed:?})"),
+            Self::Kline(symbol, interval) => write!(f, "{interval:?} klines of {symbol}"),
+            Self::Orders(filter) => write!(f, "orders of {filter:?}"),
+            Self::Positions(filter) => write!(f, "positions of {filter:?}"),
+            Self::Account(filter) => write!(f, "account of {filter:?}"),
+        }
+    }
+}
+
+/// Argument of an OKX `subscribe` or `unsubscribe` op.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OkexArg {
+    pub channel: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inst_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inst_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ccy: Option<String>,
+}
+
+impl OkexArg {
+    fn inst_id(channel: &str, inst_id: &str) -> Self {
+        Self { channel: channel.to_string(), inst_id: Some(inst_id.to_string()), inst_type: None, ccy: None }
+    }
+
+    fn inst_type(channel: &str, inst_type: &str) -> Self {
+        Self { channel: channel.to_string(), inst_id: None, inst_type: Some(inst_type.to_string()), ccy: None }
+    }
+
+    fn channel(channel: &str) -> Self {
+        Self { channel: channel.to_string(), inst_id: None, inst_type: None, ccy: None }
+    }
+}
+
+/// A topic as one exchange spells it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NativeTopic {
+    /// Binance stream name, part of the connection url.
+    Binance(String),
+    /// Huobi `ch` on the market stream, `topic` on the order stream.
+    Huobi(String),
+    Okex(OkexArg),
+    /// FTX `channel`, with the `market` of market data channels.
+    Ftx { channel: String, market: Option<String> },
+}
+
+impl NativeTopic {
+    pub(crate) fn ftx(channel: &str, market: Option<&str>) -> Self {
+        Self::Ftx { channel: channel.to_string(), market: market.map(ToString::to_string) }
+    }
+}
+
+const fn is_private(subscription: &Subscription) -> bool {
+    matches!(subscription,
+        Subscription::BinanceSpotOrder
+        | Subscription::BinanceUSwapOrder
+        | Subscription::HuobiUSwapOrderStream
+        | Subscription::OkexOrderStream
+        | Subscription::FtxOrderStream)
+}
+
+fn millis(speed: Option<Duration>) -> Option<u128> {
+    speed.map(|speed| speed.as_millis())
+}
+
+impl Topic {
+    /// Translates the topic for `subscription`, some topics take several channels on one
+    /// exchange.
+    pub fn native(&self, subscription: &Subscription) -> Result<Vec<NativeTopic>, Error> {
+        let private = matches!(self, Self::Orders(_) | Self::Positions(_) | Self::Account(_));
+        if private != is_private(subscription) {
+            let reason = if private { "a private topic on a market stream" } else { "a market topic on a private stream" };
+            return Err(self.unsupported(subscription, reason));
+        }
+
+        match *subscription {
+            Subscription::BinanceSpotMStream | Subscription::BinanceUSwapMStream => self.binance(subscription),
+            Subscription::BinanceSpotOrder | Subscription::BinanceUSwapOrder =>
+                Err(self.unsupported(subscription, "Binance private streams are selected by the listen key, use `connect`")),
+            Subscription::BinanceBLVTStream =>
+                Err(self.unsupported(subscription, "BLVT streams are only available through `connect`")),
+            Subscription::BinanceSpotApi | Subscription::BinanceUSwapApi =>
+                Err(self.unsupported(subscription, "the WebSocket API has no streams, use `Websocket::binance_api`")),
+            Subscription::HuobiUSwapMarketStream | Subscription::HuobiUSwapOrderStream => self.huobi(subscription),
+            Subscription::OkexMarketStream | Subscription::OkexOrderStream => self.okex(subscription),
+            Subscription::FtxMarketStream | Subscription::FtxOrderStream => self.ftx(subscription),
+        }
+    }
+
+    fn unsupported(&self, subscription: &Subscription, reason: &str) -> Error {
+        Error::UnsupportedTopic {
+            topic: self.to_string(),
+            subscription: format!("{subscription:?}"),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn binance(&self, subscription: &Subscription) -> Result<Vec<NativeTopic>, Error> {
+        let spot = *subscription == Subscription::BinanceSpotMStream;
+        let stream = match self {
+            Self::Trades(symbol) => format!("{}@trade", symbol.to_lowercase()),
+            Self::BookTicker(symbol) => format!("{}@bookTicker", symbol.to_lowercase()),
+            Self::Ticker(symbol) => format!("{}@ticker", symbol.to_lowercase()),
+            Self::Kline(symbol, interval) => format!("{}@kline_{}", symbol.to_lowercase(), interval.binance()),
+            Self::Depth { symbol, levels, speed } => {
+                let levels = match levels {
+                    None => String::new(),
+                    Some(levels @ (5 | 10 | 20)) => levels.to_string(),
+                    Some(_) => return Err(self.unsupported(subscription, "Binance partial depth has 5, 10 or 20 levels")),
+                };
+                let speed = match (spot, millis(*speed)) {
+                    (true, None | Some(1000)) | (false, None | Some(250)) => "",
+                    (_, Some(100)) => "@100ms",
+                    (false, Some(500)) => "@500ms",
+                    (true, Some(_)) => return Err(self.unsupported(subscription, "Binance spot depth is pushed every 1000ms or 100ms")),
+                    (false, Some(_)) => return Err(self.unsupported(subscription, "Binance futures depth is pushed every 250ms, 500ms or 100ms")),
+                };
+                format!("{}@depth{levels}{speed}", symbol.to_lowercase())
+            }
+            Self::Orders(_) | Self::Positions(_) | Self::Account(_) =>
+                return Err(self.unsupported(subscription, "Binance private streams are selected by the listen key, use `connect`")),
+        };
+        Ok(vec![NativeTopic::Binance(stream)])
+    }
+
+    fn huobi(&self, subscription: &Subscription) -> Result<Vec<NativeTopic>, Error> {
+        let ch = match self {
+            Self::Trades(symbol) => format!("market.{symbol}.trade.detail"),
+            Self::BookTicker(symbol) => format!("market.{symbol}.bbo"),
+            Self::Kline(symbol, interval) => format!("market.{symbol}.kline.{}", interval.huobi()),
+            Self::Depth { speed: Some(_), .. } => return Err(self.unsupported(subscription, "Huobi depth has no push interval")),
+            Self::Depth { symbol, levels: None, .. } => format!("market.{symbol}.depth.size_150.high_freq"),
+            Self::Depth { symbol, levels: Some(150), .. } => format!("market.{symbol}.depth.step0"),
+            Self::Depth { symbol, levels: Some(20), .. } => format!("market.{symbol}.depth.step6"),
+            Self::Depth { .. } => return Err(self.unsupported(subscription, "Huobi depth has 20 or 150 levels")),
+            Self::Ticker(_) => return Err(self.unsupported(subscription, "Huobi market details are not decoded")),
+            Self::Orders(contract_code) => format!("orders_cross.{}", contract_code.to_lowercase()),
+            Self::Positions(contract_code) => format!("positions_cross.{}", contract_code.to_lowercase()),
+            Self::Account(margin_account) => format!("accounts_cross.{}", margin_account.to_lowercase()),
+        };
+        Ok(vec![NativeTopic::Huobi(ch)])
+    }
+
+    fn okex(&self, subscription: &Subscription) -> Result<Vec<NativeTopic>, Error> {
+        let arg = match self {
+            Self::Trades(inst_id) => OkexArg::inst_id("trades", inst_id),
+            Self::Depth { speed: Some(_), .. } => return Err(self.unsupported(subscription, "OKX book channels have a fixed push interval")),
+            Self::Depth { symbol, levels: None | Some(400), .. } => OkexArg::inst_id("books", symbol),
+            Self::Depth { symbol, levels: Some(50), .. } => OkexArg::inst_id("books50-l2-tbt", symbol),
+            Self::Depth { .. } => return Err(self.unsupported(subscription, "OKX books have 50 or 400 levels")),
+            Self::BookTicker(_) | Self::Ticker(_) | Self::Kline(..) =>
+                return Err(self.unsupported(subscription, "OKX tickers and candles are not decoded")),
+            Self::Orders(inst_type) => OkexArg::inst_type("orders", inst_type),
+            Self::Positions(inst_type) => OkexArg::inst_type("positions", inst_type),
+            Self::Account(ccy) => OkexArg {
+                ccy: (!ccy.is_empty()).then(|| ccy.clone()),
+                ..OkexArg::channel("account")
+            },
+        };
+        Ok(vec![NativeTopic::Okex(arg)])
+    }
+
+    fn ftx(&self, subscription: &Subscription) -> Result<Vec<NativeTopic>, Error> {
+        match self {
+            Self::Trades(market) => Ok(vec![NativeTopic::ftx("trades", Some(market))]),
+            Self::BookTicker(market) => Ok(vec![NativeTopic::ftx("ticker", Some(market))]),
+            Self::Depth { symbol, levels: None | Some(100), speed: None } => Ok(vec![NativeTopic::ftx("orderbook", Some(symbol))]),
+            Self::Depth { .. } => Err(self.unsupported(subscription, "FTX books have 100 levels and a fixed push interval")),
+            Self::Orders(_) => Ok(vec![NativeTopic::ftx("orders", None), NativeTopic::ftx("fills", None)]),
+            Self::Ticker(_) | Self::Kline(..) | Self::Positions(_) | Self::Account(_) =>
+                Err(self.unsupported(subscription, "not offered by FTX websockets")),
+        }
+    }
+}
+
+/// Translates the topics given to `Websocket::connect`.
+///
+/// They are Binance stream names, Huobi topics, OKX `instId`s (books, price limits and trades)
+/// or `instType`s (orders, plus account, positions and balances), FTX markets (books, tickers
+/// and trades). The FTX order stream always takes fills and orders.
+#[must_use]
+pub fn legacy(subscription: &Subscription, topics: &[&str]) -> Vec<NativeTopic> {
+    match *subscription {
+        Subscription::BinanceSpotMStream
+        | Subscription::BinanceSpotOrder
+        | Subscription::BinanceUSwapMStream
+        | Subscription::BinanceBLVTStream
+        | Subscription::BinanceUSwapOrder =>
+            topics.iter().map(|topic| NativeTopic::Binance((*topic).to_string())).collect(),
+        Subscription::BinanceSpotApi | Subscription::BinanceUSwapApi => Vec::new(),
+        Subscription::HuobiUSwapMarketStream | Subscription::HuobiUSwapOrderStream =>
+            topics.iter().map(|topic| NativeTopic::Huobi((*topic).to_string())).collect(),
+        Subscription::OkexMarketStream => topics.iter()
+            .flat_map(|inst_id| ["books50-l2-tbt", "price-limit", "trades"].map(|channel| NativeTopic::Okex(OkexArg::inst_id(channel, inst_id))))
+            .collect(),
+        Subscription::OkexOrderStream => std::iter::once(OkexArg::channel("balance_and_position"))
+            .chain(topics.iter().map(|inst_type| OkexArg::inst_type("orders", inst_type)))
+            .chain([OkexArg::channel("account"), OkexArg::inst_type("positions", "ANY")])
+            .map(NativeTopic::Okex)
+            .collect(),
+        Subscription::FtxMarketStream => topics.iter()
+            .flat_map(|market| ["orderbook", "ticker", "trades"].map(|channel| NativeTopic::ftx(channel, Some(market))))
+            .collect(),
+        Subscription::FtxOrderStream => vec![NativeTopic::ftx("fills", None), NativeTopic::ftx("orders", None)],
+    }
+}
+
+/// The Binance stream names among `topics`, they make up the connection url.
+pub(crate) fn binance_streams(topics: &[NativeTopic]) -> Vec<&str> {
+    topics.iter()
+        .filter_map(|topic| match topic {
+            NativeTopic::Binance(stream) => Some(stream.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Subscribe,
+    Unsubscribe,
+}
+
+/// The requests subscribing or unsubscribing `topics` on a connection of `subscription`,
+/// with the topics each one covers. `id` identifies the Binance request in its answer.
+pub(crate) fn requests(op: Op, subscription: &Subscription, topics: &[NativeTopic], id: u64) -> Vec<(String, Vec<NativeTopic>)> {
+    let subscribe = op == Op::Subscribe;
+    let mut requests = Vec::new();
+    let mut binance = Vec::new();
+    let mut okex = Vec::new();
+
+    for topic in topics {
+        let message = match topic {
+            NativeTopic::Binance(_) => {
+                binance.push(topic.clone());
+                continue;
+            }
+            NativeTopic::Okex(_) => {
+                okex.push(topic.clone());
+                continue;
+            }
+            NativeTopic::Huobi(ch) if *subscription == Subscription::HuobiUSwapOrderStream => json!({
+                "op": if subscribe { "sub" } else { "unsub" },
+                "cid": HUOBI_ID,
+                "topic": ch,
+            }),
+            // without `data_type` Huobi pushes every book as a snapshot.
+            NativeTopic::Huobi(ch) if subscribe && ch.ends_with(".high_freq") => json!({
+                "sub": ch,
+                "data_type": "incremental",
+                "id": HUOBI_ID,
+            }),
+            NativeTopic::Huobi(ch) if subscribe => json!({
+                "sub": ch,
+                "id": HUOBI_ID,
+            }),
+            NativeTopic::Huobi(ch) => json!({
+                "unsub": ch,
+                "id": HUOBI_ID,
+            }),
+            NativeTopic::Ftx { channel, market: Some(market) } => json!({
+                "op": if subscribe { "subscribe" } else { "unsubscribe" },
+                "channel": channel,
+                "market": market,
+            }),
+            NativeTopic::Ftx { channel, market: None } => json!({
+                "op": if subscribe { "subscribe" } else { "unsubscribe" },
+                "channel": channel,
+            }),
+        };
+        requests.push((message.to_string(), vec![topic.clone()]));
+    }
+
+    if !binance.is_empty() {
+        let streams = binance_streams(&binance);
+        let message = json!({
+            "method": if subscribe { "SUBSCRIBE" } else { "UNSUBSCRIBE" },
+            "params": streams,
+            "id": id,
+        });
+        requests.push((message.to_string(), binance));
+    }
+
+    if !okex.is_empty() {
+        let args: Vec<&OkexArg> = okex.iter()
+            .filter_map(|topic| match topic {
+                NativeTopic::Okex(arg) => Some(arg),
+                _ => None,
+            })
+            .collect();
+        let message = json!({
+            "op": if subscribe { "subscribe" } else { "unsubscribe" },
+            "args": args,
+        });
+        requests.push((message.to_string(), okex));
+    }
+
+    requests
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn depth(symbol: &str, levels: Option<u32>, speed: Option<u64>) -> Topic {
+        Topic::Depth { symbol: symbol.to_string(), levels, speed: speed.map(Duration::from_millis) }
+    }
+
+    fn okex(channel: &str, inst_id: &str) -> NativeTopic {
+        NativeTopic::Okex(OkexArg::inst_id(channel, inst_id))
+    }
+
+    #[test]
+    fn topics_are_spelled_per_exchange() {
+        let cases = [
+            (Subscription::BinanceSpotMStream, Topic::Trades("BTCUSDT".to_string()), vec![NativeTopic::Binance("btcusdt@trade".to_string())]),
+            (Subscription::BinanceUSwapMStream, Topic::BookTicker("BTCUSDT".to_string()), vec![NativeTopic::Binance("btcusdt@bookTicker".to_string())]),
+            (Subscription::BinanceSpotMStream, Topic::Ticker("ETHUSDT".to_string()), vec![NativeTopic::Binance("ethusdt@ticker".to_string())]),
+            (Subscription::BinanceSpotMStream, Topic::Kline("BTCUSDT".to_string(), Interval::Month1), vec![NativeTopic::Binance("btcusdt@kline_1M".to_string())]),
+            (Subscription::HuobiUSwapMarketStream, Topic::Trades("BTC-USDT".to_string()), vec![NativeTopic::Huobi("market.BTC-USDT.trade.detail".to_string())]),
+            (Subscription::HuobiUSwapMarketStream, Topic::BookTicker("BTC-USDT".to_string()), vec![NativeTopic::Huobi("market.BTC-USDT.bbo".to_string())]),
+            (Subscription::HuobiUSwapMarketStream, Topic::Kline("BTC-USDT".to_string(), Interval::Hour1), vec![NativeTopic::Huobi("market.BTC-USDT.kline.60min".to_string())]),
+            (Subscription::HuobiUSwapOrderStream, Topic::Orders("BTC-USDT".to_string()), vec![NativeTopic::Huobi("orders_cross.btc-usdt".to_string())]),
+            (Subscription::HuobiUSwapOrderStream, Topic::Account("USDT".to_string()), vec![NativeTopic::Huobi("accounts_cross.usdt".to_string())]),
+            (Subscription::OkexMarketStream, Topic::Trades("BTC-USDT".to_string()), vec![okex("trades", "BTC-USDT")]),
+            (Subscription::OkexMarketStream, depth("BTC-USDT", None, None), vec![okex("books", "BTC-USDT")]),
+            (Subscription::OkexMarketStream, depth("BTC-USDT", Some(400), None), vec![okex("books", "BTC-USDT")]),
+            (Subscription::OkexMarketStream, depth("BTC-USDT", Some(50), None), vec![okex("books50-l2-tbt", "BTC-USDT")]),
+            (Subscription::OkexOrderStream, Topic::Orders("SWAP".to_string()), vec![NativeTopic::Okex(OkexArg::inst_type("orders", "SWAP"))]),
+            (Subscription::OkexOrderStream, Topic::Account(String::new()), vec![NativeTopic::Okex(OkexArg::channel("account"))]),
+            (Subscription::OkexOrderStream, Topic::Account("USDT".to_string()), vec![NativeTopic::Okex(OkexArg { ccy: Some("USDT".to_string()), ..OkexArg::channel("account") })]),
+            (Subscription::FtxMarketStream, Topic::BookTicker("BTC-PERP".to_string()), vec![NativeTopic::ftx("ticker", Some("BTC-PERP"))]),
+            (Subscription::FtxMarketStream, depth("BTC-PERP", Some(100), None), vec![NativeTopic::ftx("orderbook", Some("BTC-PERP"))]),
+            (Subscription::FtxOrderStream, Topic::Orders(String::new()), vec![NativeTopic::ftx("orders", None), NativeTopic::ftx("fills", None)]),
+        ];
+        for (subscription, topic, expected) in cases {
+            assert_eq!(topic.native(&subscription).unwrap(), expected, "{topic} on {subscription:?}");
+        }
+    }
+
+    #[test]
+    fn binance_depth_levels_and_speed() {
+        let cases = [
+            (Subscription::BinanceSpotMStream, None, None, Some("btcusdt@depth")),
+            (Subscription::BinanceSpotMStream, None, Some(1000), Some("btcusdt@depth")),
+            (Subscription::BinanceSpotMStream, None, Some(100), Some("btcusdt@depth@100ms")),
+            (Subscription::BinanceSpotMStream, Some(5), None, Some("btcusdt@depth5")),
+            (Subscription::BinanceSpotMStream, Some(20), Some(100), Some("btcusdt@depth20@100ms")),
+            (Subscription::BinanceSpotMStream, None, Some(250), None),
+            (Subscription::BinanceSpotMStream, None, Some(500), None),
+            (Subscription::BinanceUSwapMStream, None, None, Some("btcusdt@depth")),
+            (Subscription::BinanceUSwapMStream, None, Some(250), Some("btcusdt@depth")),
+            (Subscription::BinanceUSwapMStream, Some(10), Some(500), Some("btcusdt@depth10@500ms")),
+            (Subscription::BinanceUSwapMStream, None, Some(100), Some("btcusdt@depth@100ms")),
+            (Subscription::BinanceUSwapMStream, None, Some(1000), None),
+            (Subscription::BinanceUSwapMStream, Some(15), None, None),
+            (Subscription::BinanceSpotMStream, Some(50), None, None),
+        ];
+        for (subscription, levels, speed, expected) in cases {
+            let native = depth("BTCUSDT", levels, speed).native(&subscription);
+            match expected {
+                Some(stream) => assert_eq!(native.unwrap(), [NativeTopic::Binance(stream.to_string())], "{levels:?} {speed:?} on {subscription:?}"),
+                None => assert!(matches!(native, Err(Error::UnsupportedTopic { .. })), "{levels:?} {speed:?} on {subscription:?}: {native:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn huobi_depth_levels() {
+        let cases = [
+            (None, None, Some("market.BTC-USDT.depth.size_150.high_freq")),
+            (Some(150), None, Some("market.BTC-USDT.depth.step0")),
+            (Some(20), None, Some("market.BTC-USDT.depth.step6")),
+            (Some(50), None, None),
+            (None, Some(100), None),
+        ];
+        for (levels, speed, expected) in cases {
+            let native = depth("BTC-USDT", levels, speed).native(&Subscription::HuobiUSwapMarketStream);
+            match expected {
+                Some(ch) => assert_eq!(native.unwrap(), [NativeTopic::Huobi(ch.to_string())], "{levels:?} {speed:?}"),
+                None => assert!(matches!(native, Err(Error::UnsupportedTopic { .. })), "{levels:?} {speed:?}: {native:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_topics_are_rejected() {
+        let cases = [
+            (Subscription::BinanceSpotMStream, Topic::Orders("BTCUSDT".to_string())),
+            (Subscription::BinanceSpotOrder, Topic::Orders("BTCUSDT".to_string())),
+            (Subscription::BinanceUSwapOrder, Topic::Account("USDT".to_string())),
+            (Subscription::BinanceBLVTStream, Topic::Trades("BTCUP".to_string())),
+            (Subscription::BinanceSpotApi, Topic::Trades("BTCUSDT".to_string())),
+            (Subscription::HuobiUSwapMarketStream, Topic::Ticker("BTC-USDT".to_string())),
+            (Subscription::HuobiUSwapOrderStream, Topic::Trades("BTC-USDT".to_string())),
+            (Subscription::OkexMarketStream, Topic::BookTicker("BTC-USDT".to_string())),
+            (Subscription::OkexMarketStream, depth("BTC-USDT", Some(5), None)),
+            (Subscription::OkexMarketStream, depth("BTC-USDT", None, Some(100))),
+            (Subscription::OkexOrderStream, Topic::Trades("BTC-USDT".to_string())),
+            (Subscription::FtxMarketStream, Topic::Kline("BTC-PERP".to_string(), Interval::Minute1)),
+            (Subscription::FtxMarketStream, depth("BTC-PERP", None, Some(100))),
+            (Subscription::FtxOrderStream, Topic::Positions(String::new())),
+        ];
+        for (subscription, topic) in cases {
+            let native = topic.native(&subscription);
+            assert!(matches!(&native, Err(Error::UnsupportedTopic { subscription: name, .. }) if *name == format!("{subscription:?}")), "{topic} on {subscription:?}: {native:?}");
+        }
+    }
+
+    fn messages(op: Op, subscription: &Subscription, topics: &[NativeTopic]) -> Vec<Value> {
+        requests(op, subscription, topics, 7).into_iter()
+            .map(|(message, _)| serde_json::from_str(&message).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn requests_per_exchange() {
+        let binance = [NativeTopic::Binance("btcusdt@trade".to_string()), NativeTopic::Binance("btcusdt@depth".to_string())];
+        assert_eq!(messages(Op::Subscribe, &Subscription::BinanceUSwapMStream, &binance), [
+            json!({ "method": "SUBSCRIBE", "params": ["btcusdt@trade", "btcusdt@depth"], "id": 7 }),
+        ]);
+        assert_eq!(messages(Op::Unsubscribe, &Subscription::BinanceUSwapMStream, &binance[..1]), [
+            json!({ "method": "UNSUBSCRIBE", "params": ["btcusdt@trade"], "id": 7 }),
+        ]);
+
+        let huobi = [NativeTopic::Huobi("market.BTC-USDT.depth.size_150.high_freq".to_string()), NativeTopic::Huobi("market.BTC-USDT.bbo".to_string())];
+        assert_eq!(messages(Op::Subscribe, &Subscription::HuobiUSwapMarketStream, &huobi), [
+            json!({ "sub": "market.BTC-USDT.depth.size_150.high_freq", "data_type": "incremental", "id": HUOBI_ID }),
+            json!({ "sub": "market.BTC-USDT.bbo", "id": HUOBI_ID }),
+        ]);
+        assert_eq!(messages(Op::Unsubscribe, &Subscription::HuobiUSwapMarketStream, &huobi[..1]), [
+            json!({ "unsub": "market.BTC-USDT.depth.size_150.high_freq", "id": HUOBI_ID }),
+        ]);
+        assert_eq!(messages(Op::Subscribe, &Subscription::HuobiUSwapOrderStream, &[NativeTopic::Huobi("orders_cross.btc-usdt".to_string())]), [
+            json!({ "op": "sub", "cid": HUOBI_ID, "topic": "orders_cross.btc-usdt" }),
+        ]);
+
+        let okex_topics = [okex("trades", "BTC-USDT"), okex("books", "BTC-USDT")];
+        assert_eq!(messages(Op::Subscribe, &Subscription::OkexMarketStream, &okex_topics), [
+            json!({ "op": "subscribe", "args": [{ "channel": "trades", "instId": "BTC-USDT" }, { "channel": "books", "instId": "BTC-USDT" }] }),
+        ]);
+
+        let ftx = [NativeTopic::ftx("orderbook", Some("BTC-PERP")), NativeTopic::ftx("fills", None)];
+        assert_eq!(messages(Op::Unsubscribe, &Subscription::FtxMarketStream, &ftx), [
+            json!({ "op": "unsubscribe", "channel": "orderbook", "market": "BTC-PERP" }),
+            json!({ "op": "unsubscribe", "channel": "fills" }),
+        ]);
+    }
+}
@@ -1,22 +1,38 @@
 use crate::{
-    error::Error,
-    models::*,
+    binance_orderbook::{DepthMarket, DepthSynchronizer, PendingSnapshot, RestSnapshotProvider, SnapshotProvider},
+    borrowed::BorrowedHandler,
+    control::{Command, TopicRequests, TopicTable},
+    endpoint::Endpoint,
+    error::{self, Error, Result},
+    ftx_orderbook,
+    heartbeat::{self, Heartbeat, HeartbeatPolicy},
+    huobi_orderbook::{self, PendingSnapshotRetry},
+    listen_key::{ManagedListenKey, PendingListenKey, KEEPALIVE_INTERVAL},
+    models::{ConnectionId, DecodePolicy, Envelope, Subscription, WebsocketEvent},
+    normalized::NormalizedEvent,
+    okex_orderbook,
+    reconnect::ReconnectPolicy,
+    recorder::Recorder,
+    rpc::{Responder, RpcRequest},
+    topic::{self, NativeTopic},
 };
-use failure::Fallible;
-use futures::{prelude::*, stream::SplitStream, stream::SplitSink};
-use serde_json::from_str;
+use chrono::{DateTime, Utc};
+use futures::{prelude::*, stream::BoxStream, stream::FuturesUnordered, stream::SplitStream, stream::SplitSink};
+use serde::de::DeserializeOwned;
+use snafu::ResultExt;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     pin::Pin,
-    task::{Context, Poll},
-    time::Duration,
+    sync::{atomic::AtomicU64, Arc},
+    time::{Duration, Instant},
 };
-use streamunordered::{StreamUnordered, StreamYield};
+use streamunordered::StreamUnordered;
 use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use tokio::time::Interval;
-use tracing::*;
-use tungstenite::Message;
+use tracing::{info, trace};
+use tungstenite::{handshake::client::Request, Message};
 use url::Url;
 use std::io::Read;
 use flate2::read::GzDecoder;
@@ -27,178 +43,460 @@ type WSStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 pub type StoredStream = SplitStream<WSStream>;
 pub type StoredSink = SplitSink<WSStream, tungstenite::Message>;
 
+/// A reconnect attempt in flight: resolves to the connection, the attempt number, the topics
+/// its request was built with and the freshly opened socket.
+type PendingReconnect = Pin<Box<dyn Future<Output = (ConnectionId, u32, Vec<NativeTopic>, Result<WSStream>)> + Send>>;
+
+/// The backoff delay before a reconnect attempt: resolves to the connection and the attempt
+/// number once it is time to connect.
+type ReconnectDelay = Pin<Box<dyn Future<Output = (ConnectionId, u32)> + Send>>;
+
+/// The handler of `new_normalized_enveloped`.
+type NormalizedHandler = Box<dyn FnMut(ConnectionId, NormalizedEvent) -> Result<()> + Send>;
+
 #[allow(clippy::module_name_repetitions)]
 pub struct Websocket  {
-    credentials: HashMap<Subscription, (String, String, String)>,
-    subscriptions: HashMap<Subscription, usize>,
+    credentials: HashMap<ConnectionId, (String, String, String)>,
+    subscriptions: HashMap<ConnectionId, usize>,
+    pub(crate) topics: HashMap<ConnectionId, Vec<NativeTopic>>,
+    pub(crate) topic_table: TopicTable,
+    pub(crate) topic_requests: TopicRequests,
+    pub(crate) next_request_id: u64,
+    pub(crate) commands_tx: UnboundedSender<Command>,
+    pub(crate) commands: UnboundedReceiver<Command>,
+    pub(crate) reconnect_policy: ReconnectPolicy,
+    pub(crate) decode_policy: DecodePolicy,
+    pub(crate) unparsed: u64,
+    pub(crate) reconnect_delays: FuturesUnordered<ReconnectDelay>,
+    pub(crate) reconnects: FuturesUnordered<PendingReconnect>,
+    pub(crate) depth_books: HashMap<ConnectionId, DepthSynchronizer>,
+    pub(crate) snapshot_provider: Arc<dyn SnapshotProvider>,
+    pub(crate) snapshots: FuturesUnordered<PendingSnapshot>,
+    pub(crate) okex_books: HashMap<ConnectionId, okex_orderbook::OrderBooks>,
+    pub(crate) huobi_books: HashMap<ConnectionId, huobi_orderbook::OrderBooks>,
+    pub(crate) huobi_snapshot_retries: FuturesUnordered<PendingSnapshotRetry>,
+    pub(crate) ftx_books: HashMap<ConnectionId, ftx_orderbook::OrderBooks>,
+    pub(crate) endpoints: HashMap<ConnectionId, Endpoint>,
+    pub(crate) borrowed: HashMap<ConnectionId, BorrowedHandler>,
+    pub(crate) listen_keys: HashMap<ConnectionId, ManagedListenKey>,
+    pub(crate) listen_key_requests: FuturesUnordered<PendingListenKey>,
+    pub(crate) listen_key_timer: Interval,
+    pub(crate) rpc_tx: UnboundedSender<RpcRequest>,
+    pub(crate) rpc_requests: UnboundedReceiver<RpcRequest>,
+    /// Requests sent and not answered yet, by connection and id.
+    pub(crate) rpc_pending: HashMap<ConnectionId, HashMap<String, Responder>>,
+    pub(crate) rpc_ids: Arc<AtomicU64>,
+    pub(crate) recorder: Option<Recorder>,
+    /// Set while a replayed frame is handled, see `replay`.
+    pub(crate) replaying: bool,
+    /// Events decoded but not yet handed to the handler or the event stream.
+    pub(crate) pending: VecDeque<Envelope>,
+    /// When the frame being handled was received, see `stamp`.
+    pub(crate) received: (Instant, DateTime<Utc>),
+    /// The next `Envelope::sequence` of each connection.
+    pub(crate) sequences: HashMap<ConnectionId, u64>,
+    pub(crate) heartbeats: HashMap<ConnectionId, Heartbeat>,
+    pub(crate) heartbeat_policies: HashMap<Subscription, HeartbeatPolicy>,
     pub streams: StreamUnordered<StoredStream>,
-    pub tokens: HashMap<usize, Subscription>,
-    pub sinks: HashMap<Subscription, StoredSink>,
-    pub handler: Box<dyn FnMut(WebsocketEvent) -> Fallible<()>>,
+    pub tokens: HashMap<usize, ConnectionId>,
+    pub sinks: HashMap<ConnectionId, StoredSink>,
+    pub handler: Box<dyn FnMut(Envelope) -> Result<()> + Send>,
+    /// The handler of `new_normalized`, called instead of `handler`.
+    pub(crate) normalized_handler: Option<NormalizedHandler>,
+    /// Checks the heartbeats, see `heartbeat`.
     pub ping_timer: Interval,
 }
 
 impl Websocket {
-    pub fn new<Callback: 'static>(credentials: HashMap<Subscription,(String, String, String)>, handler: Callback) -> Self
+    /// `credentials` are those of the default account, see `add_account` for others.
+    pub fn new<Callback>(credentials: HashMap<Subscription,(String, String, String)>, mut handler: Callback) -> Self
+    where
+        Callback: FnMut(WebsocketEvent) -> Result<()> + Send + 'static
+    {
+        Self::new_enveloped(credentials, move |envelope: Envelope| handler(envelope.event))
+    }
+
+    /// Creates a client whose handler receives every event with the connection, and so the
+    /// account, it came from.
+    pub fn new_enveloped<Callback>(credentials: HashMap<Subscription,(String, String, String)>, handler: Callback) -> Self
     where
-        Callback: FnMut(WebsocketEvent) -> Fallible<()> + Send
+        Callback: FnMut(Envelope) -> Result<()> + Send + 'static
     {
+        let (commands_tx, commands) = mpsc::unbounded_channel();
+        let (rpc_tx, rpc_requests) = mpsc::unbounded_channel();
         Self {
-            credentials: credentials.clone(),
+            credentials: credentials.into_iter().map(|(subscription, keys)| (subscription.into(), keys)).collect(),
             subscriptions: HashMap::new(),
+            topics: HashMap::new(),
+            topic_table: TopicTable::default(),
+            topic_requests: HashMap::new(),
+            next_request_id: 0,
+            commands_tx,
+            commands,
+            reconnect_policy: ReconnectPolicy::default(),
+            decode_policy: DecodePolicy::default(),
+            unparsed: 0,
+            reconnect_delays: FuturesUnordered::new(),
+            reconnects: FuturesUnordered::new(),
+            depth_books: HashMap::new(),
+            snapshot_provider: Arc::new(RestSnapshotProvider::default()),
+            snapshots: FuturesUnordered::new(),
+            okex_books: HashMap::new(),
+            huobi_books: HashMap::new(),
+            huobi_snapshot_retries: FuturesUnordered::new(),
+            ftx_books: HashMap::new(),
+            endpoints: HashMap::new(),
+            borrowed: HashMap::new(),
+            listen_keys: HashMap::new(),
+            listen_key_requests: FuturesUnordered::new(),
+            listen_key_timer: tokio::time::interval_at(tokio::time::Instant::now() + KEEPALIVE_INTERVAL, KEEPALIVE_INTERVAL),
+            rpc_tx,
+            rpc_requests,
+            rpc_pending: HashMap::new(),
+            rpc_ids: Arc::new(AtomicU64::new(1)),
+            recorder: None,
+            replaying: false,
+            pending: VecDeque::new(),
+            received: (Instant::now(), Utc::now()),
+            sequences: HashMap::new(),
+            heartbeats: HashMap::new(),
+            heartbeat_policies: HashMap::new(),
             tokens: HashMap::new(),
             streams: StreamUnordered::new(),
             sinks: HashMap::new(),
             handler: Box::new(handler),
-            ping_timer: tokio::time::interval(Duration::from_secs(5)),
+            normalized_handler: None,
+            ping_timer: tokio::time::interval(heartbeat::CHECK_INTERVAL),
         }
     }
 
-    pub async fn subscribe(&mut self, subscription: Subscription, topics: &Vec<&str> ) -> Fallible<()> {
-        let ws_url = match subscription {
-            Subscription::BinanceSpotMStream => "wss://stream.binance.com:9443",
-            Subscription::BinanceSpotOrder => "wss://stream.binance.com:9443",
-            Subscription::BinanceUSwapMStream => "wss://fstream.binance.com",
-            Subscription::BinanceBLVTStream => "wss://nbstream.binance.com/lvt-p",
-            Subscription::BinanceUSwapOrder => "wss://fstream.binance.com",
+    /// Creates a client without a handler, for use with `events` or `into_stream`.
+    #[must_use]
+    pub fn new_streaming(credentials: HashMap<Subscription,(String, String, String)>) -> Self {
+        Self::new(credentials, |_| Ok(()))
+    }
 
-            Subscription::HuobiUSwapMarketStream => "wss://api.hbdm.vn",
-            Subscription::HuobiUSwapOrderStream => "wss://api.hbdm.vn",
+    /// Creates a client whose handler receives exchange-agnostic events, see `normalized`.
+    /// Events without a normalized form are not delivered, items that cannot be converted are
+    /// handled according to the `DecodePolicy`.
+    pub fn new_normalized<Callback>(credentials: HashMap<Subscription,(String, String, String)>, mut handler: Callback) -> Self
+    where
+        Callback: FnMut(NormalizedEvent) -> Result<()> + Send + 'static
+    {
+        Self::new_normalized_enveloped(credentials, move |_, event| handler(event))
+    }
 
-            Subscription::OkexMarketStream => "wss://wsaws.okex.com:8443",
-            Subscription::OkexOrderStream => "wss://wsaws.okex.com:8443",
+    /// `new_normalized` with the connection, and so the account, each event came from.
+    pub fn new_normalized_enveloped<Callback>(credentials: HashMap<Subscription,(String, String, String)>, handler: Callback) -> Self
+    where
+        Callback: FnMut(ConnectionId, NormalizedEvent) -> Result<()> + Send + 'static
+    {
+        let mut ws = Self::new_streaming(credentials);
+        ws.normalized_handler = Some(Box::new(handler));
+        ws
+    }
 
-            Subscription::FtxMarketStream => "wss://ftx.com",
-            Subscription::FtxOrderStream => "wss://ftx.com",
-        };
+    /// Yields the events of every open connection, the same ones `connect` hands to the
+    /// handler. Errors are yielded as `Err` items and do not end the stream, frames that
+    /// cannot be decoded are handled according to the `DecodePolicy`.
+    ///
+    /// Call `open` first to establish the connections.
+    pub fn events(&mut self) -> BoxStream<'_, Result<WebsocketEvent>> {
+        self.envelopes().map_ok(|envelope| envelope.event).boxed()
+    }
+
+    /// Owned variant of `events`, for moving the client into another task.
+    pub fn into_stream(self) -> BoxStream<'static, Result<WebsocketEvent>> {
+        self.into_envelopes().map_ok(|envelope| envelope.event).boxed()
+    }
+
+    /// `events` with the connection each event came from.
+    pub fn envelopes(&mut self) -> BoxStream<'_, Result<Envelope>> {
+        stream::unfold(self, |ws| async move {
+            let envelope = ws.next_event().await;
+            Some((envelope, ws))
+        }).boxed()
+    }
+
+    /// Owned variant of `envelopes`.
+    pub fn into_envelopes(self) -> BoxStream<'static, Result<Envelope>> {
+        stream::unfold(self, |mut ws| async move {
+            let envelope = ws.next_event().await;
+            Some((envelope, ws))
+        }).boxed()
+    }
+
+    /// Adds an account streaming `subscription` with its own credentials, the same tuple as
+    /// in `new`. Open it with the returned `ConnectionId` as the key of `open` or `connect`,
+    /// its events carry the `account` label.
+    pub fn add_account(&mut self, account: &str, subscription: Subscription, credentials: (String, String, String)) -> ConnectionId {
+        let connection = ConnectionId::new(account, subscription);
+        self.credentials.insert(connection.clone(), credentials);
+        connection
+    }
+
+    /// Records now as the receive time of the events emitted until the next call.
+    pub(crate) fn stamp(&mut self) {
+        self.received = (Instant::now(), Utc::now());
+    }
+
+    pub(crate) fn emit(&mut self, connection: &ConnectionId, event: WebsocketEvent) {
+        let envelope = self.envelope(connection, event);
+        self.pending.push_back(envelope);
+    }
+
+    /// Queues `event` ahead of the events emitted since the queue had `at` events, so that a
+    /// message is delivered before what was derived from it without cloning it.
+    pub(crate) fn emit_at(&mut self, at: usize, connection: &ConnectionId, event: WebsocketEvent) {
+        let envelope = self.envelope(connection, event);
+        self.pending.insert(at, envelope);
+    }
+
+    /// Wraps `event` with the current receive times. The sequence is set when it is
+    /// delivered, as `emit_at` can queue it ahead of others.
+    fn envelope(&self, connection: &ConnectionId, event: WebsocketEvent) -> Envelope {
+        let (received, received_at) = self.received;
+        Envelope {
+            connection: connection.clone(),
+            exchange_time: event.exchange_time(),
+            event,
+            received,
+            received_at,
+            sequence: 0,
+        }
+    }
+
+    /// Takes the next queued event and numbers it.
+    pub(crate) fn deliver(&mut self) -> Option<Envelope> {
+        let mut envelope = self.pending.pop_front()?;
+        let sequence = self.sequences.entry(envelope.connection.clone()).or_default();
+        envelope.sequence = *sequence;
+        *sequence += 1;
+        Some(envelope)
+    }
+
+    /// Replaces the backoff schedule used to re-establish dropped connections.
+    pub const fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect_policy = policy;
+    }
 
-        let end = match subscription {
-            Subscription::BinanceSpotMStream => 
-                format!("/stream?streams={}", topics.join("/")),
-            Subscription::BinanceSpotOrder => 
-                format!("/stream?streams={}", topics.join("/")),
-            Subscription::BinanceUSwapMStream => 
-                format!("/stream?streams={}", topics.join("/")),
-            Subscription::BinanceBLVTStream => 
-                format!("/stream?streams={}", topics.join("/")),
+    /// Chooses what happens to frames that cannot be decoded. By default they are delivered
+    /// as `WebsocketEvent::Unparsed`, `DecodePolicy::Fatal` is meant for tests.
+    pub const fn set_decode_policy(&mut self, policy: DecodePolicy) {
+        self.decode_policy = policy;
+    }
+
+    /// Number of frames that could not be decoded, or events that could not be normalized,
+    /// and were skipped.
+    #[must_use]
+    pub const fn unparsed_count(&self) -> u64 {
+        self.unparsed
+    }
+
+    /// Maintains local order books from the depth channels of `connection`, a `Subscription`
+    /// for the default account, delivering every change of a book and every
+    /// resynchronization to the handler.
+    ///
+    /// * `BinanceSpotMStream`, `BinanceUSwapMStream`: books of the `<symbol>@depth` streams,
+    ///   as `BinanceLocalOrderBook` and `BinanceOrderBookResync`.
+    /// * `OkexMarketStream`: books of the `books`, `books50-l2-tbt` and `books-l2-tbt`
+    ///   channels, as `OkexLocalOrderBook` and `OkexOrderBookResync`.
+    /// * `HuobiUSwapMarketStream`: books of the `depth.size_${size}.high_freq` topics, as
+    ///   `HuobiUSwapLocalOrderBook` and `HuobiUSwapOrderBookResync`.
+    /// * `FtxMarketStream`: books of the `orderbook` channels, as `FtxLocalOrderBook` and
+    ///   `FtxOrderBookResync`.
+    pub fn enable_local_order_book(&mut self, connection: impl Into<ConnectionId>) -> Result<()> {
+        let connection = connection.into();
+        if let Some(market) = DepthMarket::of(&connection.subscription) {
+            self.depth_books.insert(connection, DepthSynchronizer::new(market));
+        } else if connection.subscription == Subscription::OkexMarketStream {
+            self.okex_books.insert(connection, okex_orderbook::OrderBooks::default());
+        } else if connection.subscription == Subscription::HuobiUSwapMarketStream {
+            self.huobi_books.insert(connection, huobi_orderbook::OrderBooks::default());
+        } else if connection.subscription == Subscription::FtxMarketStream {
+            self.ftx_books.insert(connection, ftx_orderbook::OrderBooks::default());
+        } else {
+            return Err(Error::NoLocalOrderBook { subscription: connection.to_string() });
+        }
+        Ok(())
+    }
+
+    /// Connects `connection` to `endpoint` instead of the production host, from the next
+    /// connection on. See `Endpoint` for the presets.
+    pub fn set_endpoint(&mut self, connection: impl Into<ConnectionId>, endpoint: Endpoint) {
+        self.endpoints.insert(connection.into(), endpoint);
+    }
+
+    /// The endpoint `connection` connects to.
+    #[must_use]
+    pub fn endpoint_of(&self, connection: &ConnectionId) -> Endpoint {
+        self.endpoints.get(connection).cloned().unwrap_or_else(|| Endpoint::production(&connection.subscription))
+    }
+
+    /// The URL and handshake request of `connection` with `topics`.
+    pub(crate) fn endpoint(&self, connection: &ConnectionId, topics: &[NativeTopic]) -> Result<(Url, Request)> {
+        let streams = topic::binance_streams(topics);
+        let end = match connection.subscription {
+            Subscription::BinanceSpotMStream
+            | Subscription::BinanceSpotOrder
+            | Subscription::BinanceUSwapMStream
+            | Subscription::BinanceBLVTStream =>
+                format!("/stream?streams={}", streams.join("/")),
             Subscription::BinanceUSwapOrder =>
-                format!("/ws/{}", topics.join("/")),
+                format!("/ws/{}", streams.join("/")),
+            Subscription::BinanceSpotApi =>
+                "/ws-api/v3".to_string(),
+            Subscription::BinanceUSwapApi =>
+                "/ws-fapi/v1".to_string(),
             Subscription::HuobiUSwapMarketStream =>
-                format!("/linear-swap-ws"),
+                "/linear-swap-ws".to_string(),
             Subscription::HuobiUSwapOrderStream =>
-                format!("/linear-swap-notification"),
+                "/linear-swap-notification".to_string(),
             Subscription::OkexMarketStream =>
-                format!("/ws/v5/public"),
+                "/ws/v5/public".to_string(),
             Subscription::OkexOrderStream =>
-                format!("/ws/v5/private"),
-            Subscription::FtxMarketStream =>
-                format!("/ws"),
-            Subscription::FtxOrderStream =>
-                format!("/ws"),
+                "/ws/v5/private".to_string(),
+            Subscription::FtxMarketStream | Subscription::FtxOrderStream =>
+                "/ws".to_string(),
         };
 
-        trace!("[Websocket] Subscribing to '{:?}'", subscription.clone());
+        let endpoint = self.endpoint_of(connection);
+        let url = endpoint.url(&end)?;
+        let request = endpoint.request(&url)?;
+        Ok((url, request))
+    }
+
+    pub async fn subscribe(&mut self, connection: impl Into<ConnectionId>, topics: &[&str]) -> Result<()> {
+        let connection = connection.into();
+        let topics = topic::legacy(&connection.subscription, topics);
+        self.open_socket(connection, &topics).await
+    }
 
-        let endpoint = Url::parse(&format!("{}{}", ws_url, end)).unwrap();
-        
-        let (ws_stream, _) = connect_async(endpoint.clone()).await?;
-        info!("[Websocket] websocket handshake has been successfully completed.{:?}", endpoint.clone());
+    pub(crate) async fn open_socket(&mut self, connection: ConnectionId, topics: &[NativeTopic]) -> Result<()> {
+        trace!("[Websocket] Subscribing to '{:?}'", connection);
 
+        let (endpoint, request) = self.endpoint(&connection, topics)?;
+
+        let (ws_stream, _) = connect_async(request).await
+            .context(error::ConnectSnafu { url: endpoint.as_str() })?;
+        info!("[Websocket] websocket handshake has been successfully completed.{:?}", endpoint);
+
+        self.register(connection, ws_stream);
+
+        Ok(())
+
+    }
+
+    pub(crate) fn register(&mut self, connection: ConnectionId, ws_stream: WSStream) {
         let (sink, stream) = ws_stream.split();
-        
+
         let token = self.streams.insert(stream);
 
-        self.sinks.insert(subscription.clone(), sink);
-        self.subscriptions.insert(subscription.clone(), token);
-        self.tokens.insert(token, subscription.clone());
+        self.sinks.insert(connection.clone(), sink);
+        self.heartbeats.insert(connection.clone(), Heartbeat::new());
+        self.subscriptions.insert(connection.clone(), token);
+        self.tokens.insert(token, connection);
+    }
 
-        Ok(())
+    /// Drops the socket of one connection, leaving every other connection untouched.
+    /// Returns `false` if it was not live.
+    pub(crate) fn drop_connection(&mut self, connection: &ConnectionId) -> bool {
+        self.sinks.remove(connection);
+        self.heartbeats.remove(connection);
+        match self.subscriptions.remove(connection) {
+            Some(token) => {
+                self.tokens.remove(&token);
+                Pin::new(&mut self.streams).remove(token);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Queues a new connection attempt for `connection` after the backoff delay of `attempt`
+    /// and returns that delay. The attempt runs concurrently with the receive loop, see
+    /// `start_reconnect`.
+    pub(crate) fn schedule_reconnect(&self, connection: ConnectionId, attempt: u32) -> Duration {
+        let delay = self.reconnect_policy.delay(attempt);
+        self.reconnect_delays.push(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            (connection, attempt)
+        }));
+        delay
+    }
+
+    /// Opens the socket of a reconnect attempt whose delay has passed. The request is built
+    /// now, from the topics and listen key of the connection at this point, so that changes
+    /// made during the backoff are part of it.
+    pub(crate) fn start_reconnect(&self, connection: ConnectionId, attempt: u32) {
+        let topics = self.topics.get(&connection).cloned().unwrap_or_default();
+        let endpoint = self.endpoint(&connection, &topics);
 
+        self.reconnects.push(Box::pin(async move {
+            let ws_stream = match endpoint {
+                Ok((endpoint, request)) => connect_async(request).await
+                    .map(|(ws_stream, _)| ws_stream)
+                    .context(error::ConnectSnafu { url: endpoint.as_str() }),
+                Err(e) => Err(e),
+            };
+            (connection, attempt, topics, ws_stream)
+        }));
     }
 
-    pub fn unsubscribe(&mut self, subscription: &Subscription) -> Option<StoredStream> {
+    pub fn unsubscribe(&mut self, connection: impl Into<ConnectionId>) -> Option<StoredStream> {
         let streams = Pin::new(&mut self.streams);
         self.subscriptions
-            .get(subscription)
+            .get(&connection.into())
             .and_then(|token| StreamUnordered::take(streams, *token))
     }
 
-    pub fn check_key(&self, subscription: &Subscription) -> Fallible<(&str, &str)> {
-        match self.credentials.get(&subscription).as_ref() {
-            None => Err(Error::NoApiKeySet.into()),
-            Some((k, s,p )) => Ok((k, s)),
+    pub fn check_key(&self, connection: &ConnectionId) -> Result<(&str, &str)> {
+        match self.credentials.get(connection) {
+            None => Err(Error::NoApiKeySet { subscription: connection.to_string() }),
+            Some((k, s, _)) => Ok((k, s)),
         }
     }
 
-    pub fn okex_check_key(&self, subscription: &Subscription) -> Fallible<(&str, &str, &str)> {
-        match self.credentials.get(&subscription).as_ref() {
-            None => Err(Error::NoApiKeySet.into()),
+    pub fn okex_check_key(&self, connection: &ConnectionId) -> Result<(&str, &str, &str)> {
+        match self.credentials.get(connection) {
+            None => Err(Error::NoApiKeySet { subscription: connection.to_string() }),
             Some((k, s, p)) => Ok((k, s, p)),
         }
     }
 
-    pub fn ftx_check_key(&self, subscription: &Subscription) -> Fallible<(&str, &str, &str)> {
-        match self.credentials.get(&subscription).as_ref() {
-            None => Err(Error::NoApiKeySet.into()),
+    pub fn ftx_check_key(&self, connection: &ConnectionId) -> Result<(&str, &str, &str)> {
+        match self.credentials.get(connection) {
+            None => Err(Error::NoApiKeySet { subscription: connection.to_string() }),
             Some((k, s, account)) => Ok((k, s, account)),
         }
     }
 
-    pub fn parse_message(&self, msg: Message, token: usize) -> Fallible<(WebsocketEvent, usize)> {
-        let subscription = self.tokens.get(&token).unwrap();
-        let bin = match msg {
-            // Message::Text(msg) => return Ok(WebsocketEvent::Text(msg)),
-            Message::Text(msg) => {
-                let message: WebsocketEvent = from_str(&msg)?;
-                println!("1.{:?}", message.clone());
-                return Ok((message, token));
-            },
-            Message::Binary(b) => b,
-            Message::Pong(c) => c,
-            Message::Ping(d) => d,
-            Message::Close(..) => return Err(failure::format_err!("Socket closed")),
-            Message::Frame(_) => todo!(),
-            // Message::Frame(..) => return Err(failure::format_err!("Socket Raw"))
+    pub fn parse_message(&self, msg: Message, token: usize) -> Result<(WebsocketEvent, usize)> {
+        let message = match msg {
+            Message::Text(msg) => decode(&msg)?,
+            Message::Binary(b) | Message::Pong(b) | Message::Ping(b) => {
+                let s = gunzip(&b)?;
+                trace!("Incoming websocket message {:?}", s);
+                decode(&s)?
+            }
+            Message::Close(..) | Message::Frame(_) => return Err(Error::NotConnected {
+                subscription: self.tokens.get(&token).map(ToString::to_string).unwrap_or_default(),
+            }),
         };
 
-        let mut d = GzDecoder::new(&*bin);
-        let mut s = String::new();
-        d.read_to_string(&mut s).unwrap();
-
-        trace!("Incoming websocket message {:?}", s);
-        
-        let message: WebsocketEvent = from_str(&s)?;
-
-        // print!("messsgae: {:?}", message );
-
         Ok((message, token))
     }
+}
 
-
+/// Decodes a text message, keeping the payload in the error.
+pub(crate) fn decode<T: DeserializeOwned>(raw: &str) -> Result<T> {
+    serde_json::from_str(raw).context(error::DecodeSnafu { raw })
 }
 
-impl  Stream for Websocket {
-    type Item = Fallible<(Message, usize)>;
-
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match Pin::new(&mut self.as_mut().get_mut().streams).poll_next(cx) {
-            Poll::Ready(Some((y, token))) => match y {
-                StreamYield::Item(item) => {
-                    Poll::Ready({
-                        Some(
-                            match item.map_err(failure::Error::from)
-                            {
-                                Ok(msg) => Ok((msg, token)),
-                                Err(err) => Err(failure::Error::from(err)),
-                            }
-                        )
-                    })
-                }
-                StreamYield::Finished(_) => Poll::Pending,
-            },
-            Poll::Ready(None) => Poll::Ready(Some(Err(Error::NoStreamSubscribed.into()))),
-            Poll::Pending => Poll::Pending,
-        }
-    }
+/// Decompresses a gzip message, as sent by Huobi.
+pub(crate) fn gunzip(bin: &[u8]) -> Result<String> {
+    let mut s = String::new();
+    GzDecoder::new(bin).read_to_string(&mut s).context(error::GzipSnafu)?;
+    Ok(s)
 }
 
@@ -0,0 +1,260 @@
+//! Golden files of the frames each exchange sends.
+//!
+//! Every file under `tests/fixtures/<Subscription>/` holds one frame as received on that
+//! subscription, gunzipped for Huobi, and is named after the variant of the stream enum it
+//! must decode into: `<Variant>.json`, or `<Variant>.<case>.json` for another shape of the
+//! same variant. FTX frames are all `FtxRsp`, they are named after their `type` and the
+//! variant of their `data`, like `Update-Ticker.json`.
+use chrono::Utc;
+use crypto_websockets::{
+    ftx_model::Response,
+    recorder::{Direction, FrameKind, RecordedFrame},
+    replay::Replay,
+    websocket::Websocket,
+    BinanceSpotWebsocketEvent, BinanceUSwapWebsocketEvent, FtxWebsocketEvent, HuobiUSwapWebsocketEvent,
+    OkexWebsocketEvent, Subscription,
+};
+use futures::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{collections::{BTreeSet, HashMap}, fmt::Debug, fs, path::PathBuf};
+
+/// The variants of `WebsocketEvent` decoded from frames, under the same name as in the
+/// stream enums.
+const WEBSOCKET_EVENTS: &[&str] = &[
+    "BinanceSpotAccountUpdate", "BinanceSpotOrderTrade", "BinanceSpotBalanceUpdate", "BinanceSpotAggrTrades",
+    "BinanceSpotTrade", "BinanceSpotOrderBook", "BinanceSpotDayTicker", "BinanceSpotDayTickerAll",
+    "BinanceSpotKline", "BinanceSpotDepthOrderBook", "BinanceSpotBookTicker", "BinanceSpotListenKeyEvent",
+    "BinanceUSwapVec", "BinanceUSwapDayTickerEvent", "BinanceUSwapBookTickerEvent", "BinanceUSwapMiniTickerEvent",
+    "BinanceUSwapVecMiniTickerEvent", "BinanceUSwapAccountUpdateEvent", "BinanceUSwapOrderTradeEvent",
+    "BinanceUSwapListenKeyEvent", "BinanceUSwapMarginCallEvent", "BinanceUSwapAccountConfigEvent",
+    "BinanceUSwapAggrTradesEvent", "BinanceUSwapIndexPriceEvent", "BinanceUSwapMarkPriceEvent",
+    "BinanceUSwapVecMarkPriceEvent", "BinanceUSwapTradeEvent", "BinanceUSwapKlineEvent",
+    "BinanceUSwapContinuousKlineEvent", "BinanceUSwapIndexKlineEvent", "BinanceUSwapLiquidationEvent",
+    "BinanceUSwapOrderBook", "BinanceUSwapDepthOrderBookEvent", "BinanceUSwapEtpNavEvent", "BinanceUSwapEtpKlineEvent",
+    "HuobiUSwapIncrementalOrderBook", "HuobiUSwapOrderBook", "HuobiUSwapBBO", "HuobiUSwapKline",
+    "HuobiUSwapTradeDetail", "HuobiUSwapAccount", "HuobiUSwapOrder", "HuobiUSwapMatchOrder", "HuobiUSwapPosition",
+    "OkexOrderBook", "OkexTrade", "OkexOrder", "OkexAccountPosition", "OkexAccount", "OkexPosition",
+];
+
+/// The variants of the stream enums the client answers itself, without an event. `Text`
+/// only matches a JSON string, which no exchange sends, and has no fixture.
+const CONTROL_EVENTS: &[&str] = &[
+    "HuobiUSwapMarketPing", "HuobiUSwapDepthSnapshot", "HuobiUSwapSubStatus", "HuobiUSwapOpStatus",
+    "OkexSubRsp", "OkexSubEvent", "Pong",
+];
+
+/// The `type`s and `data` variants of FTX responses.
+const FTX_NAMES: &[&str] = &[
+    "Subscribed", "Unsubscribed", "Update", "Error", "Partial", "Pong", "Info",
+    "Ticker", "Trades", "OrderbookData", "Fill", "Order",
+];
+
+/// Variants the untagged enum cannot tell from another one, only the stream name can.
+const ROUTED_BY_STREAM: &[&str] = &[
+    // partial books of USDⓈ-M come in the format of the diff. stream.
+    "BinanceUSwapOrderBook",
+];
+
+struct Fixture {
+    path: PathBuf,
+    subscription: Subscription,
+    /// The variant the frame decodes into, from the file name.
+    variant: String,
+    raw: String,
+}
+
+fn fixtures() -> Vec<Fixture> {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut fixtures = Vec::new();
+    for dir in fs::read_dir(&root).unwrap() {
+        let dir = dir.unwrap().path();
+        let subscription: Subscription = dir.file_name().unwrap().to_str().unwrap().parse().unwrap();
+        for file in fs::read_dir(&dir).unwrap() {
+            let path = file.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let variant = name.split('.').next().unwrap().to_string();
+            let raw = fs::read_to_string(&path).unwrap().trim_end().to_string();
+            fixtures.push(Fixture { path, subscription: subscription.clone(), variant, raw });
+        }
+    }
+    fixtures.sort_by(|a, b| a.path.cmp(&b.path));
+    assert!(!fixtures.is_empty(), "no fixtures in {}", root.display());
+    fixtures
+}
+
+/// The name of the variant `value` holds, as `Debug` prints it.
+fn variant_name(value: &impl Debug) -> String {
+    let debug = format!("{value:?}");
+    debug.split(['(', ' ', '{']).next().unwrap_or_default().to_string()
+}
+
+/// `<type>` or `<type>-<data>`.
+fn ftx_name(rsp: &Response) -> String {
+    let kind = variant_name(&rsp.r#type);
+    rsp.data.as_ref().map_or_else(|| kind.clone(), |data| format!("{kind}-{}", variant_name(data)))
+}
+
+fn is_huobi(subscription: &Subscription) -> bool {
+    matches!(subscription, Subscription::HuobiUSwapMarketStream | Subscription::HuobiUSwapOrderStream)
+}
+
+/// The variant `raw` decodes into with `parse`, as the receive loop decodes it.
+fn parse(subscription: &Subscription, raw: &str) -> crypto_websockets::Result<String> {
+    match subscription {
+        Subscription::BinanceSpotMStream | Subscription::BinanceSpotOrder => {
+            BinanceSpotWebsocketEvent::parse(raw).map(|event| variant_name(&event))
+        }
+        Subscription::BinanceUSwapMStream | Subscription::BinanceBLVTStream | Subscription::BinanceUSwapOrder => {
+            BinanceUSwapWebsocketEvent::parse(raw).map(|event| variant_name(&event))
+        }
+        Subscription::HuobiUSwapMarketStream | Subscription::HuobiUSwapOrderStream => {
+            HuobiUSwapWebsocketEvent::parse(raw).map(|event| variant_name(&event))
+        }
+        Subscription::OkexMarketStream | Subscription::OkexOrderStream => {
+            OkexWebsocketEvent::parse(raw).map(|event| variant_name(&event))
+        }
+        Subscription::FtxMarketStream | Subscription::FtxOrderStream => {
+            FtxWebsocketEvent::parse(raw).map(|FtxWebsocketEvent::FtxRsp(rsp)| ftx_name(&rsp))
+        }
+        _ => panic!("no stream enum for {subscription:?}"),
+    }
+}
+
+/// The variant `raw` decodes into through the untagged enum alone.
+fn untagged(subscription: &Subscription, raw: &str) -> serde_json::Result<String> {
+    fn decode<T: DeserializeOwned + Debug>(raw: &str) -> serde_json::Result<String> {
+        serde_json::from_str::<T>(raw).map(|event| variant_name(&event))
+    }
+    match subscription {
+        Subscription::BinanceSpotMStream | Subscription::BinanceSpotOrder => decode::<BinanceSpotWebsocketEvent>(raw),
+        Subscription::BinanceUSwapMStream | Subscription::BinanceBLVTStream | Subscription::BinanceUSwapOrder => {
+            decode::<BinanceUSwapWebsocketEvent>(raw)
+        }
+        Subscription::HuobiUSwapMarketStream | Subscription::HuobiUSwapOrderStream => decode::<HuobiUSwapWebsocketEvent>(raw),
+        Subscription::OkexMarketStream | Subscription::OkexOrderStream => decode::<OkexWebsocketEvent>(raw),
+        Subscription::FtxMarketStream | Subscription::FtxOrderStream => {
+            serde_json::from_str::<FtxWebsocketEvent>(raw).map(|FtxWebsocketEvent::FtxRsp(rsp)| ftx_name(&rsp))
+        }
+        _ => panic!("no stream enum for {subscription:?}"),
+    }
+}
+
+/// Serializes the event `raw` decodes into and decodes that again, returning both
+/// serializations. `None` for the enums that do not serialize.
+fn round_trip(subscription: &Subscription, raw: &str) -> Option<Result<(String, String), String>> {
+    fn twice<T: Serialize + Debug>(raw: &str, parse: fn(&str) -> crypto_websockets::Result<T>) -> Result<(String, String), String> {
+        let first = serde_json::to_string(&parse(raw).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        let again = parse(&first).map_err(|e| format!("{e} in {first}"))?;
+        let second = serde_json::to_string(&again).map_err(|e| e.to_string())?;
+        Ok((first, second))
+    }
+    Some(match subscription {
+        Subscription::BinanceSpotMStream | Subscription::BinanceSpotOrder => twice(raw, BinanceSpotWebsocketEvent::parse),
+        Subscription::BinanceUSwapMStream | Subscription::BinanceBLVTStream | Subscription::BinanceUSwapOrder => {
+            twice(raw, BinanceUSwapWebsocketEvent::parse)
+        }
+        Subscription::HuobiUSwapMarketStream | Subscription::HuobiUSwapOrderStream => twice(raw, HuobiUSwapWebsocketEvent::parse),
+        _ => return None,
+    })
+}
+
+/// The events replaying `fixture` must deliver.
+fn expected_events(fixture: &Fixture) -> Vec<String> {
+    match fixture.subscription {
+        Subscription::FtxMarketStream | Subscription::FtxOrderStream
+            if fixture.variant.starts_with("Update") || fixture.variant.starts_with("Partial") =>
+        {
+            vec!["FtxRsp".to_string()]
+        }
+        _ if WEBSOCKET_EVENTS.contains(&fixture.variant.as_str()) => vec![fixture.variant.clone()],
+        _ => Vec::new(),
+    }
+}
+
+fn check(failures: &[String]) {
+    assert!(failures.is_empty(), "{} fixtures failed:\n{}", failures.len(), failures.join("\n"));
+}
+
+#[test]
+fn every_fixture_decodes_into_its_variant() {
+    let mut failures = Vec::new();
+    for fixture in fixtures() {
+        match parse(&fixture.subscription, &fixture.raw) {
+            Ok(variant) if variant == fixture.variant => (),
+            Ok(variant) => failures.push(format!("{}: decoded into {variant}", fixture.path.display())),
+            Err(e) => failures.push(format!("{}: {e}", fixture.path.display())),
+        }
+    }
+    check(&failures);
+}
+
+#[test]
+fn untagged_enums_agree_with_the_routing() {
+    let mut failures = Vec::new();
+    for fixture in fixtures() {
+        // the OKX `pong` is not JSON.
+        if ROUTED_BY_STREAM.contains(&fixture.variant.as_str()) || fixture.raw == "pong" {
+            continue;
+        }
+        match untagged(&fixture.subscription, &fixture.raw) {
+            Ok(variant) if variant == fixture.variant => (),
+            Ok(variant) => failures.push(format!("{}: the untagged enum picked {variant}", fixture.path.display())),
+            Err(e) => failures.push(format!("{}: {e}", fixture.path.display())),
+        }
+    }
+    check(&failures);
+}
+
+#[test]
+fn serialized_events_decode_the_same() {
+    let mut failures = Vec::new();
+    for fixture in fixtures() {
+        if ROUTED_BY_STREAM.contains(&fixture.variant.as_str()) {
+            continue;
+        }
+        match round_trip(&fixture.subscription, &fixture.raw) {
+            None => (),
+            Some(Ok((first, second))) if first == second => (),
+            Some(Ok((first, second))) => failures.push(format!("{}: {first} became {second}", fixture.path.display())),
+            Some(Err(e)) => failures.push(format!("{}: {e}", fixture.path.display())),
+        }
+    }
+    check(&failures);
+}
+
+#[tokio::test]
+async fn replayed_fixtures_deliver_their_event() {
+    let mut failures = Vec::new();
+    for fixture in fixtures() {
+        let frame = RecordedFrame {
+            ts: Utc::now(),
+            subscription: fixture.subscription.clone(),
+            account: None,
+            direction: Direction::Inbound,
+            kind: if is_huobi(&fixture.subscription) { FrameKind::Binary } else { FrameKind::Text },
+            payload: fixture.raw.clone(),
+        };
+        let mut ws = Websocket::new_streaming(HashMap::new());
+        let delivered: Vec<String> = ws.replay_envelopes(Replay::from_frames([frame]))
+            .map(|envelope| envelope.map_or_else(|e| format!("error: {e}"), |envelope| variant_name(&envelope.event)))
+            .collect()
+            .await;
+        let expected = expected_events(&fixture);
+        if delivered != expected {
+            failures.push(format!("{}: delivered {delivered:?}, expected {expected:?}", fixture.path.display()));
+        }
+    }
+    check(&failures);
+}
+
+#[test]
+fn every_variant_has_a_fixture() {
+    let fixtures = fixtures();
+    let names: BTreeSet<&str> = fixtures.iter()
+        .flat_map(|fixture| fixture.variant.split('-'))
+        .collect();
+    let missing: Vec<&&str> = WEBSOCKET_EVENTS.iter().chain(CONTROL_EVENTS).chain(FTX_NAMES)
+        .filter(|name| !names.contains(**name))
+        .collect();
+    assert!(missing.is_empty(), "no fixture for {missing:?}");
+}
@@ -0,0 +1,343 @@
+use crypto_websockets::{
+    control::TopicAck,
+    heartbeat::{HeartbeatPolicy, Ping},
+    listen_key::{BinanceRest, HmacSigner},
+    mock::{MockExchange, MockRest, WAIT_TIMEOUT},
+    models::{ConnectionState, Subscription, WebsocketEvent},
+    reconnect::ReconnectPolicy,
+    normalized::{Exchange, NormalizedEvent},
+    topic::Topic,
+    websocket::Websocket,
+};
+use futures::prelude::*;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+
+const BINANCE_USWAP_BOOK_TICKER: &str = r#"{"stream":"btcusdt@bookTicker","data":{"e":"bookTicker","u":2451832211,"s":"BTCUSDT","b":"16530.10","B":"3.125","a":"16530.20","A":"0.881","T":1672531200120,"E":1672531200123}}"#;
+const HUOBI_INCREMENTAL_DEPTH: &str = r#"{"ch":"market.BTC-USDT.depth.size_20.high_freq","tick":{"asks":[[16530.2,881],[16530.3,0],[16530.6,4200]],"bids":[[16530.1,3125],[16530.0,10],[16529.9,1502]],"ch":"market.BTC-USDT.depth.size_20.high_freq","event":"update","id":115338190720,"mrid":115338190720,"ts":1672531200120,"version":1806745017},"ts":1672531200123}"#;
+
+fn credentials(subscription: Subscription) -> HashMap<Subscription, (String, String, String)> {
+    HashMap::from([(subscription, ("key".to_string(), "secret".to_string(), "passphrase".to_string()))])
+}
+
+/// Opens `subscription` on `mock` and runs the client in the background.
+async fn connect(
+    mock: &MockExchange,
+    subscription: Subscription,
+    topics: Vec<Topic>,
+) -> mpsc::UnboundedReceiver<WebsocketEvent> {
+    connect_with(mock, subscription, topics, |_| ()).await
+}
+
+/// `connect` with the client set up by `configure` first.
+async fn connect_with(
+    mock: &MockExchange,
+    subscription: Subscription,
+    topics: Vec<Topic>,
+    configure: impl FnOnce(&mut Websocket),
+) -> mpsc::UnboundedReceiver<WebsocketEvent> {
+    let mut ws = Websocket::new_streaming(credentials(subscription.clone()));
+    configure(&mut ws);
+    ws.set_endpoint(subscription.clone(), mock.endpoint());
+    ws.open_topics(HashMap::from([(subscription, topics)])).await.unwrap();
+
+    let (tx, events) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut stream = ws.into_stream();
+        // errors do not end the stream.
+        while let Some(event) = stream.next().await {
+            if let Ok(event) = event {
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    events
+}
+
+async fn next_matching(events: &mut mpsc::UnboundedReceiver<WebsocketEvent>, matches: impl Fn(&WebsocketEvent) -> bool) -> WebsocketEvent {
+    tokio::time::timeout(WAIT_TIMEOUT, async {
+        loop {
+            let event = events.recv().await.expect("the client stopped");
+            if matches(&event) {
+                return event;
+            }
+        }
+    }).await.expect("no matching event")
+}
+
+fn subscribed(event: &WebsocketEvent) -> bool {
+    matches!(event, WebsocketEvent::Topic(topic) if topic.ack == TopicAck::Subscribed)
+}
+
+#[tokio::test]
+async fn binance_streams_are_in_the_url() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    let mut events = connect(&mock, Subscription::BinanceUSwapMStream, vec![Topic::BookTicker("BTCUSDT".to_string())]).await;
+
+    mock.wait_for_connections(1).await;
+    assert!(mock.paths()[0].contains("btcusdt@bookTicker"), "{:?}", mock.paths());
+
+    mock.push(BINANCE_USWAP_BOOK_TICKER);
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::BinanceUSwapBookTickerEvent(_))).await;
+}
+
+#[tokio::test]
+async fn normalized_events_carry_their_account() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    let (tx, mut events) = mpsc::unbounded_channel();
+    let mut ws = Websocket::new_normalized_enveloped(HashMap::new(), move |connection, event| {
+        let _ = tx.send((connection, event));
+        Ok(())
+    });
+    let alice = ws.add_account("alice", Subscription::BinanceUSwapMStream, ("key".to_string(), "secret".to_string(), String::new()));
+    ws.set_endpoint(alice.clone(), mock.endpoint());
+    let topics = HashMap::from([(alice.clone(), vec![Topic::BookTicker("BTCUSDT".to_string())])]);
+    tokio::spawn(async move { ws.connect_topics(topics).await });
+
+    mock.wait_for_connections(1).await;
+    mock.push(BINANCE_USWAP_BOOK_TICKER);
+    let connection = tokio::time::timeout(WAIT_TIMEOUT, async {
+        loop {
+            let (connection, event) = events.recv().await.expect("the client stopped");
+            if matches!(event, NormalizedEvent::BookTicker(_)) {
+                return connection;
+            }
+        }
+    }).await.expect("no book ticker");
+    assert_eq!(connection, alice);
+}
+
+#[tokio::test]
+async fn huobi_market_subscribes_and_answers_pings() {
+    let mock = MockExchange::start(Exchange::HuobiUSwap).await.unwrap();
+    let mut events = connect(&mock, Subscription::HuobiUSwapMarketStream, vec![Topic::Trades("BTC-USDT".to_string())]).await;
+
+    mock.wait_for(|frame| frame.contains(r#""sub":"market.BTC-USDT.trade.detail""#)).await;
+    next_matching(&mut events, subscribed).await;
+
+    mock.ping();
+    mock.wait_for(|frame| frame.contains("pong")).await;
+
+    mock.push(HUOBI_INCREMENTAL_DEPTH);
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::HuobiUSwapIncrementalOrderBook(_))).await;
+}
+
+#[tokio::test]
+async fn huobi_orders_authenticate_before_subscribing() {
+    let mock = MockExchange::start(Exchange::HuobiUSwap).await.unwrap();
+    let mut events = connect(&mock, Subscription::HuobiUSwapOrderStream, vec![Topic::Orders("BTC-USDT".to_string())]).await;
+
+    let auth = mock.wait_for(|frame| frame.contains(r#""op":"auth""#)).await;
+    assert!(auth.contains(r#""AccessKeyId":"key""#) && auth.contains("Signature"), "{auth}");
+    mock.wait_for(|frame| frame.contains(r#""op":"sub""#)).await;
+    next_matching(&mut events, subscribed).await;
+
+    mock.ping();
+    mock.wait_for(|frame| frame.contains(r#""op":"pong""#)).await;
+}
+
+#[tokio::test]
+async fn okex_logs_in_then_subscribes() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    let mut events = connect(&mock, Subscription::OkexOrderStream, vec![Topic::Orders("SWAP".to_string())]).await;
+
+    let login = mock.wait_for(|frame| frame.contains(r#""op":"login""#)).await;
+    assert!(login.contains(r#""apiKey":"key""#) && login.contains(r#""passphrase":"passphrase""#), "{login}");
+    let subscribe = mock.wait_for(|frame| frame.contains(r#""op":"subscribe""#)).await;
+    assert!(subscribe.contains(r#""channel":"orders""#), "{subscribe}");
+    next_matching(&mut events, subscribed).await;
+
+    let received = mock.received();
+    let position = |op: &str| received.iter().position(|frame| frame.contains(op));
+    assert!(position(r#""op":"login""#) < position(r#""op":"subscribe""#));
+}
+
+#[tokio::test]
+async fn ftx_logs_in_and_subscribes() {
+    let mock = MockExchange::start(Exchange::Ftx).await.unwrap();
+    let mut events = connect(&mock, Subscription::FtxOrderStream, vec![Topic::Orders(String::new())]).await;
+
+    let login = mock.wait_for(|frame| frame.contains(r#""op":"login""#)).await;
+    assert!(login.contains(r#""key":"key""#) && login.contains(r#""sign""#), "{login}");
+    mock.wait_for(|frame| frame.contains(r#""channel":"orders""#)).await;
+    next_matching(&mut events, subscribed).await;
+}
+
+#[tokio::test]
+async fn scripted_rejection_and_reconnect() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    mock.reply_to(r#""channel":"trades""#, &[r#"{"event":"error","code":"60018","msg":"Invalid channel"}"#]);
+    let mut events = connect(&mock, Subscription::OkexMarketStream, vec![Topic::Trades("BTC-USDT".to_string())]).await;
+
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::Topic(topic) if matches!(topic.ack, TopicAck::Rejected(_)))).await;
+
+    mock.disconnect();
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::Connection(connection) if matches!(connection.state, ConnectionState::Reconnected { .. }))).await;
+    assert_eq!(mock.paths().len(), 2);
+}
+
+#[tokio::test]
+async fn topics_added_while_reconnecting_are_in_the_new_url() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    let mut control = None;
+    let mut events = connect_with(&mock, Subscription::BinanceUSwapMStream, vec![Topic::BookTicker("BTCUSDT".to_string())], |ws| {
+        ws.set_reconnect_policy(ReconnectPolicy { initial_delay: Duration::from_secs(1), ..ReconnectPolicy::default() });
+        control = Some(ws.control());
+    }).await;
+    mock.wait_for_connections(1).await;
+
+    mock.disconnect();
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::Connection(connection) if matches!(connection.state, ConnectionState::Reconnecting { .. }))).await;
+    control.unwrap().subscribe(Subscription::BinanceUSwapMStream, &[Topic::Trades("ETHUSDT".to_string())]).unwrap();
+
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::Connection(connection) if matches!(connection.state, ConnectionState::Reconnected { .. }))).await;
+    let paths = mock.paths();
+    assert!(paths[1].contains("btcusdt@bookTicker") && paths[1].contains("ethusdt@trade"), "{paths:?}");
+}
+
+/// OKX pings every second, a connection silent for two seconds is dead.
+fn fast_okex_heartbeat(ws: &mut Websocket) {
+    ws.set_heartbeat_policy(Subscription::OkexMarketStream, HeartbeatPolicy {
+        ping: Some(Ping::Text { ping: "ping".to_string(), pong: "pong".to_string() }),
+        interval: Duration::from_secs(1),
+        idle_timeout: Duration::from_secs(3),
+    });
+}
+
+#[tokio::test]
+async fn okex_is_pinged_and_stays_up() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    let mut events = connect_with(&mock, Subscription::OkexMarketStream, vec![Topic::Trades("BTC-USDT".to_string())], fast_okex_heartbeat).await;
+    next_matching(&mut events, subscribed).await;
+
+    // answered with `pong` by the mock, which keeps the connection alive past the timeout.
+    mock.wait_for(|frame| frame == "ping").await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(mock.paths().len(), 1);
+}
+
+#[tokio::test]
+async fn silent_connection_is_reconnected() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    mock.respond(|frame| (frame == "ping").then(Vec::new));
+    let mut events = connect_with(&mock, Subscription::OkexMarketStream, vec![Topic::Trades("BTC-USDT".to_string())], fast_okex_heartbeat).await;
+
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::Connection(connection) if matches!(connection.state, ConnectionState::Disconnected(_)))).await;
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::Connection(connection) if matches!(connection.state, ConnectionState::Reconnected { .. }))).await;
+    assert_eq!(mock.paths().len(), 2);
+}
+
+#[tokio::test]
+async fn rejected_login_leaves_the_connection_down() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    mock.reply_to(r#""op":"login""#, &[r#"{"event":"login","code":"60009","msg":"Login failed."}"#]);
+    let mut events = connect(&mock, Subscription::OkexOrderStream, vec![Topic::Orders("SWAP".to_string())]).await;
+
+    let rejected = next_matching(&mut events, |event| matches!(event, WebsocketEvent::Connection(connection) if matches!(connection.state, ConnectionState::LoginRejected { .. }))).await;
+    let WebsocketEvent::Connection(connection) = rejected else { unreachable!() };
+    assert_eq!(connection.state, ConnectionState::LoginRejected { code: "60009".to_string(), msg: "Login failed.".to_string() });
+
+    // past the first reconnect delay, nothing reconnected.
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(mock.paths().len(), 1);
+}
+
+const OKEX_BOOK_SNAPSHOT: &str = r#"{"arg":{"channel":"books50-l2-tbt","instId":"BTC-USDT"},"action":"snapshot","data":[{"asks":[["3366.8","9","0","3"],["3368","8","0","4"]],"bids":[["3366.1","7","0","3"],["3366","6","0","4"]],"ts":"1672531200120","checksum":-1881014294}]}"#;
+const OKEX_BOOK_UPDATE_BAD_CHECKSUM: &str = r#"{"arg":{"channel":"books50-l2-tbt","instId":"BTC-USDT"},"action":"update","data":[{"asks":[],"bids":[["3366","0","0","0"]],"ts":"1672531200220","checksum":12345}]}"#;
+
+#[tokio::test]
+async fn okex_checksum_mismatch_resubscribes_the_book() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    let depth = Topic::Depth { symbol: "BTC-USDT".to_string(), levels: Some(50), speed: None };
+    let mut events = connect_with(&mock, Subscription::OkexMarketStream, vec![depth], |ws| {
+        ws.enable_local_order_book(Subscription::OkexMarketStream).unwrap();
+    }).await;
+    next_matching(&mut events, subscribed).await;
+
+    mock.push(OKEX_BOOK_SNAPSHOT);
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::OkexLocalOrderBook(_))).await;
+
+    mock.push(OKEX_BOOK_UPDATE_BAD_CHECKSUM);
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::OkexOrderBookResync(_))).await;
+    mock.wait_for(|frame| frame.contains(r#""op":"unsubscribe""#) && frame.contains("books50-l2-tbt")).await;
+    let subscribes = || mock.received().iter().filter(|frame| frame.contains(r#""op":"subscribe""#)).count();
+    tokio::time::timeout(WAIT_TIMEOUT, async {
+        while subscribes() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("the book was not subscribed again");
+}
+
+const FTX_BOOK_PARTIAL: &str = r#"{"channel":"orderbook","market":"BTC-PERP","type":"partial","data":{"time":1672531200.12,"checksum":1391516373,"bids":[[5000.5,1.0],[5000.0,2.5]],"asks":[[5001.0,0.1],[5002.5,3.0]],"action":"partial"}}"#;
+const FTX_BOOK_CORRUPTED_UPDATE: &str = r#"{"channel":"orderbook","market":"BTC-PERP","type":"update","data":{"time":1672531200.22,"checksum":240322049,"bids":[[5000.0,0.0]],"asks":[],"action":"update"}}"#;
+
+#[tokio::test]
+async fn ftx_checksum_mismatch_resubscribes_the_book() {
+    let mock = MockExchange::start(Exchange::Ftx).await.unwrap();
+    let depth = Topic::Depth { symbol: "BTC-PERP".to_string(), levels: None, speed: None };
+    let mut events = connect_with(&mock, Subscription::FtxMarketStream, vec![depth], |ws| {
+        ws.enable_local_order_book(Subscription::FtxMarketStream).unwrap();
+    }).await;
+    next_matching(&mut events, subscribed).await;
+
+    mock.push(FTX_BOOK_PARTIAL);
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::FtxLocalOrderBook(_))).await;
+
+    mock.push(FTX_BOOK_CORRUPTED_UPDATE);
+    next_matching(&mut events, |event| matches!(event, WebsocketEvent::FtxOrderBookResync(_))).await;
+    mock.wait_for(|frame| frame.contains(r#""op":"unsubscribe""#) && frame.contains(r#""channel":"orderbook""#)).await;
+    let subscribes = || mock.received().iter().filter(|frame| frame.contains(r#""op":"subscribe""#)).count();
+    tokio::time::timeout(WAIT_TIMEOUT, async {
+        while subscribes() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("the book was not subscribed again");
+}
+
+/// Lets the client manage the listen key of the USDⓈ-M order stream over `rest`, with
+/// keepalives every `keepalive` and fast retries.
+fn manage_listen_key(ws: &mut Websocket, rest: &MockRest, keepalive: Duration) {
+    let rest = BinanceRest::new(&rest.url(), &rest.url(), Arc::new(HmacSigner::new("key", "secret")));
+    ws.manage_listen_key(Subscription::BinanceUSwapOrder, rest).unwrap();
+    ws.set_listen_key_keepalive(keepalive);
+    ws.set_reconnect_policy(ReconnectPolicy { initial_delay: Duration::from_millis(50), ..ReconnectPolicy::default() });
+}
+
+#[tokio::test]
+async fn expired_listen_key_is_replaced() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    let rest = MockRest::start().await.unwrap();
+    let _events = connect_with(&mock, Subscription::BinanceUSwapOrder, Vec::new(), |ws| {
+        manage_listen_key(ws, &rest, Duration::from_secs(3600));
+    }).await;
+    mock.wait_for_connections(1).await;
+    assert_eq!(rest.requests(), ["POST /fapi/v1/listenKey"]);
+    assert!(mock.paths()[0].ends_with("/ws/listen-key-1"), "{:?}", mock.paths());
+
+    mock.push(r#"{"e":"listenKeyExpired","E":1672531200123,"listenKey":"listen-key-1"}"#);
+    mock.wait_for_connections(2).await;
+    assert_eq!(rest.requests(), ["POST /fapi/v1/listenKey", "POST /fapi/v1/listenKey"]);
+    assert!(mock.paths()[1].ends_with("/ws/listen-key-2"), "{:?}", mock.paths());
+}
+
+#[tokio::test]
+async fn failed_keepalive_is_retried_and_unknown_key_replaced() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    let rest = MockRest::start().await.unwrap();
+    rest.answer_keepalives(&[(503, "Service Unavailable")]);
+    let _events = connect_with(&mock, Subscription::BinanceUSwapOrder, Vec::new(), |ws| {
+        manage_listen_key(ws, &rest, Duration::from_millis(300));
+    }).await;
+    mock.wait_for_connections(1).await;
+
+    rest.wait_for_requests("PUT /fapi/v1/listenKey?listenKey=listen-key-1", 2).await;
+    assert_eq!(rest.requests().iter().filter(|request| request.starts_with("POST")).count(), 1);
+    assert_eq!(mock.paths().len(), 1);
+
+    rest.answer_keepalives(&[(400, r#"{"code":-1125,"msg":"This listenKey does not exist."}"#)]);
+    rest.wait_for_requests("POST", 2).await;
+    mock.wait_for_connections(2).await;
+    assert!(mock.paths()[1].ends_with("/ws/listen-key-2"), "{:?}", mock.paths());
+}
@@ -0,0 +1,271 @@
+use crypto_websockets::{
+    binance_api::{BinanceApi, NewOrder, OrderRef},
+    binance_model::{OrderSide, OrderStatus, TimeInForce},
+    listen_key::{HmacSigner, RestSigner},
+    mock::{MockExchange, WAIT_TIMEOUT},
+    models::Subscription,
+    normalized::Exchange,
+    okex_model::{InstType, Side, TdMode},
+    okex_trading::{OkexTrading, PlaceOrder},
+    websocket::Websocket,
+    Error,
+};
+use futures::prelude::*;
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// Opens `subscription` on `mock` without topics, the receive loop is started by `run`.
+async fn open(mock: &MockExchange, subscription: Subscription) -> Websocket {
+    let credentials = ("key".to_string(), "secret".to_string(), "passphrase".to_string());
+    let mut ws = Websocket::new_streaming(HashMap::from([(subscription.clone(), credentials)]));
+    ws.set_endpoint(subscription.clone(), mock.endpoint());
+    ws.open_topics(HashMap::from([(subscription, Vec::new())])).await.unwrap();
+    ws
+}
+
+/// Runs the receive loop of `ws`, which delivers the answers.
+fn run(ws: Websocket) {
+    tokio::spawn(async move {
+        let mut events = ws.into_stream();
+        while events.next().await.is_some() {}
+    });
+}
+
+async fn binance_api(mock: &MockExchange) -> BinanceApi {
+    let ws = open(mock, Subscription::BinanceUSwapApi).await;
+    let api = ws.binance_api(Subscription::BinanceUSwapApi, Arc::new(HmacSigner::new("key", "secret"))).unwrap();
+    run(ws);
+    api
+}
+
+fn request(frame: &str) -> Value {
+    serde_json::from_str(frame).unwrap()
+}
+
+/// A USDⓈ-M order answering the request `id`.
+fn binance_order(id: &Value, client_order_id: &str, status: &str) -> String {
+    json!({ "id": id, "status": 200, "result": {
+        "orderId": 325_078_477, "symbol": "BTCUSDT", "status": status, "clientOrderId": client_order_id,
+        "price": "16530.10", "avgPrice": "0.00", "origQty": "0.010", "executedQty": "0", "cumQuote": "0",
+        "timeInForce": "GTC", "type": "LIMIT", "reduceOnly": false, "side": "BUY", "positionSide": "BOTH",
+        "stopPrice": "0", "updateTime": 1_672_531_200_120_u64,
+    }}).to_string()
+}
+
+fn binance_error(id: &Value, code: i64, msg: &str) -> String {
+    json!({ "id": id, "status": 400, "error": { "code": code, "msg": msg } }).to_string()
+}
+
+fn limit_order(client_order_id: &str) -> NewOrder {
+    NewOrder::limit("BTCUSDT", OrderSide::Buy, Decimal::from_str("0.010").unwrap(), Decimal::from_str("16530.10").unwrap(), TimeInForce::GTC)
+        .with_client_order_id(client_order_id)
+}
+
+fn is_method(frame: &str, method: &str) -> bool {
+    frame.contains(&format!(r#""method":"{method}""#))
+}
+
+#[tokio::test]
+async fn binance_order_is_placed_and_canceled() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    mock.respond(|frame| {
+        let request = request(frame);
+        match request["method"].as_str()? {
+            "order.place" => Some(vec![binance_order(&request["id"], "b1", "NEW")]),
+            "order.cancel" => Some(vec![binance_order(&request["id"], "b1", "CANCELED")]),
+            _ => None,
+        }
+    });
+    let api = binance_api(&mock).await;
+
+    let placed = api.place_order(&limit_order("b1")).await.unwrap();
+    assert_eq!((placed.order_id, placed.status), (325_078_477, OrderStatus::New));
+
+    let canceled = api.cancel_order("BTCUSDT", &OrderRef::ClientOrderId("b1".to_string())).await.unwrap();
+    assert_eq!(canceled.status, OrderStatus::Canceled);
+    let cancel = request(&mock.wait_for(|frame| is_method(frame, "order.cancel")).await);
+    assert_eq!(cancel["params"]["origClientOrderId"], "b1");
+}
+
+#[tokio::test]
+async fn binance_refusals_are_request_rejected() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    mock.respond(|frame| {
+        let request = request(frame);
+        match request["method"].as_str()? {
+            "order.place" => Some(vec![binance_error(&request["id"], -2019, "Margin is insufficient.")]),
+            "order.cancel" => Some(vec![binance_error(&request["id"], -2011, "Unknown order sent.")]),
+            _ => None,
+        }
+    });
+    let api = binance_api(&mock).await;
+
+    let placed = api.place_order(&limit_order("b1")).await;
+    assert!(matches!(placed, Err(Error::RequestRejected { ref code, ref msg, .. }) if code == "-2019" && msg == "Margin is insufficient."), "{placed:?}");
+    let canceled = api.cancel_order("BTCUSDT", &OrderRef::OrderId(1)).await;
+    assert!(matches!(canceled, Err(Error::RequestRejected { ref code, .. }) if code == "-2011"), "{canceled:?}");
+}
+
+#[tokio::test]
+async fn binance_requests_are_signed_over_sorted_parameters() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    mock.respond(|frame| {
+        let request = request(frame);
+        Some(vec![binance_order(&request["id"], "b1", "NEW")])
+    });
+    let api = binance_api(&mock).await.with_recv_window(3000);
+    api.place_order(&limit_order("b1")).await.unwrap();
+
+    let request = request(&mock.wait_for(|frame| is_method(frame, "order.place")).await);
+    let params = request["params"].as_object().unwrap();
+    let keys: Vec<&str> = params.keys().map(String::as_str).collect();
+    assert_eq!(keys, [
+        "apiKey", "newClientOrderId", "newOrderRespType", "price", "quantity", "recvWindow", "side", "signature",
+        "symbol", "timeInForce", "timestamp", "type",
+    ]);
+    assert_eq!(params["apiKey"], "key");
+
+    let payload = params.iter()
+        .filter(|(key, _)| *key != "signature")
+        .map(|(key, value)| format!("{key}={}", value.as_str().unwrap()))
+        .collect::<Vec<_>>()
+        .join("&");
+    assert!(payload.starts_with("apiKey=key&newClientOrderId=b1&newOrderRespType=RESULT&price=16530.10&quantity=0.010&recvWindow=3000&side=BUY"), "{payload}");
+    assert_eq!(params["signature"], HmacSigner::new("key", "secret").sign(&payload));
+}
+
+#[tokio::test]
+async fn binance_answers_go_to_the_request_with_their_id() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    // holds the first order back and answers both, last first, with the second.
+    let first = Mutex::new(None);
+    mock.respond(move |frame| {
+        let request = request(frame);
+        let client_order_id = request["params"]["newClientOrderId"].as_str()?.to_string();
+        let answer = binance_order(&request["id"], &client_order_id, "NEW");
+        let mut first = first.lock().unwrap();
+        match first.take() {
+            None => {
+                *first = Some(answer);
+                Some(Vec::new())
+            }
+            Some(held) => Some(vec![answer, held]),
+        }
+    });
+    let api = binance_api(&mock).await;
+
+    let first = api.place_order(&limit_order("first"));
+    mock.wait_for(|frame| frame.contains(r#""newClientOrderId":"first""#)).await;
+    let second = api.place_order(&limit_order("second"));
+    let (first, second) = tokio::time::timeout(WAIT_TIMEOUT, future::join(first, second)).await.unwrap();
+    assert_eq!(first.unwrap().client_order_id, "first");
+    assert_eq!(second.unwrap().client_order_id, "second");
+}
+
+#[tokio::test]
+async fn binance_requests_in_flight_fail_on_disconnect() {
+    let mock = MockExchange::start(Exchange::BinanceUSwap).await.unwrap();
+    mock.respond(|frame| is_method(frame, "order.place").then(Vec::new));
+    let api = binance_api(&mock).await;
+
+    let placed = api.place_order(&limit_order("b1"));
+    let placed = tokio::spawn(placed);
+    mock.wait_for(|frame| is_method(frame, "order.place")).await;
+    mock.disconnect();
+
+    let placed = tokio::time::timeout(WAIT_TIMEOUT, placed).await.unwrap().unwrap();
+    assert!(matches!(placed, Err(Error::NotConnected { .. })), "{placed:?}");
+}
+
+async fn okex_trading(mock: &MockExchange) -> OkexTrading {
+    let ws = open(mock, Subscription::OkexOrderStream).await;
+    let trading = ws.okex_trading(Subscription::OkexOrderStream).unwrap();
+    run(ws);
+    trading
+}
+
+fn okex_answer(request: &Value, code: &str, msg: &str, data: &Value) -> String {
+    json!({ "id": request["id"], "op": request["op"], "code": code, "msg": msg, "data": data }).to_string()
+}
+
+fn okex_order(cl_ord_id: &str, s_code: &str, s_msg: &str) -> Value {
+    json!({ "clOrdId": cl_ord_id, "ordId": if s_code == "0" { "312269865356374016" } else { "" }, "tag": "", "ts": "1672531200120", "sCode": s_code, "sMsg": s_msg })
+}
+
+fn okex_limit(cl_ord_id: &str) -> PlaceOrder {
+    PlaceOrder::limit("BTC-USDT-SWAP", TdMode::Cross, Side::Buy, Decimal::from(25), Decimal::from_str("16530.1").unwrap())
+        .with_cl_ord_id(cl_ord_id)
+}
+
+fn is_op(frame: &str, op: &str) -> bool {
+    frame.contains(&format!(r#""op":"{op}""#))
+}
+
+#[tokio::test]
+async fn okex_order_is_placed() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    mock.respond(|frame| {
+        let request = request(frame);
+        (request["op"] == "order").then(|| vec![okex_answer(&request, "0", "", &json!([okex_order("o1", "0", "")]))])
+    });
+    let trading = okex_trading(&mock).await;
+
+    let ack = trading.place_order(&okex_limit("o1")).await.unwrap();
+    assert_eq!(ack.ord_id, "312269865356374016");
+    assert_eq!(ack.cl_ord_id.as_deref(), Some("o1"));
+    assert_eq!(ack.tag, None);
+    assert_eq!(ack.ts.map(|ts| ts.timestamp_millis()), Some(1_672_531_200_120));
+
+    let request = request(&mock.wait_for(|frame| is_op(frame, "order")).await);
+    assert_eq!(request["args"], json!([{
+        "instId": "BTC-USDT-SWAP", "tdMode": "cross", "side": "buy", "ordType": "limit", "sz": "25", "px": "16530.1", "clOrdId": "o1",
+    }]));
+}
+
+#[tokio::test]
+async fn okex_batch_reports_each_order() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    mock.respond(|frame| {
+        let request = request(frame);
+        let data = json!([okex_order("o1", "0", ""), okex_order("o2", "51008", "Order failed. Insufficient balance.")]);
+        (request["op"] == "batch-orders").then(|| vec![okex_answer(&request, "2", "Bulk operation partially succeeded.", &data)])
+    });
+    let trading = okex_trading(&mock).await;
+
+    let acks = trading.place_orders(&[okex_limit("o1"), okex_limit("o2")]).await.unwrap();
+    assert_eq!(acks.len(), 2);
+    assert_eq!(acks[0].as_ref().unwrap().cl_ord_id.as_deref(), Some("o1"));
+    assert!(matches!(&acks[1], Err(Error::RequestRejected { code, msg, .. }) if code == "51008" && msg == "Order failed. Insufficient balance."), "{:?}", acks[1]);
+}
+
+#[tokio::test]
+async fn okex_mass_cancel() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    mock.respond(|frame| {
+        let request = request(frame);
+        (request["op"] == "mass-cancel").then(|| vec![okex_answer(&request, "0", "", &json!([{ "result": true }]))])
+    });
+    let trading = okex_trading(&mock).await;
+
+    trading.mass_cancel(InstType::Option, "BTC-USD").await.unwrap();
+    let request = request(&mock.wait_for(|frame| is_op(frame, "mass-cancel")).await);
+    assert_eq!(request["args"], json!([{ "instType": "OPTION", "instFamily": "BTC-USD" }]));
+}
+
+#[tokio::test]
+async fn okex_refused_requests_are_request_rejected() {
+    let mock = MockExchange::start(Exchange::Okex).await.unwrap();
+    mock.respond(|frame| {
+        let request = request(frame);
+        (request["op"] == "order").then(|| vec![okex_answer(&request, "60013", "Invalid args", &json!([]))])
+    });
+    let trading = okex_trading(&mock).await;
+
+    let placed = trading.place_order(&okex_limit("o1")).await;
+    assert!(matches!(placed, Err(Error::RequestRejected { ref code, ref msg, .. }) if code == "60013" && msg == "Invalid args"), "{placed:?}");
+}